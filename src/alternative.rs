@@ -0,0 +1,300 @@
+//! Alternative instructions, modelled on `ALTERNATIVE()` of Linux kernel.
+//!
+//! An [`alternative`] block is assembled with its original instructions in place, and its
+//! replacement instructions in a separate section. Each block is recorded in the
+//! `__static_alternatives` section along with a predicate. In [`global_init`][crate::global_init],
+//! the replacement of every block whose predicate holds is copied over the original instructions,
+//! and the remaining bytes are filled with NOP instructions.
+
+use crate::{
+    StaticKeyError, arch, code_manipulate::CodeManipulator, os, patch_lock::PatchGuard,
+    transaction::MAX_BATCH_PATCHES,
+};
+
+/// Entries in the __static_alternatives section.
+///
+/// The address fields are relative addresses between target address and the address of field that
+/// record it, and they are updated to absolute addresses in [`global_init`][crate::global_init].
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct AlternativeEntry {
+    /// Address of the original instructions
+    code: usize,
+    /// Address of the replacement instructions
+    replacement: usize,
+    /// Address of the predicate, which is `fn() -> bool`
+    predicate: usize,
+    /// Length of the original instructions
+    code_len: u32,
+    /// Length of the replacement instructions
+    replacement_len: u32,
+}
+
+impl AlternativeEntry {
+    /// Update fields to be absolute address
+    #[cfg(not(all(target_os = "windows", target_arch = "x86_64")))]
+    fn make_relative_address_absolute(&mut self) {
+        self.code = ((&raw const self.code) as usize).wrapping_add(self.code);
+        self.replacement = ((&raw const self.replacement) as usize).wrapping_add(self.replacement);
+        self.predicate = ((&raw const self.predicate) as usize).wrapping_add(self.predicate);
+    }
+
+    // For Win64, the relative address is truncated into 32bit. See `JumpEntry`.
+    /// Update fields to be absolute address
+    #[cfg(all(target_os = "windows", target_arch = "x86_64"))]
+    fn make_relative_address_absolute(&mut self) {
+        let code = (self.code as i32) as i64 as usize;
+        self.code = ((&raw const self.code) as usize).wrapping_add(code);
+        let replacement = (self.replacement as i32) as i64 as usize;
+        self.replacement = ((&raw const self.replacement) as usize).wrapping_add(replacement);
+        let predicate = (self.predicate as i32) as i64 as usize;
+        self.predicate = ((&raw const self.predicate) as usize).wrapping_add(predicate);
+    }
+
+    /// Whether this entry is dummy
+    fn is_dummy(&self) -> bool {
+        self.code == 0
+    }
+
+    /// Whether the predicate holds
+    fn predicate_holds(&self) -> bool {
+        let predicate: fn() -> bool = unsafe { core::mem::transmute(self.predicate) };
+        predicate()
+    }
+
+    /// Replacement instructions
+    fn replacement(&self) -> &'static [u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self.replacement as *const u8,
+                self.replacement_len as usize,
+            )
+        }
+    }
+
+    /// Create a dummy entry
+    #[cfg_attr(not(target_os = "windows"), allow(unused))]
+    pub(crate) const fn dummy() -> Self {
+        Self {
+            code: 0,
+            replacement: 0,
+            predicate: 0,
+            code_len: 0,
+            replacement_len: 0,
+        }
+    }
+}
+
+/// Predicate of the dummy alternative, which never holds
+fn dummy_alternative_predicate() -> bool {
+    false
+}
+
+/// Bytes to be written by [`CodeManipulator::write_code_batch`], which are sorted by address.
+struct PatchBuffer {
+    /// Pending patches
+    patches: [(*mut core::ffi::c_void, [u8; 1]); MAX_BATCH_PATCHES],
+    /// Count of pending patches
+    patch_count: usize,
+}
+
+impl PatchBuffer {
+    /// Add a byte to be written at `addr`
+    fn push(&mut self, addr: usize, byte: u8) {
+        self.patches[self.patch_count] = (addr as *mut _, [byte]);
+        self.patch_count += 1;
+        if self.patch_count == MAX_BATCH_PATCHES {
+            self.flush();
+        }
+    }
+
+    /// Write all pending patches
+    ///
+    /// # Panics
+    ///
+    /// Panics if the instructions cannot be modified.
+    fn flush(&mut self) {
+        if self.patch_count == 0 {
+            return;
+        }
+        let written = &self.patches[..self.patch_count];
+        if let Err(err) = unsafe { os::ArchCodeManipulator::write_code_batch(written) } {
+            let err = StaticKeyError::write_code(written[0].0 as usize, err);
+            panic!("Failed to apply alternative instructions: {err}");
+        }
+        self.patch_count = 0;
+    }
+}
+
+/// Whether `addr` lies in the original instructions of any alternative. Must be called after
+/// [`global_init`][crate::global_init].
+pub(crate) fn is_patch_site(addr: usize) -> bool {
+    let entry_start_addr = &raw const os::ALTERNATIVE_ENTRY_START;
+    let entry_stop_addr = &raw const os::ALTERNATIVE_ENTRY_STOP;
+    let entries = unsafe {
+        let len = entry_stop_addr.offset_from(entry_start_addr) as usize;
+        core::slice::from_raw_parts(entry_start_addr, len)
+    };
+    entries.iter().any(|entry| {
+        !entry.is_dummy() && (entry.code..entry.code + entry.code_len as usize).contains(&addr)
+    })
+}
+
+/// Update entries in __static_alternatives section to be absolute address, and copy the
+/// replacement of every entry whose predicate holds. Called in [`global_init`][crate::global_init].
+///
+/// # Panics
+///
+/// Panics if any replacement is longer than its original instructions, or the instructions cannot
+/// be modified.
+pub(crate) fn global_init() {
+    // Make sure there are at least one entry
+    #[cfg(not(all(
+        target_os = "windows",
+        any(target_arch = "x86", target_arch = "x86_64")
+    )))]
+    unsafe {
+        crate::alternative!("", "", dummy_alternative_predicate)
+    };
+    // This is an ugly workaround for https://github.com/rust-lang/rust/issues/128177
+    #[cfg(all(
+        target_os = "windows",
+        any(target_arch = "x86", target_arch = "x86_64")
+    ))]
+    unsafe {
+        crate::alternative!("", "", dummy_alternative_predicate, options(att_syntax))
+    };
+
+    let entry_start_addr = &raw mut os::ALTERNATIVE_ENTRY_START;
+    let entry_stop_addr = &raw mut os::ALTERNATIVE_ENTRY_STOP;
+    let entries = unsafe {
+        let len = entry_stop_addr.offset_from(entry_start_addr) as usize;
+        core::slice::from_raw_parts_mut(entry_start_addr, len)
+    };
+    for entry in entries.iter_mut() {
+        if entry.is_dummy() {
+            continue;
+        }
+        entry.make_relative_address_absolute();
+        if entry.replacement_len > entry.code_len {
+            panic!(
+                "Replacement of alternative instructions at {:#x} is {} bytes, which is longer than {} bytes of the original instructions.",
+                entry.code, entry.replacement_len, entry.code_len
+            );
+        }
+    }
+    // The patches are written in the order of address
+    entries.sort_unstable_by_key(|entry| entry.code);
+
+    // Serialize with modifications by other threads, including other versions of this crate
+    let _guard = PatchGuard::lock();
+    let mut buffer = PatchBuffer {
+        patches: [(core::ptr::null_mut(), [0]); MAX_BATCH_PATCHES],
+        patch_count: 0,
+    };
+    // The NOP instructions are filled in chunks, whose length is a multiple of all instruction
+    // lengths
+    let mut nops = [0; 64];
+    for entry in entries.iter() {
+        if entry.is_dummy() || entry.code_len == 0 || !entry.predicate_holds() {
+            continue;
+        }
+        let replacement = entry.replacement();
+        for (offset, byte) in replacement.iter().enumerate() {
+            buffer.push(entry.code + offset, *byte);
+        }
+        let mut offset = replacement.len();
+        while offset < entry.code_len as usize {
+            let len = (entry.code_len as usize - offset).min(nops.len());
+            arch::arch_fill_nops(&mut nops[..len]);
+            for byte in &nops[..len] {
+                buffer.push(entry.code + offset, *byte);
+                offset += 1;
+            }
+        }
+    }
+    buffer.flush();
+}
+
+/// Inline assembly whose instructions are replaced in [`global_init`][crate::global_init] if
+/// `predicate` holds.
+///
+/// The arguments are the original template, the replacement template, the path of a predicate
+/// function `fn() -> bool`, and the operands and options, which are the same as
+/// [`asm!`][core::arch::asm]. The operands are shared by both templates. Like `asm!`, it must be
+/// used in an `unsafe` block.
+///
+/// The predicate is called once in [`global_init`][crate::global_init]. If it holds, the
+/// replacement is copied over the original instructions, and the remaining bytes are filled with
+/// NOP instructions. The replacement must be the same length as or shorter than the original
+/// instructions, otherwise [`global_init`][crate::global_init] panics.
+///
+/// The replacement is assembled in a separate section, so it must not contain relative
+/// addresses out of itself, such as jumps and calls to other functions, or RIP-relative memory
+/// accesses on x86_64. The numeric labels `2` to `5` are used by this macro, so they must not be
+/// defined in the templates. On Windows x86 and x86_64, the templates must be in AT&T syntax with
+/// `options(att_syntax)`, since the section names are mangled in Intel syntax due to
+/// <https://github.com/rust-lang/rust/issues/128177>.
+///
+/// # Usage
+///
+/// ```rust
+/// # #[cfg(target_arch = "x86_64")]
+/// # mod imp {
+/// use static_keys::alternative;
+///
+/// fn has_bmi1() -> bool {
+///     std::arch::is_x86_feature_detected!("bmi1")
+/// }
+///
+/// /// Count of trailing zeros of non-zero `input`, using `tzcnt` if possible.
+/// pub fn trailing_zeros(input: u64) -> u64 {
+///     let output: u64;
+///     unsafe {
+///         // `tzcnt` is 1 byte longer than `bsf`, so a `nop` is appended to `bsf`
+///         alternative!(
+///             "bsf {1}, {0}\nnop",
+///             "tzcnt {1}, {0}",
+///             has_bmi1,
+///             out(reg) output,
+///             in(reg) input,
+///             options(pure, nomem, nostack, att_syntax),
+///         );
+///     }
+///     output
+/// }
+/// # }
+///
+/// fn main() {
+///     static_keys::global_init();
+///     # #[cfg(target_arch = "x86_64")]
+///     assert_eq!(imp::trailing_zeros(0b1000), 3);
+/// }
+/// ```
+#[macro_export]
+macro_rules! alternative {
+    (@strip $args: tt [$($operands: tt)*] ,) => {
+        $crate::alternative!(@asm $args [$($operands)* ,])
+    };
+    (@strip $args: tt [$($operands: tt)*]) => {
+        $crate::alternative!(@asm $args [$($operands)* ,])
+    };
+    (@strip $args: tt [$($operands: tt)*] $next: tt $($rest: tt)*) => {
+        $crate::alternative!(@strip $args [$($operands)* $next] $($rest)*)
+    };
+    (@asm [$original: expr, $replacement: expr, $predicate: path] [$($operands: tt)*]) => {{
+        // Make sure the predicate has the right signature
+        const _: fn() -> bool = $predicate;
+        ::core::arch::asm!(
+            $crate::arch_alternative_asm_template!($original, $replacement),
+            $($operands)*
+            alternative_predicate = sym $predicate,
+        )
+    }};
+    ($original: expr, $replacement: expr, $predicate: path $(,)?) => {
+        $crate::alternative!(@asm [$original, $replacement, $predicate] [])
+    };
+    ($original: expr, $replacement: expr, $predicate: path, $($operands: tt)+) => {
+        $crate::alternative!(@strip [$original, $replacement, $predicate] [] $($operands)+)
+    };
+}
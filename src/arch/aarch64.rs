@@ -5,6 +5,11 @@ use crate::{JumpEntry, JumpLabelType};
 /// Length of jump instruction to be replaced
 pub const ARCH_JUMP_INS_LENGTH: usize = 4;
 
+/// `b` instruction without offset
+const AARCH64_INSN_B: u32 = 0x14000000;
+/// `br` instruction without register
+const AARCH64_INSN_BR: u32 = 0xd61f0000;
+
 /// New instruction generated according to jump label type and jump entry
 #[inline(always)]
 pub fn arch_jump_entry_instruction(
@@ -12,17 +17,102 @@ pub fn arch_jump_entry_instruction(
     jump_entry: &JumpEntry,
 ) -> [u8; ARCH_JUMP_INS_LENGTH] {
     match jump_label_type {
+        JumpLabelType::Jmp if jump_entry.is_far() => {
+            // The destination is loaded by the preceding `add Xd, Xd, :lo12:target`
+            let add = u32::from_ne_bytes(unsafe {
+                core::ptr::read_volatile((jump_entry.code_addr() - 4) as *const [u8; 4])
+            });
+            // br Xd
+            (AARCH64_INSN_BR | ((add & 0x1f) << 5)).to_ne_bytes()
+        }
         JumpLabelType::Jmp => {
-            // Note that aarch64 only supports relative address within +/-128MB.
-            // In current implementation, this assumption is always hold.
-            let relative_addr = (jump_entry.target_addr() - jump_entry.code_addr()) as u32;
-            let [a, b, c, d] = (relative_addr / 4).to_ne_bytes();
-            [a, b, c, d | 0b00010100]
+            // The range is checked in `global_init`
+            let relative_addr = jump_entry
+                .target_addr()
+                .wrapping_sub(jump_entry.code_addr()) as u32;
+            (AARCH64_INSN_B | ((relative_addr >> 2) & 0x03ffffff)).to_ne_bytes()
         }
         JumpLabelType::Nop => [0x1f, 0x20, 0x03, 0xd5],
     }
 }
 
+/// Whether the JMP destination of given jump entry can be encoded in the `b` instruction, which
+/// supports 4-byte aligned relative address within +/-128MB. For far instructions, the `adrp`
+/// instruction supports relative page address within +/-4GB.
+#[inline(always)]
+pub fn arch_jump_target_in_range(jump_entry: &JumpEntry) -> bool {
+    if jump_entry.is_far() {
+        // `adrp` is 8 bytes before
+        let relative_page = ((jump_entry.target_addr() >> 12)
+            .wrapping_sub((jump_entry.code_addr() - 8) >> 12)) as isize;
+        return (-(1 << 20)..(1 << 20)).contains(&relative_page);
+    }
+    let relative_addr = jump_entry
+        .target_addr()
+        .wrapping_sub(jump_entry.code_addr()) as isize;
+    relative_addr % 4 == 0 && (-(1 << 27)..(1 << 27)).contains(&relative_addr)
+}
+
+/// Length of the JMP instruction in static call trampolines
+pub const ARCH_STATIC_CALL_INS_LENGTH: usize = ARCH_JUMP_INS_LENGTH;
+
+/// New instruction of the static call trampoline recorded by given entry, which jumps to the
+/// target of the entry
+#[inline(always)]
+pub fn arch_static_call_instruction(entry: &JumpEntry) -> [u8; ARCH_STATIC_CALL_INS_LENGTH] {
+    arch_jump_entry_instruction(JumpLabelType::Jmp, entry)
+}
+
+/// Whether the target of given static call entry can be encoded in the JMP instruction of its
+/// trampoline
+#[inline(always)]
+pub fn arch_static_call_target_in_range(entry: &JumpEntry) -> bool {
+    arch_jump_target_in_range(entry)
+}
+
+/// Length of the `movz` and `movk` instructions of runtime constant sites
+pub const ARCH_RUNTIME_CONST_INS_LENGTH: usize = 16;
+
+/// Immediates of the instructions loading `value` at runtime constant sites, which are the 16-bit
+/// chunks from the lowest to the highest
+#[doc(hidden)]
+#[inline(always)]
+pub const fn arch_runtime_const_immediates(value: u64) -> [i64; 4] {
+    [
+        (value & 0xffff) as i64,
+        ((value >> 16) & 0xffff) as i64,
+        ((value >> 32) & 0xffff) as i64,
+        ((value >> 48) & 0xffff) as i64,
+    ]
+}
+
+/// New instructions of the runtime constant site, which are `instruction` with their immediates
+/// replaced by `value`
+#[inline(always)]
+pub fn arch_runtime_const_instruction(
+    instruction: &[u8; ARCH_RUNTIME_CONST_INS_LENGTH],
+    value: u64,
+) -> [u8; ARCH_RUNTIME_CONST_INS_LENGTH] {
+    let immediates = arch_runtime_const_immediates(value);
+    let mut new_instruction = [0; ARCH_RUNTIME_CONST_INS_LENGTH];
+    for (index, immediate) in immediates.into_iter().enumerate() {
+        let range = index * 4..index * 4 + 4;
+        let insn = u32::from_ne_bytes(instruction[range.clone()].try_into().unwrap());
+        // [sf opc] [100101] [hw] [imm16] [rd]
+        let insn = (insn & !(0xffff << 5)) | ((immediate as u32) << 5);
+        new_instruction[range].copy_from_slice(&insn.to_ne_bytes());
+    }
+    new_instruction
+}
+
+/// Fill `code` with NOP instructions. The length of `code` is always a multiple of 4.
+#[inline(always)]
+pub fn arch_fill_nops(code: &mut [u8]) {
+    for chunk in code.chunks_mut(4) {
+        chunk.copy_from_slice(&[0x1f, 0x20, 0x03, 0xd5]);
+    }
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! arch_static_key_init_nop_asm_template {
@@ -65,3 +155,210 @@ macro_rules! arch_static_key_init_jmp_asm_template {
         )
     };
 }
+
+// The address of destination is loaded by `adrp` and `add`, and the `br` is placed right after them.
+#[cfg(not(target_vendor = "apple"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_key_far_addr_asm_template {
+    () => {
+        r#"
+            adrp {3}, {0}
+            add {3}, {3}, :lo12:{0}
+        "#
+    };
+}
+
+// Mach-O uses different relocation specifiers
+#[cfg(target_vendor = "apple")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_key_far_addr_asm_template {
+    () => {
+        r#"
+            adrp {3}, {0}@PAGE
+            add {3}, {3}, {0}@PAGEOFF
+        "#
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_key_init_far_nop_asm_template {
+    () => {
+        ::core::concat!(
+            $crate::arch_static_key_far_addr_asm_template!(),
+            r#"
+            2:
+                nop
+            .pushsection "#,
+            $crate::os_static_key_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} + {2} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_key_init_far_jmp_asm_template {
+    () => {
+        ::core::concat!(
+            $crate::arch_static_key_far_addr_asm_template!(),
+            r#"
+            2:
+                br {3}
+            .pushsection "#,
+            $crate::os_static_key_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} + {2} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_call_trampoline_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            .balign 8
+            2:
+                b {0}
+            .pushsection "#,
+            $crate::os_static_call_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_switch_asm_template {
+    ($($value: literal),+) => {
+        ::core::concat!(
+            r#"
+            2:
+            "#,
+            $(
+                ".if {initial} == ",
+                ::core::stringify!($value),
+                "\n",
+                "b {",
+                ::core::stringify!($value),
+                "}",
+                "\n.endif\n",
+            )+
+            r#"
+            .pushsection "#,
+            $crate::os_static_switch_table_sec_name_attr!(),
+            r#"
+            .balign 4
+            3:
+            .long {count}
+            "#,
+            $(".long {", ::core::stringify!($value), "} - .\n",)+
+            r#"
+            .popsection
+            .pushsection "#,
+            $crate::os_static_switch_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 3b - .
+            .quad {key} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_runtime_const_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            2:
+                movz {0}, #{imm0}
+                movk {0}, #{imm1}, lsl #16
+                movk {0}, #{imm2}, lsl #32
+                movk {0}, #{imm3}, lsl #48
+            3:
+            .pushsection "#,
+            $crate::os_runtime_const_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 3b - .
+            .quad {key} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_runtime_const {
+    ($key: path) => {{
+        let value: u64;
+        unsafe {
+            ::core::arch::asm!(
+                $crate::arch_runtime_const_asm_template!(),
+                out(reg) value,
+                key = sym $key,
+                imm0 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[0],
+                imm1 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[1],
+                imm2 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[2],
+                imm3 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[3],
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        value
+    }};
+}
+
+// The replacement is assembled in a separate section, and copied over the original instructions
+// in `global_init` if the predicate holds.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_alternative_asm_template {
+    ($original: expr, $replacement: expr) => {
+        ::core::concat!(
+            "2:\n",
+            $original,
+            "\n3:\n.pushsection ",
+            $crate::os_alternative_replacement_sec_name_attr!(),
+            "\n4:\n",
+            $replacement,
+            "\n5:\n.popsection\n.pushsection ",
+            $crate::os_alternative_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 4b - .
+            .quad {alternative_predicate} - .
+            .long 3b - 2b
+            .long 5b - 4b
+            .popsection
+            "#
+        )
+    };
+}
@@ -7,6 +7,8 @@ pub const ARCH_JUMP_INS_LENGTH: usize = 4;
 
 const LOONGARCH64_INSN_NOP: u32 = 0x03400000;
 const LOONGARCH64_INSN_B: u32 = 0x50000000;
+/// `jirl $zero, rj, 0` without `rj`
+const LOONGARCH64_INSN_JR: u32 = 0x4c000000;
 /// New instruction generated according to jump label type and jump entry
 #[inline(always)]
 pub fn arch_jump_entry_instruction(
@@ -14,12 +16,22 @@ pub fn arch_jump_entry_instruction(
     jump_entry: &JumpEntry,
 ) -> [u8; ARCH_JUMP_INS_LENGTH] {
     match jump_label_type {
+        JumpLabelType::Jmp if jump_entry.is_far() => {
+            // The destination is loaded by the preceding `addi.d rd, rd, %pc_lo12(...)`
+            let addi = u32::from_ne_bytes(unsafe {
+                core::ptr::read_volatile((jump_entry.code_addr() - 4) as *const [u8; 4])
+            });
+            // 010011 [IMM] [rj] [rd]
+            (LOONGARCH64_INSN_JR | ((addi & 0x1f) << 5)).to_ne_bytes()
+        }
         // 010100 [IMM]
         // opcode I26[15:0] I26[25:16]
         JumpLabelType::Jmp => {
-            // Note that loongarch64 only supports relative address within +/-128MB.
-            // In current implementation, this assumption is always hold.
-            let relative_addr = (jump_entry.target_addr() - jump_entry.code_addr()) as u32;
+            // Note that loongarch64 only supports relative address within +/-128MB, which is
+            // checked in `global_init`.
+            let relative_addr = jump_entry
+                .target_addr()
+                .wrapping_sub(jump_entry.code_addr()) as u32;
             // MASK 25:16 = 0b_0000_0011_1111_1111_0000_0000_0000_0000 = 0x03FF0000
             // MASK 15:0  = 0b_0000_0000_0000_0000_1111_1111_1111_1111 = 0x0000FFFF
             let mut b = LOONGARCH64_INSN_B;
@@ -31,6 +43,89 @@ pub fn arch_jump_entry_instruction(
     }
 }
 
+/// Whether the JMP destination of given jump entry can be encoded in the `b` instruction, which
+/// supports 4-byte aligned relative address within +/-128MB. For far instructions, the `pcalau12i`
+/// instruction supports relative page address within +/-2GB.
+#[inline(always)]
+pub fn arch_jump_target_in_range(jump_entry: &JumpEntry) -> bool {
+    if jump_entry.is_far() {
+        // `pcalau12i` is 8 bytes before
+        let relative_page = ((jump_entry.target_addr() >> 12)
+            .wrapping_sub((jump_entry.code_addr() - 8) >> 12)) as isize;
+        return (-(1 << 19)..(1 << 19)).contains(&relative_page);
+    }
+    let relative_addr = jump_entry
+        .target_addr()
+        .wrapping_sub(jump_entry.code_addr()) as isize;
+    relative_addr % 4 == 0 && (-(1 << 27)..(1 << 27)).contains(&relative_addr)
+}
+
+/// Length of the JMP instruction in static call trampolines
+pub const ARCH_STATIC_CALL_INS_LENGTH: usize = ARCH_JUMP_INS_LENGTH;
+
+/// New instruction of the static call trampoline recorded by given entry, which jumps to the
+/// target of the entry
+#[inline(always)]
+pub fn arch_static_call_instruction(entry: &JumpEntry) -> [u8; ARCH_STATIC_CALL_INS_LENGTH] {
+    arch_jump_entry_instruction(JumpLabelType::Jmp, entry)
+}
+
+/// Whether the target of given static call entry can be encoded in the JMP instruction of its
+/// trampoline
+#[inline(always)]
+pub fn arch_static_call_target_in_range(entry: &JumpEntry) -> bool {
+    arch_jump_target_in_range(entry)
+}
+
+/// Length of the instructions of runtime constant sites
+pub const ARCH_RUNTIME_CONST_INS_LENGTH: usize = 16;
+
+/// Immediates of the `lu12i.w`, `ori`, `lu32i.d` and `lu52i.d` instructions loading `value` at
+/// runtime constant sites, which are bits 31:12, 11:0, 51:32 and 63:52 respectively
+#[doc(hidden)]
+#[inline(always)]
+pub const fn arch_runtime_const_immediates(value: u64) -> [i64; 4] {
+    let value = value as i64;
+    [
+        (value << 32) >> 44,
+        value & 0xfff,
+        (value << 12) >> 44,
+        value >> 52,
+    ]
+}
+
+/// New instructions of the runtime constant site, which are `instruction` with their immediates
+/// replaced by `value`
+#[inline(always)]
+pub fn arch_runtime_const_instruction(
+    instruction: &[u8; ARCH_RUNTIME_CONST_INS_LENGTH],
+    value: u64,
+) -> [u8; ARCH_RUNTIME_CONST_INS_LENGTH] {
+    let immediates = arch_runtime_const_immediates(value);
+    let mut new_instruction = [0; ARCH_RUNTIME_CONST_INS_LENGTH];
+    for (index, immediate) in immediates.into_iter().enumerate() {
+        let range = index * 4..index * 4 + 4;
+        let insn = u32::from_ne_bytes(instruction[range.clone()].try_into().unwrap());
+        let insn = if index % 2 == 0 {
+            // [opcode] [si20] [rd]
+            (insn & !(0xfffff << 5)) | (((immediate as u32) & 0xfffff) << 5)
+        } else {
+            // [opcode] [imm12] [rj] [rd]
+            (insn & !(0xfff << 10)) | (((immediate as u32) & 0xfff) << 10)
+        };
+        new_instruction[range].copy_from_slice(&insn.to_ne_bytes());
+    }
+    new_instruction
+}
+
+/// Fill `code` with NOP instructions. The length of `code` is always a multiple of 4.
+#[inline(always)]
+pub fn arch_fill_nops(code: &mut [u8]) {
+    for chunk in code.chunks_mut(4) {
+        chunk.copy_from_slice(&LOONGARCH64_INSN_NOP.to_ne_bytes());
+    }
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! arch_static_key_init_nop_asm_template {
@@ -72,3 +167,188 @@ macro_rules! arch_static_key_init_jmp_asm_template {
         )
     };
 }
+
+// The address of destination is loaded by `pcalau12i` and `addi.d`, and the `jr` is placed right
+// after them.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_key_init_far_nop_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+                pcalau12i {3}, %pc_hi20({0})
+                addi.d {3}, {3}, %pc_lo12({0})
+            2:
+                nop
+            .pushsection "#,
+            $crate::os_static_key_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} + {2} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_key_init_far_jmp_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+                pcalau12i {3}, %pc_hi20({0})
+                addi.d {3}, {3}, %pc_lo12({0})
+            2:
+                jr {3}
+            .pushsection "#,
+            $crate::os_static_key_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} + {2} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_call_trampoline_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            .balign 8
+            2:
+                b {0}
+            .pushsection "#,
+            $crate::os_static_call_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_switch_asm_template {
+    ($($value: literal),+) => {
+        ::core::concat!(
+            r#"
+            2:
+            "#,
+            $(
+                ".if {initial} == ",
+                ::core::stringify!($value),
+                "\n",
+                "b {",
+                ::core::stringify!($value),
+                "}",
+                "\n.endif\n",
+            )+
+            r#"
+            .pushsection "#,
+            $crate::os_static_switch_table_sec_name_attr!(),
+            r#"
+            .balign 4
+            3:
+            .long {count}
+            "#,
+            $(".long {", ::core::stringify!($value), "} - .\n",)+
+            r#"
+            .popsection
+            .pushsection "#,
+            $crate::os_static_switch_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 3b - .
+            .quad {key} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_runtime_const_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            2:
+                lu12i.w {0}, {imm0}
+                ori {0}, {0}, {imm1}
+                lu32i.d {0}, {imm2}
+                lu52i.d {0}, {0}, {imm3}
+            3:
+            .pushsection "#,
+            $crate::os_runtime_const_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 3b - .
+            .quad {key} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_runtime_const {
+    ($key: path) => {{
+        let value: u64;
+        unsafe {
+            ::core::arch::asm!(
+                $crate::arch_runtime_const_asm_template!(),
+                out(reg) value,
+                key = sym $key,
+                imm0 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[0],
+                imm1 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[1],
+                imm2 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[2],
+                imm3 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[3],
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        value
+    }};
+}
+
+// The replacement is assembled in a separate section, and copied over the original instructions
+// in `global_init` if the predicate holds.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_alternative_asm_template {
+    ($original: expr, $replacement: expr) => {
+        ::core::concat!(
+            "2:\n",
+            $original,
+            "\n3:\n.pushsection ",
+            $crate::os_alternative_replacement_sec_name_attr!(),
+            "\n4:\n",
+            $replacement,
+            "\n5:\n.popsection\n.pushsection ",
+            $crate::os_alternative_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 4b - .
+            .quad {alternative_predicate} - .
+            .long 3b - 2b
+            .long 5b - 4b
+            .popsection
+            "#
+        )
+    };
+}
@@ -6,6 +6,8 @@ use crate::{JumpEntry, JumpLabelType};
 pub const ARCH_JUMP_INS_LENGTH: usize = 4;
 
 const RISCV_INSN_JAL: u32 = 0x0000006f;
+/// `jalr zero, 0(rs1)` without `rs1`
+const RISCV_INSN_JR: u32 = 0x00000067;
 
 /// New instruction generated according to jump label type and jump entry
 #[inline(always)]
@@ -14,12 +16,23 @@ pub fn arch_jump_entry_instruction(
     jump_entry: &JumpEntry,
 ) -> [u8; ARCH_JUMP_INS_LENGTH] {
     match jump_label_type {
+        JumpLabelType::Jmp if jump_entry.is_far() => {
+            // The destination is loaded by the preceding `addi rd, rd, %pcrel_lo(...)`
+            let addi = u32::from_ne_bytes(unsafe {
+                core::ptr::read_volatile((jump_entry.code_addr() - 4) as *const [u8; 4])
+            });
+            // [imm   ] [rs1] [000] [rd ] [opcode ]
+            // [000000] [rs1] [000] [000] [1100111]
+            (RISCV_INSN_JR | (((addi >> 7) & 0x1f) << 15)).to_ne_bytes()
+        }
         // [offset          ] [rd] [opcode ]
         // [20|10:1|11|19:12] [rd] [1101111]
         JumpLabelType::Jmp => {
-            // Note that riscv64 only supports relative address within +/-512k.
-            // In current implementation, this assumption is always hold.
-            let relative_addr = (jump_entry.target_addr() - jump_entry.code_addr()) as u32;
+            // Note that riscv64 only supports relative address within +/-1MB, which is checked
+            // in `global_init`.
+            let relative_addr = jump_entry
+                .target_addr()
+                .wrapping_sub(jump_entry.code_addr()) as u32;
             let mut jal = RISCV_INSN_JAL;
             // MASK 19:12 = 0b_0000_0000_0000_1111_1111_0000_0000_0000 = 0x000FF000
             // MASK 11    = 0b_0000_0000_0000_0000_0000_1000_0000_0000 = 0x00000800
@@ -36,6 +49,125 @@ pub fn arch_jump_entry_instruction(
     }
 }
 
+/// Whether the JMP destination of given jump entry can be encoded in the `jal` instruction, which
+/// supports 2-byte aligned relative address within +/-1MB. For far instructions, `auipc` and `addi`
+/// support relative address within +/-2GB.
+#[inline(always)]
+pub fn arch_jump_target_in_range(jump_entry: &JumpEntry) -> bool {
+    let relative_addr = jump_entry
+        .target_addr()
+        .wrapping_sub(jump_entry.code_addr()) as isize;
+    if jump_entry.is_far() {
+        // `auipc` is 8 bytes before, and the upper 20 bits are rounded by the sign of lower 12 bits
+        return i32::try_from(relative_addr + 8 + 0x800).is_ok();
+    }
+    relative_addr % 2 == 0 && (-(1 << 20)..(1 << 20)).contains(&relative_addr)
+}
+
+/// Length of the `auipc` and `jalr` instructions in static call trampolines
+pub const ARCH_STATIC_CALL_INS_LENGTH: usize = 8;
+
+/// `auipc t1, 0`
+const RISCV_INSN_AUIPC_T1: u32 = 0x00000317;
+/// `jalr zero, 0(t1)`
+const RISCV_INSN_JR_T1: u32 = 0x00030067;
+
+/// New instructions of the static call trampoline recorded by given entry, which jump to the
+/// target of the entry. The destination is loaded into `t1`, which is a temporary register never
+/// used to pass arguments.
+#[inline(always)]
+pub fn arch_static_call_instruction(entry: &JumpEntry) -> [u8; ARCH_STATIC_CALL_INS_LENGTH] {
+    // The range is checked before updating
+    let relative_addr = entry.target_addr().wrapping_sub(entry.code_addr()) as i32;
+    // The upper 20 bits are rounded by the sign of lower 12 bits
+    let hi = relative_addr.wrapping_add(0x800) >> 12;
+    let lo = relative_addr.wrapping_sub(hi << 12);
+    // [imm[31:12]] [rd ] [opcode ]
+    let auipc = RISCV_INSN_AUIPC_T1 | ((hi as u32) << 12);
+    // [imm[11:0] ] [rs1] [000] [rd ] [opcode ]
+    let jalr = RISCV_INSN_JR_T1 | (((lo as u32) & 0xfff) << 20);
+    let mut instruction = [0; ARCH_STATIC_CALL_INS_LENGTH];
+    instruction[..4].copy_from_slice(&auipc.to_ne_bytes());
+    instruction[4..].copy_from_slice(&jalr.to_ne_bytes());
+    instruction
+}
+
+/// Whether the target of given static call entry can be encoded in the `auipc` and `jalr`
+/// instructions of its trampoline, which support relative address within +/-2GB.
+#[inline(always)]
+pub fn arch_static_call_target_in_range(entry: &JumpEntry) -> bool {
+    let relative_addr = entry.target_addr().wrapping_sub(entry.code_addr()) as isize;
+    i32::try_from(relative_addr + 0x800).is_ok()
+}
+
+/// Length of the instructions of runtime constant sites
+pub const ARCH_RUNTIME_CONST_INS_LENGTH: usize = 24;
+
+/// Upper 20 bits and lower 12 bits of `value` loaded by `lui` and `addiw`. The upper 20 bits are
+/// rounded by the sign of lower 12 bits.
+const fn split_lui_addiw(value: i32) -> (i64, i64) {
+    let hi = (value as u32).wrapping_add(0x800) >> 12;
+    let lo = (value as u32).wrapping_sub(hi << 12) as i32;
+    (hi as i64, lo as i64)
+}
+
+/// Immediates of the instructions loading `value` at runtime constant sites.
+///
+/// The sign-extended lower 32 bits are loaded into a scratch register, and the upper 32 bits are
+/// adjusted by the sign of lower 32 bits before shifted and added.
+#[doc(hidden)]
+#[inline(always)]
+pub const fn arch_runtime_const_immediates(value: u64) -> [i64; 4] {
+    let lo = value as u32 as i32;
+    let hi = (value.wrapping_sub(lo as i64 as u64) >> 32) as u32 as i32;
+    let (hi_hi, hi_lo) = split_lui_addiw(hi);
+    let (lo_hi, lo_lo) = split_lui_addiw(lo);
+    [hi_hi, hi_lo, lo_hi, lo_lo]
+}
+
+/// New instructions of the runtime constant site, which are `instruction` with their immediates
+/// replaced by `value`
+#[inline(always)]
+pub fn arch_runtime_const_instruction(
+    instruction: &[u8; ARCH_RUNTIME_CONST_INS_LENGTH],
+    value: u64,
+) -> [u8; ARCH_RUNTIME_CONST_INS_LENGTH] {
+    let [hi_hi, hi_lo, lo_hi, lo_lo] = arch_runtime_const_immediates(value);
+    let mut new_instruction = *instruction;
+    // `lui`, `addiw`, `slli`, `lui`, `addiw`, `add`
+    for (index, immediate) in [(0, hi_hi), (1, hi_lo), (3, lo_hi), (4, lo_lo)] {
+        let range = index * 4..index * 4 + 4;
+        let insn = u32::from_ne_bytes(instruction[range.clone()].try_into().unwrap());
+        let insn = if index % 3 == 0 {
+            // [imm[31:12]] [rd ] [opcode ]
+            (insn & 0xfff) | ((immediate as u32) << 12)
+        } else {
+            // [imm[11:0] ] [rs1] [000] [rd ] [opcode ]
+            (insn & 0xfffff) | ((immediate as u32) << 20)
+        };
+        new_instruction[range].copy_from_slice(&insn.to_ne_bytes());
+    }
+    new_instruction
+}
+
+/// `c.nop`
+const RISCV_INSN_C_NOP: u16 = 0x0001;
+
+/// Fill `code` with NOP instructions. The length of `code` is always a multiple of 2, and the
+/// compressed `c.nop` is used for the last 2 bytes if needed.
+#[inline(always)]
+pub fn arch_fill_nops(code: &mut [u8]) {
+    for chunk in code.chunks_mut(4) {
+        if chunk.len() == 4 {
+            chunk.copy_from_slice(&[0x13, 0x00, 0x00, 0x00]);
+        } else {
+            chunk.copy_from_slice(&RISCV_INSN_C_NOP.to_ne_bytes());
+        }
+    }
+}
+
+// The `.balign 4` makes sure the instruction is naturally aligned, so it can be replaced while
+// other harts are executing it. This is the same as Linux kernel.
 #[doc(hidden)]
 #[macro_export]
 macro_rules! arch_static_key_init_nop_asm_template {
@@ -44,6 +176,7 @@ macro_rules! arch_static_key_init_nop_asm_template {
             r#"
             .option push
             .option norelax
+            .balign 4
             .option norvc
             2:
                 nop
@@ -69,6 +202,7 @@ macro_rules! arch_static_key_init_jmp_asm_template {
             r#"
             .option push
             .option norelax
+            .balign 4
             .option norvc
             2:
                 jal zero, {0}
@@ -85,3 +219,220 @@ macro_rules! arch_static_key_init_jmp_asm_template {
         )
     };
 }
+
+// The address of destination is loaded by `auipc` and `addi`, and the `jr` is placed right after them.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_key_init_far_nop_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            .option push
+            .option norelax
+            .balign 4
+            .option norvc
+            1:
+                auipc {3}, %pcrel_hi({0})
+                addi {3}, {3}, %pcrel_lo(1b)
+            2:
+                nop
+            .option pop
+            .pushsection "#,
+            $crate::os_static_key_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} + {2} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_key_init_far_jmp_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            .option push
+            .option norelax
+            .balign 4
+            .option norvc
+            1:
+                auipc {3}, %pcrel_hi({0})
+                addi {3}, {3}, %pcrel_lo(1b)
+            2:
+                jr {3}
+            .option pop
+            .pushsection "#,
+            $crate::os_static_key_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} + {2} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+// The `jalr` is placed right after `auipc`, so that both instructions can be replaced without
+// relaxation.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_call_trampoline_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            .balign 8
+            .option push
+            .option norelax
+            .option norvc
+            2:
+                auipc t1, %pcrel_hi({0})
+                jalr zero, %pcrel_lo(2b)(t1)
+            .option pop
+            .pushsection "#,
+            $crate::os_static_call_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_switch_asm_template {
+    ($($value: literal),+) => {
+        ::core::concat!(
+            r#"
+            .option push
+            .option norelax
+            .balign 4
+            .option norvc
+            2:
+            "#,
+            $(
+                ".if {initial} == ",
+                ::core::stringify!($value),
+                "\n",
+                "jal zero, {",
+                ::core::stringify!($value),
+                "}",
+                "\n.endif\n",
+            )+
+            r#"
+            .option pop
+            .pushsection "#,
+            $crate::os_static_switch_table_sec_name_attr!(),
+            r#"
+            .balign 4
+            3:
+            .long {count}
+            "#,
+            $(".long {", ::core::stringify!($value), "} - .\n",)+
+            r#"
+            .popsection
+            .pushsection "#,
+            $crate::os_static_switch_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 3b - .
+            .quad {key} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+// The value is loaded by `lui` and `addiw` in two halves, and `{1}` is a scratch register.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_runtime_const_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            .option push
+            .option norelax
+            .balign 4
+            .option norvc
+            2:
+                lui {0}, {imm0}
+                addiw {0}, {0}, {imm1}
+                slli {0}, {0}, 32
+                lui {1}, {imm2}
+                addiw {1}, {1}, {imm3}
+                add {0}, {0}, {1}
+            3:
+            .option pop
+            .pushsection "#,
+            $crate::os_runtime_const_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 3b - .
+            .quad {key} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_runtime_const {
+    ($key: path) => {{
+        let value: u64;
+        unsafe {
+            ::core::arch::asm!(
+                $crate::arch_runtime_const_asm_template!(),
+                out(reg) value,
+                out(reg) _,
+                key = sym $key,
+                imm0 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[0],
+                imm1 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[1],
+                imm2 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[2],
+                imm3 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[3],
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        value
+    }};
+}
+
+// The replacement is assembled in a separate section, and copied over the original instructions
+// in `global_init` if the predicate holds.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_alternative_asm_template {
+    ($original: expr, $replacement: expr) => {
+        ::core::concat!(
+            "2:\n",
+            $original,
+            "\n3:\n.pushsection ",
+            $crate::os_alternative_replacement_sec_name_attr!(),
+            "\n4:\n",
+            $replacement,
+            "\n5:\n.popsection\n.pushsection ",
+            $crate::os_alternative_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 4b - .
+            .quad {alternative_predicate} - .
+            .long 3b - 2b
+            .long 5b - 4b
+            .popsection
+            "#
+        )
+    };
+}
@@ -13,8 +13,10 @@ pub fn arch_jump_entry_instruction(
 ) -> [u8; ARCH_JUMP_INS_LENGTH] {
     match jump_label_type {
         JumpLabelType::Jmp => {
-            let relative_addr =
-                (jump_entry.target_addr() - (jump_entry.code_addr() + ARCH_JUMP_INS_LENGTH)) as u32;
+            let relative_addr = jump_entry
+                .target_addr()
+                .wrapping_sub(jump_entry.code_addr() + ARCH_JUMP_INS_LENGTH)
+                as u32;
             let [a, b, c, d] = relative_addr.to_ne_bytes();
             [0xe9, a, b, c, d]
         }
@@ -22,6 +24,77 @@ pub fn arch_jump_entry_instruction(
     }
 }
 
+/// Whether the JMP destination of given jump entry can be encoded in the JMP instruction
+#[inline(always)]
+pub fn arch_jump_target_in_range(jump_entry: &JumpEntry) -> bool {
+    let relative_addr = jump_entry
+        .target_addr()
+        .wrapping_sub(jump_entry.code_addr() + ARCH_JUMP_INS_LENGTH)
+        as isize;
+    i32::try_from(relative_addr).is_ok()
+}
+
+/// Length of the JMP instruction in static call trampolines
+pub const ARCH_STATIC_CALL_INS_LENGTH: usize = ARCH_JUMP_INS_LENGTH;
+
+/// New instruction of the static call trampoline recorded by given entry, which jumps to the
+/// target of the entry
+#[inline(always)]
+pub fn arch_static_call_instruction(entry: &JumpEntry) -> [u8; ARCH_STATIC_CALL_INS_LENGTH] {
+    arch_jump_entry_instruction(JumpLabelType::Jmp, entry)
+}
+
+/// Whether the target of given static call entry can be encoded in the JMP instruction of its
+/// trampoline
+#[inline(always)]
+pub fn arch_static_call_target_in_range(entry: &JumpEntry) -> bool {
+    arch_jump_target_in_range(entry)
+}
+
+/// Length of the `movabs` instruction of runtime constant sites
+pub const ARCH_RUNTIME_CONST_INS_LENGTH: usize = 10;
+
+/// Immediates of the instructions loading `value` at runtime constant sites
+#[doc(hidden)]
+#[inline(always)]
+pub const fn arch_runtime_const_immediates(value: u64) -> [i64; 4] {
+    [value as i64, 0, 0, 0]
+}
+
+/// New instruction of the runtime constant site, which is `instruction` with its immediate
+/// replaced by `value`
+#[inline(always)]
+pub fn arch_runtime_const_instruction(
+    instruction: &[u8; ARCH_RUNTIME_CONST_INS_LENGTH],
+    value: u64,
+) -> [u8; ARCH_RUNTIME_CONST_INS_LENGTH] {
+    let mut new_instruction = *instruction;
+    // REX.W prefix and opcode are followed by the 8-byte immediate
+    new_instruction[2..].copy_from_slice(&value.to_ne_bytes());
+    new_instruction
+}
+
+/// Recommended multi-byte NOP instructions of each length, the same as `x86_nops` of Linux kernel
+const X86_NOPS: [&[u8]; 9] = [
+    &[],
+    &[0x90],
+    &[0x66, 0x90],
+    &[0x0f, 0x1f, 0x00],
+    &[0x0f, 0x1f, 0x40, 0x00],
+    &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x80, 0x00, 0x00, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
+
+/// Fill `code` with NOP instructions, using as few instructions as possible
+#[inline(always)]
+pub fn arch_fill_nops(code: &mut [u8]) {
+    for chunk in code.chunks_mut(X86_NOPS.len() - 1) {
+        chunk.copy_from_slice(X86_NOPS[chunk.len()]);
+    }
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! arch_static_key_init_nop_asm_template {
@@ -66,3 +139,155 @@ macro_rules! arch_static_key_init_jmp_asm_template {
         )
     };
 }
+
+// The trampoline of a static call is a 5-byte JMP to its target, see
+// `arch_static_key_init_jmp_asm_template`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_call_trampoline_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            .balign 8
+            2:
+            .byte 0xe9
+            .long ({0} - 4) - .
+            .byte 0xcc,0xcc,0xcc
+            .pushsection "#,
+            $crate::os_static_call_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad {0} - .
+            .quad {1} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+// The JMP instruction of a static switch site jumps to the arm of initial value, and the relative
+// addresses of all arms are recorded in a table. See `arch_static_key_init_jmp_asm_template`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_static_switch_asm_template {
+    ($($value: literal),+) => {
+        ::core::concat!(
+            r#"
+            2:
+            "#,
+            $(
+                ".if {initial} == ",
+                ::core::stringify!($value),
+                "\n",
+                ".byte 0xe9\n.long ({",
+                ::core::stringify!($value),
+                "} - 4) - .",
+                "\n.endif\n",
+            )+
+            r#"
+            .pushsection "#,
+            $crate::os_static_switch_table_sec_name_attr!(),
+            r#"
+            .balign 4
+            3:
+            .long {count}
+            "#,
+            $(".long {", ::core::stringify!($value), "} - .\n",)+
+            r#"
+            .popsection
+            .pushsection "#,
+            $crate::os_static_switch_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 3b - .
+            .quad {key} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+// Here we do not use `movabs rax, {0}` because the operand order differs between Intel and AT&T
+// syntax. The immediate is always 8 bytes in `movabs`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_runtime_const_asm_template {
+    () => {
+        ::core::concat!(
+            r#"
+            2:
+            .byte 0x48,0xb8
+            .quad {imm0}
+            3:
+            .pushsection "#,
+            $crate::os_runtime_const_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 3b - .
+            .quad {key} - .
+            .popsection
+            "#
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_runtime_const {
+    ($key: path) => {{
+        let value: u64;
+        // This is an ugly workaround for https://github.com/rust-lang/rust/issues/128177
+        #[cfg(not(target_os = "windows"))]
+        unsafe {
+            ::core::arch::asm!(
+                $crate::arch_runtime_const_asm_template!(),
+                key = sym $key,
+                imm0 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[0],
+                out("rax") value,
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        #[cfg(target_os = "windows")]
+        unsafe {
+            ::core::arch::asm!(
+                $crate::arch_runtime_const_asm_template!(),
+                key = sym $key,
+                imm0 = const $crate::runtime_const::arch_runtime_const_immediates($key.initial_value())[0],
+                out("rax") value,
+                options(nomem, nostack, preserves_flags, att_syntax),
+            );
+        }
+        value
+    }};
+}
+
+// The replacement is assembled in a separate section, and copied over the original instructions
+// in `global_init` if the predicate holds.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! arch_alternative_asm_template {
+    ($original: expr, $replacement: expr) => {
+        ::core::concat!(
+            "2:\n",
+            $original,
+            "\n3:\n.pushsection ",
+            $crate::os_alternative_replacement_sec_name_attr!(),
+            "\n4:\n",
+            $replacement,
+            "\n5:\n.popsection\n.pushsection ",
+            $crate::os_alternative_sec_name_attr!(),
+            r#"
+            .balign 8
+            .quad 2b - .
+            .quad 4b - .
+            .quad {alternative_predicate} - .
+            .long 3b - 2b
+            .long 5b - 4b
+            .popsection
+            "#
+        )
+    };
+}
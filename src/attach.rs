@@ -0,0 +1,562 @@
+//! Modify static keys of another process with ptrace. Only available with `std` feature on Linux.
+//!
+//! [`RemoteProcess`] locates the `__static_keys` section and its companion section of names in the
+//! executable of the target process, and the load base in `/proc/<pid>/maps`. To modify a static
+//! key, all threads of the target process are stopped with ptrace, the instructions and the status
+//! of the static key are written through `/proc/<pid>/mem`, which can write read-only code pages
+//! unlike `process_vm_writev`, and then the threads are resumed.
+//!
+//! The target process must use the same version of this crate, and only the static keys used in its
+//! executable are known. Bookkeeping of the target process, such as pending disables of deferred
+//! static keys and counts of counted static keys, is not updated.
+
+use std::{
+    fs::File,
+    io,
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    GenericStaticKey, JumpEntry,
+    arch::ARCH_JUMP_INS_LENGTH,
+    code_manipulate::DummyCodeManipulator,
+    control::{ControlTarget, KeyState},
+    elf::{Elf, PT_LOAD},
+};
+
+/// Name of the section storing jump entries
+const JUMP_ENTRY_SECTION: &str = "__static_keys";
+/// Name of the section storing names of static keys
+const KEY_NAME_SECTION: &str = "__static_key_names";
+/// Symbol of the process-wide patch lock, see [`crate::patch_lock`]
+const PATCH_LOCK_SYMBOL: &str = "static_keys_patch_lock_v1";
+/// Times to retry when the target process is modifying static keys
+const LOCK_RETRIES: usize = 1000;
+/// Size of a word
+const WORD: usize = core::mem::size_of::<usize>();
+
+/// Offset of the status in a static key
+const ENABLED_OFFSET: usize =
+    core::mem::offset_of!(GenericStaticKey<DummyCodeManipulator, true>, enabled);
+/// Offset of the initial status in a static key
+const INITIAL_OFFSET: usize =
+    core::mem::offset_of!(GenericStaticKey<DummyCodeManipulator, true>, initial);
+
+/// Error occurred when modifying static keys of another process.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AttachError {
+    /// Failed to access files of the target process in `/proc`
+    Io {
+        /// Path of the accessed file
+        path: PathBuf,
+        /// Error reported by the OS
+        error: io::Error,
+    },
+    /// The executable of the target process is not a supported ELF file
+    InvalidElf(&'static str),
+    /// The executable of the target process does not use static keys
+    NoStaticKeys,
+    /// The executable of the target process is not found in `/proc/<pid>/maps`
+    NotMapped,
+    /// The static key is not known
+    UnknownKey(String),
+    /// Failed to stop or resume a thread of the target process
+    Ptrace {
+        /// ID of the thread
+        tid: i32,
+        /// `errno` of the failed system call
+        errno: i32,
+    },
+    /// The target process keeps modifying static keys
+    Busy,
+    /// The instruction at a jump entry matches neither status of its static key
+    UnexpectedInstruction {
+        /// Address of the instruction in the target process
+        site: usize,
+    },
+    /// The instruction at a jump entry is generated by
+    /// [`static_branch_likely_far`][crate::static_branch_likely_far] or
+    /// [`static_branch_unlikely_far`][crate::static_branch_unlikely_far], which is not supported on
+    /// current architecture
+    UnsupportedFarBranch {
+        /// Address of the instruction in the target process
+        site: usize,
+    },
+}
+
+impl core::fmt::Display for AttachError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "failed to access {}: {error}", path.display()),
+            Self::InvalidElf(reason) => write!(f, "invalid executable: {reason}"),
+            Self::NoStaticKeys => write!(f, "the executable does not use static keys"),
+            Self::NotMapped => write!(f, "the executable is not mapped"),
+            Self::UnknownKey(name) => write!(f, "unknown static key {name}"),
+            Self::Ptrace { tid, errno } => {
+                write!(f, "ptrace of thread {tid} failed with error code {errno}")
+            }
+            Self::Busy => write!(f, "the process keeps modifying static keys"),
+            Self::UnexpectedInstruction { site } => {
+                write!(f, "unexpected instruction at {site:#x}")
+            }
+            Self::UnsupportedFarBranch { site } => {
+                write!(f, "far branch at {site:#x} is not supported")
+            }
+        }
+    }
+}
+
+impl core::error::Error for AttachError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// A static key of another process, yielded by [`RemoteProcess::keys`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteKey {
+    /// Address of the static key in the target process
+    addr: usize,
+    /// Name of the static key, if recorded
+    name: Option<String>,
+    /// Current status
+    enabled: bool,
+    /// Initial status
+    initial: bool,
+    /// Count of jump entries
+    site_count: usize,
+}
+
+impl RemoteKey {
+    /// Address of the static key in the target process
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Name of the static key, if it is defined by
+    /// [`define_static_key_false`][crate::define_static_key_false] or
+    /// [`define_static_key_true`][crate::define_static_key_true]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Current status of the static key
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Initial status of the static key
+    pub fn initial_enabled(&self) -> bool {
+        self.initial
+    }
+
+    /// Count of branch sites associated with the static key
+    pub fn site_count(&self) -> usize {
+        self.site_count
+    }
+}
+
+/// Threads of the target process stopped by ptrace. They are resumed when dropped.
+struct StoppedThreads {
+    /// IDs of stopped threads
+    tids: Vec<i32>,
+}
+
+impl StoppedThreads {
+    /// Stop all threads of process `pid`
+    fn stop(pid: i32) -> Result<Self, AttachError> {
+        let mut stopped = Self { tids: Vec::new() };
+        let task_dir = PathBuf::from(std::format!("/proc/{pid}/task"));
+        // Threads may be created while stopping others, so repeat until no new thread is found
+        loop {
+            let entries = std::fs::read_dir(&task_dir).map_err(|error| AttachError::Io {
+                path: task_dir.clone(),
+                error,
+            })?;
+            let mut found_new = false;
+            for entry in entries {
+                let Some(tid) = entry
+                    .ok()
+                    .and_then(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+                else {
+                    continue;
+                };
+                if stopped.tids.contains(&tid) {
+                    continue;
+                }
+                found_new = true;
+                if let Err(err) = stop_thread(tid) {
+                    // The thread has exited
+                    if err == libc::ESRCH {
+                        continue;
+                    }
+                    return Err(AttachError::Ptrace { tid, errno: err });
+                }
+                stopped.tids.push(tid);
+            }
+            if !found_new {
+                return Ok(stopped);
+            }
+        }
+    }
+}
+
+impl Drop for StoppedThreads {
+    fn drop(&mut self) {
+        for &tid in &self.tids {
+            unsafe { libc::ptrace(libc::PTRACE_DETACH, tid, 0usize, 0usize) };
+        }
+    }
+}
+
+/// `errno` of last failed system call
+fn errno() -> i32 {
+    io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/// Attach to a thread and wait until it is stopped
+fn stop_thread(tid: i32) -> Result<(), i32> {
+    if unsafe { libc::ptrace(libc::PTRACE_SEIZE, tid, 0usize, 0usize) } != 0 {
+        return Err(errno());
+    }
+    if unsafe { libc::ptrace(libc::PTRACE_INTERRUPT, tid, 0usize, 0usize) } != 0 {
+        let err = errno();
+        unsafe { libc::ptrace(libc::PTRACE_DETACH, tid, 0usize, 0usize) };
+        return Err(err);
+    }
+    let mut status = 0;
+    if unsafe { libc::waitpid(tid, &mut status, libc::__WALL) } != tid {
+        let err = errno();
+        unsafe { libc::ptrace(libc::PTRACE_DETACH, tid, 0usize, 0usize) };
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Jump entries and named static keys read from the target process
+struct Snapshot {
+    /// Non-dummy jump entries with absolute addresses, sorted by key address
+    jump_entries: Vec<JumpEntry>,
+    /// Static key addresses and names
+    names: Vec<(usize, String)>,
+}
+
+/// Another process whose static keys can be read and modified.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// use static_keys::attach::RemoteProcess;
+///
+/// let process = RemoteProcess::open(1234).unwrap();
+/// for key in process.keys().unwrap() {
+///     println!("{:?}: {}", key.name(), key.is_enabled());
+/// }
+/// process.set("net.trace", true).unwrap();
+/// ```
+pub struct RemoteProcess {
+    /// Process ID
+    pid: i32,
+    /// `/proc/<pid>/mem`
+    mem: File,
+    /// Path of `/proc/<pid>/mem`
+    mem_path: PathBuf,
+    /// Content of `__static_keys` section in the executable
+    jump_entry_data: Vec<u8>,
+    /// Address of `__static_keys` section in the target process
+    jump_entry_addr: usize,
+    /// Address and size of the section storing names of static keys in the target process
+    key_name_section: Option<(usize, usize)>,
+    /// Address of the patch lock in the target process, if the executable is not stripped
+    patch_lock_addr: Option<usize>,
+}
+
+/// Read a file in `/proc`
+fn read_proc(path: PathBuf) -> Result<Vec<u8>, AttachError> {
+    std::fs::read(&path).map_err(|error| AttachError::Io { path, error })
+}
+
+impl RemoteProcess {
+    /// Locate the static keys of process `pid`.
+    ///
+    /// Reading and modifying another process requires the permission to ptrace it.
+    pub fn open(pid: i32) -> Result<Self, AttachError> {
+        let exe_path = PathBuf::from(std::format!("/proc/{pid}/exe"));
+        let exe = std::fs::read_link(&exe_path).map_err(|error| AttachError::Io {
+            path: exe_path.clone(),
+            error,
+        })?;
+        let elf = Elf::parse(read_proc(exe_path)?).map_err(AttachError::InvalidElf)?;
+        let jump_entry_section = elf
+            .section(JUMP_ENTRY_SECTION)
+            .ok_or(AttachError::NoStaticKeys)?;
+        let jump_entry_data = elf
+            .section_data(jump_entry_section)
+            .ok_or(AttachError::InvalidElf("malformed __static_keys section"))?
+            .to_vec();
+
+        let base = if elf.is_dyn() {
+            // The first mapping of the executable is its lowest loadable segment
+            let maps_path = PathBuf::from(std::format!("/proc/{pid}/maps"));
+            let maps = String::from_utf8_lossy(&read_proc(maps_path)?).into_owned();
+            let exe = exe.to_string_lossy();
+            let start = maps
+                .lines()
+                .find(|line| line.ends_with(&*exe))
+                .and_then(|line| line.split('-').next())
+                .and_then(|start| usize::from_str_radix(start, 16).ok())
+                .ok_or(AttachError::NotMapped)?;
+            let lowest_vaddr = elf
+                .segments()
+                .iter()
+                .filter(|segment| segment.kind == PT_LOAD)
+                .map(|segment| segment.vaddr)
+                .min()
+                .ok_or(AttachError::InvalidElf("no loadable segment"))?;
+            start.wrapping_sub(lowest_vaddr & !0xfff)
+        } else {
+            0
+        };
+
+        let mem_path = PathBuf::from(std::format!("/proc/{pid}/mem"));
+        let mem = File::options()
+            .read(true)
+            .write(true)
+            .open(&mem_path)
+            .map_err(|error| AttachError::Io {
+                path: mem_path.clone(),
+                error,
+            })?;
+        Ok(Self {
+            pid,
+            mem,
+            mem_path,
+            jump_entry_data,
+            jump_entry_addr: base.wrapping_add(jump_entry_section.addr),
+            key_name_section: elf
+                .section(KEY_NAME_SECTION)
+                .map(|section| (base.wrapping_add(section.addr), section.size)),
+            patch_lock_addr: elf
+                .symbol(PATCH_LOCK_SYMBOL)
+                .map(|addr| base.wrapping_add(addr)),
+        })
+    }
+
+    /// Process ID of the target process
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Read memory of the target process
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), AttachError> {
+        self.mem
+            .read_exact_at(buf, addr as u64)
+            .map_err(|error| AttachError::Io {
+                path: self.mem_path.clone(),
+                error,
+            })
+    }
+
+    /// Write memory of the target process
+    fn write(&self, addr: usize, buf: &[u8]) -> Result<(), AttachError> {
+        self.mem
+            .write_all_at(buf, addr as u64)
+            .map_err(|error| AttachError::Io {
+                path: self.mem_path.clone(),
+                error,
+            })
+    }
+
+    /// Read a word of the target process
+    fn read_word(&self, addr: usize) -> Result<usize, AttachError> {
+        let mut buf = [0; WORD];
+        self.read(addr, &mut buf)?;
+        Ok(usize::from_ne_bytes(buf))
+    }
+
+    /// Read a boolean of the target process
+    fn read_bool(&self, addr: usize) -> Result<bool, AttachError> {
+        let mut buf = [0; 1];
+        self.read(addr, &mut buf)?;
+        Ok(buf[0] != 0)
+    }
+
+    /// Read the instruction at given jump entry of the target process
+    fn read_instruction(
+        &self,
+        jump_entry: &JumpEntry,
+    ) -> Result<[u8; ARCH_JUMP_INS_LENGTH], AttachError> {
+        let mut buf = [0; ARCH_JUMP_INS_LENGTH];
+        self.read(jump_entry.code_addr(), &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read jump entries and names of static keys of the target process
+    fn snapshot(&self) -> Result<Snapshot, AttachError> {
+        let mut data = std::vec![0; self.jump_entry_data.len()];
+        self.read(self.jump_entry_addr, &mut data)?;
+        // Before global_init, the jump entries are the same as the ones in the executable, whose
+        // fields are relative addresses
+        let uninitialized = data == self.jump_entry_data;
+        let jump_entries =
+            crate::elf::decode_jump_entries(&data, self.jump_entry_addr, uninitialized);
+
+        let mut names = Vec::new();
+        if let Some((addr, size)) = self.key_name_section {
+            let mut data = std::vec![0; size];
+            self.read(addr, &mut data)?;
+            for chunk in data.chunks_exact(3 * WORD) {
+                let [key_addr, name_addr, name_len] = [0, WORD, 2 * WORD]
+                    .map(|offset| crate::elf::read_word(chunk, offset).unwrap_or(0));
+                if key_addr == 0 {
+                    continue;
+                }
+                let mut name = std::vec![0; name_len];
+                self.read(name_addr, &mut name)?;
+                names.push((key_addr, String::from_utf8_lossy(&name).into_owned()));
+            }
+        }
+        Ok(Snapshot {
+            jump_entries,
+            names,
+        })
+    }
+
+    /// All static keys used by the executable of the target process or named, sorted by address.
+    ///
+    /// The target process keeps running, so the result may be outdated immediately.
+    pub fn keys(&self) -> Result<Vec<RemoteKey>, AttachError> {
+        let snapshot = self.snapshot()?;
+        let mut addrs = snapshot
+            .jump_entries
+            .iter()
+            .map(JumpEntry::key_addr)
+            .chain(snapshot.names.iter().map(|(addr, _)| *addr))
+            .collect::<Vec<_>>();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+            .into_iter()
+            .map(|addr| {
+                Ok(RemoteKey {
+                    addr,
+                    name: snapshot
+                        .names
+                        .iter()
+                        .find(|(key_addr, _)| *key_addr == addr)
+                        .map(|(_, name)| name.clone()),
+                    enabled: self.read_bool(addr + ENABLED_OFFSET)?,
+                    initial: self.read_bool(addr + INITIAL_OFFSET)?,
+                    site_count: snapshot
+                        .jump_entries
+                        .iter()
+                        .filter(|jump_entry| jump_entry.key_addr() == addr)
+                        .count(),
+                })
+            })
+            .collect()
+    }
+
+    /// Set the status of the static key with given name.
+    pub fn set(&self, name: &str, enabled: bool) -> Result<(), AttachError> {
+        let key = self
+            .keys()?
+            .into_iter()
+            .find(|key| key.name() == Some(name))
+            .ok_or_else(|| AttachError::UnknownKey(name.to_string()))?;
+        self.set_addr(key.addr, enabled)
+    }
+
+    /// Set the status of the static key at `key_addr` in the target process.
+    ///
+    /// All threads of the target process are stopped while modifying. If the target process is
+    /// modifying static keys, it is resumed and stopped again later. Nothing is modified if any
+    /// instruction is unexpected.
+    pub fn set_addr(&self, key_addr: usize, enabled: bool) -> Result<(), AttachError> {
+        let _stopped = self.stop_unlocked()?;
+        let snapshot = self.snapshot()?;
+        let current = self.read_bool(key_addr + ENABLED_OFFSET)?;
+        if current == enabled {
+            return Ok(());
+        }
+        let mut patches = Vec::new();
+        for jump_entry in snapshot
+            .jump_entries
+            .iter()
+            .filter(|jump_entry| jump_entry.key_addr() == key_addr)
+        {
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            if jump_entry.is_far() {
+                // The register of JMP instruction is decided by instructions in the target process
+                return Err(AttachError::UnsupportedFarBranch {
+                    site: jump_entry.code_addr(),
+                });
+            }
+            let found = self.read_instruction(jump_entry)?;
+            if found == crate::jump_entry_instruction(jump_entry, current) {
+                patches.push((
+                    jump_entry.code_addr(),
+                    crate::jump_entry_instruction(jump_entry, enabled),
+                ));
+            } else {
+                return Err(AttachError::UnexpectedInstruction {
+                    site: jump_entry.code_addr(),
+                });
+            }
+        }
+        for (site, instruction) in patches {
+            self.write(site, &instruction)?;
+        }
+        self.write(key_addr + ENABLED_OFFSET, &[enabled as u8])
+    }
+
+    /// Execute a command of the [control protocol](crate::control) on the target process, and
+    /// return its response.
+    pub fn execute(&self, command: &str) -> String {
+        // Modifying another process never affects current process
+        unsafe { crate::control::execute(self, command) }
+    }
+
+    /// Stop all threads of the target process when it is not modifying static keys
+    fn stop_unlocked(&self) -> Result<StoppedThreads, AttachError> {
+        for _ in 0..LOCK_RETRIES {
+            let stopped = StoppedThreads::stop(self.pid)?;
+            match self.patch_lock_addr {
+                Some(addr) if self.read_word(addr)? != 0 => {
+                    drop(stopped);
+                    std::thread::sleep(core::time::Duration::from_millis(1));
+                }
+                _ => return Ok(stopped),
+            }
+        }
+        Err(AttachError::Busy)
+    }
+}
+
+impl ControlTarget for RemoteProcess {
+    fn keys(&self) -> Result<Vec<KeyState>, String> {
+        Ok(RemoteProcess::keys(self)
+            .map_err(|err| err.to_string())?
+            .into_iter()
+            .filter_map(|key| {
+                Some(KeyState {
+                    addr: key.addr,
+                    name: key.name?,
+                    enabled: key.enabled,
+                    initial: key.initial,
+                })
+            })
+            .collect())
+    }
+
+    unsafe fn set(&self, addr: usize, enabled: bool) -> Result<(), String> {
+        self.set_addr(addr, enabled).map_err(|err| err.to_string())
+    }
+}
@@ -0,0 +1,75 @@
+//! Pre-bake the default status of static keys into a copy of an ELF executable or shared object.
+//!
+//! Each `PATTERN=VALUE` sets the static keys whose name or symbol matches `PATTERN`, where `VALUE`
+//! is `on`, `off`, `true`, `false`, `1` or `0`. The output file is written only if all of them
+//! succeed. See `static_keys::dump` for details.
+
+use std::process::ExitCode;
+
+use static_keys::dump::Image;
+
+/// Usage of this binary
+const USAGE: &str = "Usage: static-keys-bake <INPUT> <OUTPUT> <PATTERN=on|off>...";
+
+/// Parse an item in the form of `PATTERN=VALUE`
+fn parse_item(item: &str) -> Option<(&str, bool)> {
+    let (pattern, value) = item.split_once('=')?;
+    let enabled = match value {
+        "on" | "true" | "1" => true,
+        "off" | "false" | "0" => false,
+        _ => return None,
+    };
+    Some((pattern, enabled))
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [input, output, items @ ..] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let Some(items) = items
+        .iter()
+        .map(|item| parse_item(item))
+        .collect::<Option<Vec<_>>>()
+        .filter(|items| !items.is_empty())
+    else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let mut image = match Image::open(input) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("Failed to inspect {input}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    for (pattern, enabled) in items {
+        match image.set_matching(pattern, enabled) {
+            Ok(0) => {
+                eprintln!("No static key matches {pattern}");
+                return ExitCode::FAILURE;
+            }
+            Ok(count) => println!(
+                "{pattern}: {count} static key{} set to {}",
+                if count == 1 { "" } else { "s" },
+                if enabled { "on" } else { "off" }
+            ),
+            Err(err) => {
+                eprintln!("Failed to set {pattern}: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let written = std::fs::write(output, image.data()).and_then(|()| {
+        // Keep the output executable
+        std::fs::set_permissions(output, std::fs::metadata(input)?.permissions())
+    });
+    if let Err(err) = written {
+        eprintln!("Failed to write {output}: {err}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,99 @@
+//! Send a command to the control server of static keys, and print its response.
+//!
+//! See `static_keys::control` for the protocol. On Linux, `static-keys-ctl attach <PID> <command>`
+//! executes the command on another process with ptrace instead, see `static_keys::attach`.
+
+use std::process::ExitCode;
+
+/// Usage of this binary
+const USAGE: &str = "Usage: static-keys-ctl <SOCKET | attach PID> <list | get NAME | enable PATTERN | disable PATTERN | reset [PATTERN]>";
+
+#[cfg(unix)]
+fn main() -> ExitCode {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+    };
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [socket, command @ ..] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    if command.is_empty() {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    }
+    if socket == "attach" {
+        return attach(command);
+    }
+
+    let response = (|| -> std::io::Result<String> {
+        let mut stream = UnixStream::connect(socket)?;
+        stream.write_all(format!("{}\n", command.join(" ")).as_bytes())?;
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        Ok(response)
+    })();
+    match response {
+        Ok(response) => {
+            print!("{response}");
+            exit_code(&response)
+        }
+        Err(err) => {
+            eprintln!("Failed to communicate with {socket}: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Exit code according to the response
+#[cfg(unix)]
+fn exit_code(response: &str) -> ExitCode {
+    if response.starts_with("{\"ok\":true") {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Execute the command on another process
+#[cfg(target_os = "linux")]
+fn attach(args: &[String]) -> ExitCode {
+    let [pid, command @ ..] = args else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let Ok(pid) = pid.parse() else {
+        eprintln!("Invalid process ID {pid}");
+        return ExitCode::from(2);
+    };
+    if command.is_empty() {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    }
+    match static_keys::attach::RemoteProcess::open(pid) {
+        Ok(process) => {
+            let response = process.execute(&command.join(" "));
+            println!("{response}");
+            exit_code(&response)
+        }
+        Err(err) => {
+            eprintln!("Failed to attach to process {pid}: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn attach(_args: &[String]) -> ExitCode {
+    eprintln!("Attaching to other processes is only supported on Linux.");
+    ExitCode::FAILURE
+}
+
+#[cfg(not(unix))]
+fn main() -> ExitCode {
+    eprintln!("{USAGE}");
+    eprintln!("Unix domain sockets are not supported on this platform.");
+    ExitCode::FAILURE
+}
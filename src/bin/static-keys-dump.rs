@@ -0,0 +1,100 @@
+//! Print static keys and their branch sites in an ELF executable or shared object.
+//!
+//! Each `--expect NAME` requires a static key with given name or symbol to have at least one
+//! branch site, otherwise the exit code is non-zero. See `static_keys::dump` for details.
+
+use std::process::ExitCode;
+
+use static_keys::{
+    InstructionKind,
+    dump::{Image, ImageKey},
+};
+
+/// Usage of this binary
+const USAGE: &str = "Usage: static-keys-dump [--expect NAME]... <FILE>";
+
+/// Print a static key and its branch sites
+fn print_key(key: &ImageKey) {
+    let label = match (key.name(), key.symbol()) {
+        (Some(name), Some(symbol)) => format!("{name} ({symbol})"),
+        (Some(label), None) | (None, Some(label)) => label.to_string(),
+        (None, None) => format!("<unnamed> ({:#x})", key.addr()),
+    };
+    let sites = key.sites().len();
+    println!(
+        "{label}: initially {}, {sites} site{}",
+        if key.initial_enabled() { "on" } else { "off" },
+        if sites == 1 { "" } else { "s" }
+    );
+    for site in key.sites() {
+        let location = match site.function() {
+            Some((function, offset)) => format!("{function}+{offset:#x}"),
+            None => "<unknown>".to_string(),
+        };
+        let instruction = match site.instruction() {
+            InstructionKind::Nop => "nop",
+            InstructionKind::Jmp => "jmp",
+            InstructionKind::Unknown => "unknown",
+        };
+        println!(
+            "    {:#x} {location}: {instruction}, likely {}{}",
+            site.code_addr(),
+            site.likely_branch_is_true(),
+            if site.is_far() { ", far" } else { "" }
+        );
+    }
+}
+
+fn main() -> ExitCode {
+    let mut expected = Vec::new();
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--expect" {
+            let Some(name) = args.next() else {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            };
+            expected.push(name);
+        } else if path.is_none() {
+            path = Some(arg);
+        } else {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let keys = match Image::open(&path) {
+        Ok(image) => image.keys(),
+        Err(err) => {
+            eprintln!("Failed to inspect {path}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    for key in &keys {
+        print_key(key);
+    }
+
+    let mut exit_code = ExitCode::SUCCESS;
+    for name in &expected {
+        match keys
+            .iter()
+            .find(|key| key.name() == Some(name) || key.symbol() == Some(name))
+        {
+            Some(key) if key.sites().is_empty() => {
+                eprintln!("Static key {name} has no branch sites");
+                exit_code = ExitCode::FAILURE;
+            }
+            Some(_) => {}
+            None => {
+                eprintln!("Static key {name} is not found");
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+    }
+    exit_code
+}
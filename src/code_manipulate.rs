@@ -3,6 +3,8 @@
 //! Since we need to make the code region writable and restore it during jump entry update,
 //! we need to provide utility functions here.
 
+pub use crate::os::ArchCodeManipulator;
+
 /// Manipulate memory protection in code region.
 pub trait CodeManipulator {
     /// Write `data` as code instruction to `addr`.
@@ -10,6 +12,9 @@ pub trait CodeManipulator {
     /// The `addr` is not aligned, you need to align it you self. The length is not too long, usually
     /// 5 bytes.
     ///
+    /// If an error is returned, the data may be partially written. The caller will write the
+    /// original instruction back.
+    ///
     /// # Safety
     ///
     /// This method will do best effort to make the code region writable, and then write the data into
@@ -17,12 +22,57 @@ pub trait CodeManipulator {
     /// Never call this method when there are multi-threads running. Spawn threads after this method
     /// is called. This method may manipulate code region memory protection, and if other threads are
     /// executing codes in the same code page, it may lead to unexpected behaviors.
-    unsafe fn write_code<const L: usize>(addr: *mut core::ffi::c_void, data: &[u8; L]);
+    unsafe fn write_code<const L: usize>(
+        addr: *mut core::ffi::c_void,
+        data: &[u8; L],
+    ) -> Result<(), CodeManipulateError>;
+
+    /// Write each data in `patches` as code instruction to its address.
+    ///
+    /// The `patches` are sorted by address and never overlap. Implementations are encouraged to
+    /// manipulate the memory protection of each code page only once for all patches in that page.
+    /// If an error is returned, the patches may be partially written.
+    ///
+    /// The default implementation calls [`write_code`][Self::write_code] for each patch.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`write_code`][Self::write_code].
+    unsafe fn write_code_batch<const L: usize>(
+        patches: &[(*mut core::ffi::c_void, [u8; L])],
+    ) -> Result<(), CodeManipulateError> {
+        for (addr, data) in patches {
+            unsafe { Self::write_code(*addr, data)? };
+        }
+        Ok(())
+    }
+}
+
+/// Error occurred when manipulating code region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeManipulateError {
+    /// Name of the failed system call
+    pub syscall: &'static str,
+    /// Error code reported by the OS, such as `errno` on Linux. 0 if not available.
+    pub errno: i32,
 }
 
+impl core::fmt::Display for CodeManipulateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} failed with error code {}", self.syscall, self.errno)
+    }
+}
+
+impl core::error::Error for CodeManipulateError {}
+
 /// Dummy code manipulator. Do nothing. Used to declare a dummy static key which is never modified
 pub(crate) struct DummyCodeManipulator;
 
 impl CodeManipulator for DummyCodeManipulator {
-    unsafe fn write_code<const L: usize>(_addr: *mut core::ffi::c_void, _data: &[u8; L]) {}
+    unsafe fn write_code<const L: usize>(
+        _addr: *mut core::ffi::c_void,
+        _data: &[u8; L],
+    ) -> Result<(), CodeManipulateError> {
+        Ok(())
+    }
 }
@@ -0,0 +1,395 @@
+//! Configuration of named static keys at startup. Only available with `std` feature.
+//!
+//! The status of static keys can be set without recompiling by:
+//!
+//! * The [`FILE_ENV_VAR`] environment variable, which is the path of a configuration file. Each
+//!   line of the file is `name = value`, and a TOML-like `[prefix]` line prepends `prefix.` to the
+//!   names after it. `#` starts a comment.
+//! * The [`ENV_VAR`] environment variable, such as `STATIC_KEYS="net.trace=on,db.slow_log=off"`.
+//! * [`ARG`] command-line arguments, such as `--static-key net.trace=on` or
+//!   `--static-key=net.trace=on`.
+//!
+//! Values are `on`, `off`, `true`, `false`, `1` and `0`. Names are looked up in the
+//! [`registry`][crate::registry], and may be wildcard patterns accepted by
+//! [`Registry::matching`][crate::Registry::matching]. Names matching no static key are collected
+//! in [`Report::unknown`] instead of failing.
+//!
+//! Call [`init`] at the beginning of application to apply all of them in the above order, so later
+//! sources override earlier ones.
+
+use std::{
+    path::{Path, PathBuf},
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::StaticKeyError;
+
+/// Environment variable holding a comma-separated list of `name=value`
+pub const ENV_VAR: &str = "STATIC_KEYS";
+/// Environment variable holding the path of a configuration file
+pub const FILE_ENV_VAR: &str = "STATIC_KEYS_FILE";
+/// Command-line argument followed by `name=value`
+pub const ARG: &str = "--static-key";
+
+/// Error occurred when applying configuration.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// Failed to read the configuration file
+    Io {
+        /// Path of the configuration file
+        path: PathBuf,
+        /// Error reported by the OS
+        error: std::io::Error,
+    },
+    /// An item is not in the form of `name=value`
+    Syntax {
+        /// The malformed item
+        item: String,
+    },
+    /// The value of an item is not recognized
+    InvalidValue {
+        /// Name of the item
+        name: String,
+        /// The unrecognized value
+        value: String,
+    },
+    /// Failed to modify matched static keys. Items before it have been applied.
+    StaticKey {
+        /// Name of the item
+        name: String,
+        /// Error occurred when modifying static keys
+        error: StaticKeyError,
+    },
+}
+
+impl core::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io { path, error } => {
+                write!(f, "failed to read {}: {error}", path.display())
+            }
+            Self::Syntax { item } => write!(f, "expected `name=value`, found `{item}`"),
+            Self::InvalidValue { name, value } => {
+                write!(f, "invalid value `{value}` of static key {name}")
+            }
+            Self::StaticKey { name, error } => {
+                write!(f, "failed to set static key {name}: {error}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            Self::StaticKey { error, .. } => Some(error),
+            Self::Syntax { .. } | Self::InvalidValue { .. } => None,
+        }
+    }
+}
+
+/// Result of applying configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Count of static key modifications
+    applied: usize,
+    /// Names matching no static key
+    unknown: Vec<String>,
+}
+
+impl Report {
+    /// Count of static key modifications. A static key matched by several items is counted
+    /// several times.
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    /// Names matching no static key, in the order of appearance
+    pub fn unknown(&self) -> &[String] {
+        &self.unknown
+    }
+
+    /// Merge the report of configuration applied later
+    fn merge(&mut self, other: Report) {
+        self.applied += other.applied;
+        self.unknown.extend(other.unknown);
+    }
+}
+
+/// Parse the value of an item
+fn parse_value(name: &str, value: &str) -> Result<bool, ConfigError> {
+    match value {
+        "on" | "true" | "1" => Ok(true),
+        "off" | "false" | "0" => Ok(false),
+        _ => Err(ConfigError::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// Parse an item in the form of `name=value`
+fn parse_item(item: &str) -> Result<(String, bool), ConfigError> {
+    let Some((name, value)) = item.split_once('=') else {
+        return Err(ConfigError::Syntax {
+            item: item.to_string(),
+        });
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ConfigError::Syntax {
+            item: item.to_string(),
+        });
+    }
+    Ok((name.to_string(), parse_value(name, value.trim())?))
+}
+
+/// Parse a comma-separated list of `name=value`
+fn parse_list(config: &str) -> Result<Vec<(String, bool)>, ConfigError> {
+    config
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse_item)
+        .collect()
+}
+
+/// Remove the surrounding quotes if any
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// Remove the comment of a line, ignoring `#` inside quotes
+fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' if !in_quotes => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Parse the content of a configuration file
+fn parse_file(content: &str) -> Result<Vec<(String, bool)>, ConfigError> {
+    let mut items = Vec::new();
+    let mut prefix = String::new();
+    for line in content.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            prefix = unquote(section.trim()).to_string();
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(ConfigError::Syntax {
+                item: line.to_string(),
+            });
+        };
+        let name = unquote(name.trim());
+        if name.is_empty() {
+            return Err(ConfigError::Syntax {
+                item: line.to_string(),
+            });
+        }
+        let name = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            std::format!("{prefix}.{name}")
+        };
+        let enabled = parse_value(&name, unquote(value.trim()))?;
+        items.push((name, enabled));
+    }
+    Ok(items)
+}
+
+/// Parse the `name=value` following [`ARG`] in command-line arguments
+fn parse_args<I, S>(args: I) -> Result<Vec<(String, bool)>, ConfigError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut items = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.as_ref();
+        if arg == ARG {
+            let Some(item) = args.next() else {
+                return Err(ConfigError::Syntax {
+                    item: arg.to_string(),
+                });
+            };
+            items.extend(parse_list(item.as_ref())?);
+        } else if let Some(item) = arg
+            .strip_prefix(ARG)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            items.extend(parse_list(item)?);
+        }
+    }
+    Ok(items)
+}
+
+/// Set the status of static keys named `name`, or matching `name` if it is a wildcard pattern, and
+/// return the count of them
+///
+/// # Safety
+///
+/// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+unsafe fn set_named(name: &str, enabled: bool) -> Result<usize, StaticKeyError> {
+    let registry = crate::registry();
+    if name.contains(['*', '?']) {
+        return unsafe { registry.set_matching(name, enabled) };
+    }
+    let mut count = 0;
+    for key in registry.iter().filter(|key| key.name() == name) {
+        unsafe { key.try_set(enabled) }?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Set the status of static keys matching each item in order
+///
+/// # Safety
+///
+/// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+unsafe fn apply(items: Vec<(String, bool)>) -> Result<Report, ConfigError> {
+    let mut report = Report::default();
+    for (name, enabled) in items {
+        match unsafe { set_named(&name, enabled) } {
+            Ok(0) => report.unknown.push(name),
+            Ok(count) => report.applied += count,
+            Err(error) => return Err(ConfigError::StaticKey { name, error }),
+        }
+    }
+    Ok(report)
+}
+
+/// Apply a comma-separated list of `name=value`, such as `net.trace=on,db.slow_log=off`.
+///
+/// Nothing is modified if `config` is malformed.
+///
+/// # Safety
+///
+/// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+pub unsafe fn apply_str(config: &str) -> Result<Report, ConfigError> {
+    unsafe { apply(parse_list(config)?) }
+}
+
+/// Apply the [`ENV_VAR`] environment variable. Do nothing if it is not set.
+///
+/// # Safety
+///
+/// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+pub unsafe fn apply_env() -> Result<Report, ConfigError> {
+    match std::env::var(ENV_VAR) {
+        Ok(config) => unsafe { apply_str(&config) },
+        Err(_) => Ok(Report::default()),
+    }
+}
+
+/// Apply a configuration file. See [module-level documentation](self) for its format.
+///
+/// Nothing is modified if the file is malformed.
+///
+/// # Safety
+///
+/// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+pub unsafe fn apply_file(path: impl AsRef<Path>) -> Result<Report, ConfigError> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path).map_err(|error| ConfigError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    unsafe { apply(parse_file(&content)?) }
+}
+
+/// Apply [`ARG`] in command-line arguments. Other arguments are ignored.
+///
+/// Nothing is modified if any [`ARG`] is malformed.
+///
+/// # Safety
+///
+/// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+pub unsafe fn apply_args<I, S>(args: I) -> Result<Report, ConfigError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    unsafe { apply(parse_args(args)?) }
+}
+
+/// Call [`global_init`][crate::global_init], and then apply the configuration file in
+/// [`FILE_ENV_VAR`], the [`ENV_VAR`] environment variable, and [`ARG`] in command-line arguments in
+/// order.
+///
+/// Arguments which are not valid Unicode are ignored.
+///
+/// # Safety
+///
+/// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable]. This function should be called
+/// at the beginning of application, before spawning other threads.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::define_static_key_false;
+///
+/// define_static_key_false!(MY_STATIC_KEY, "my.feature");
+///
+/// fn main() {
+///     let report = unsafe { static_keys::config::init() }.unwrap();
+///     for name in report.unknown() {
+///         eprintln!("Unknown static key: {name}");
+///     }
+/// }
+/// ```
+pub unsafe fn init() -> Result<Report, ConfigError> {
+    crate::global_init();
+    let mut report = Report::default();
+    if let Some(path) = std::env::var_os(FILE_ENV_VAR) {
+        report.merge(unsafe { apply_file(path) }?);
+    }
+    report.merge(unsafe { apply_env() }?);
+    report.merge(unsafe {
+        apply_args(std::env::args_os().filter_map(|arg| arg.into_string().ok()))
+    }?);
+    Ok(report)
+}
+
+/// Current status of all named static keys as a comma-separated list of `name=value`, which can be
+/// applied by [`apply_str`].
+///
+/// Names of static keys never contain wildcards or separators, so each item is applied exactly to
+/// the static keys with that name.
+pub fn snapshot() -> String {
+    let mut config = String::new();
+    for key in crate::registry().iter() {
+        if !config.is_empty() {
+            config.push(',');
+        }
+        config.push_str(key.name());
+        config.push_str(if key.is_enabled() { "=on" } else { "=off" });
+    }
+    config
+}
+
+/// Set the [`ENV_VAR`] environment variable to [`snapshot`], so that child processes calling [`init`]
+/// start with the same status of static keys.
+///
+/// # Safety
+///
+/// See [`std::env::set_var`].
+pub unsafe fn export_env() {
+    unsafe { std::env::set_var(ENV_VAR, snapshot()) };
+}
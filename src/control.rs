@@ -0,0 +1,244 @@
+//! Control server to modify named static keys in running processes. Only available with `std`
+//! feature on Unix.
+//!
+//! [`serve`] listens on a Unix domain socket. Each line sent by a client is a command, and each
+//! command is answered by a line of JSON object, whose `ok` field tells whether it succeeded:
+//!
+//! | Command              | Response                                                               |
+//! |----------------------|------------------------------------------------------------------------|
+//! | `list`               | `{"ok":true,"keys":[{"name":"net.trace","enabled":true,"initial":false}]}` |
+//! | `get NAME`           | `{"ok":true,"key":{"name":"net.trace","enabled":true,"initial":false}}` |
+//! | `enable PATTERN`     | `{"ok":true,"matched":1}`                                              |
+//! | `disable PATTERN`    | `{"ok":true,"matched":1}`                                              |
+//! | `reset [PATTERN]`    | `{"ok":true,"matched":1}`, restoring the initial status of all matched static keys |
+//!
+//! Failed commands are answered by `{"ok":false,"error":"..."}`. `PATTERN` is a wildcard pattern
+//! accepted by [`Registry::matching`][crate::Registry::matching]. Use the `static-keys-ctl` binary
+//! of this crate to send commands from shell.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::patch_lock::PatchGuard;
+
+/// Append `s` to `out` as a JSON string
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => out.push_str(&std::format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// State of a named static key seen by commands
+pub(crate) struct KeyState {
+    /// Address of the static key
+    pub(crate) addr: usize,
+    /// Name of the static key
+    pub(crate) name: String,
+    /// Current status
+    pub(crate) enabled: bool,
+    /// Initial status
+    pub(crate) initial: bool,
+}
+
+/// Static keys controlled by commands, which may be in current process or another process
+pub(crate) trait ControlTarget {
+    /// All named static keys
+    fn keys(&self) -> Result<Vec<KeyState>, String>;
+
+    /// Set the status of the static key at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`serve`].
+    unsafe fn set(&self, addr: usize, enabled: bool) -> Result<(), String>;
+}
+
+/// Named static keys of current process
+struct LocalTarget;
+
+impl ControlTarget for LocalTarget {
+    fn keys(&self) -> Result<Vec<KeyState>, String> {
+        Ok(crate::registry()
+            .iter()
+            .map(|key| KeyState {
+                addr: key.addr(),
+                name: key.name().into(),
+                enabled: key.is_enabled(),
+                initial: key.initial_enabled(),
+            })
+            .collect())
+    }
+
+    unsafe fn set(&self, addr: usize, enabled: bool) -> Result<(), String> {
+        crate::ensure_global_init_timed().map_err(|err| err.to_string())?;
+        let _guard = PatchGuard::lock();
+        crate::expiry::cancel(addr);
+        // Named static keys are always modified by the default code manipulator. The S generic is
+        // useless here
+        unsafe {
+            crate::static_key_update_timed_locked::<crate::os::ArchCodeManipulator, true>(
+                addr, enabled,
+            )
+        }
+        .map_err(|err| err.to_string())
+    }
+}
+
+/// Append `key` to `out` as a JSON object
+fn push_json_key(out: &mut String, key: &KeyState) {
+    out.push_str("{\"name\":");
+    push_json_string(out, &key.name);
+    out.push_str(&std::format!(
+        ",\"enabled\":{},\"initial\":{}}}",
+        key.enabled,
+        key.initial
+    ));
+}
+
+/// Response of a failed command
+fn error_response(error: &str) -> String {
+    let mut out = String::from("{\"ok\":false,\"error\":");
+    push_json_string(&mut out, error);
+    out.push('}');
+    out
+}
+
+/// Set the status of all static keys whose name matches `pattern`. If `enabled` is `None`, the
+/// initial status is restored.
+///
+/// # Safety
+///
+/// See [`serve`].
+unsafe fn set_matching(
+    target: &impl ControlTarget,
+    pattern: &str,
+    enabled: Option<bool>,
+) -> Result<String, String> {
+    let mut matched = 0;
+    for key in target.keys()? {
+        if !crate::registry::wildcard_match(pattern, &key.name) {
+            continue;
+        }
+        let enabled = enabled.unwrap_or(key.initial);
+        unsafe { target.set(key.addr, enabled) }
+            .map_err(|err| std::format!("failed to set static key {}: {err}", key.name))?;
+        matched += 1;
+    }
+    if matched == 0 {
+        return Err(std::format!("no static key matches {pattern}"));
+    }
+    Ok(std::format!("{{\"ok\":true,\"matched\":{matched}}}"))
+}
+
+/// Execute a command on `target` and return its response
+///
+/// # Safety
+///
+/// See [`serve`].
+pub(crate) unsafe fn execute(target: &impl ControlTarget, line: &str) -> String {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let argument = words.next();
+    if words.next().is_some() {
+        return error_response("too many arguments");
+    }
+    let response = match (command, argument) {
+        ("list", None) => target.keys().map(|keys| {
+            let mut out = String::from("{\"ok\":true,\"keys\":[");
+            for (index, key) in keys.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                push_json_key(&mut out, key);
+            }
+            out.push_str("]}");
+            out
+        }),
+        ("get", Some(name)) => target.keys().and_then(|keys| {
+            let key = keys
+                .iter()
+                .find(|key| key.name == name)
+                .ok_or_else(|| std::format!("unknown static key {name}"))?;
+            let mut out = String::from("{\"ok\":true,\"key\":");
+            push_json_key(&mut out, key);
+            out.push('}');
+            Ok(out)
+        }),
+        ("enable", Some(pattern)) => unsafe { set_matching(target, pattern, Some(true)) },
+        ("disable", Some(pattern)) => unsafe { set_matching(target, pattern, Some(false)) },
+        ("reset", pattern) => unsafe { set_matching(target, pattern.unwrap_or("*"), None) },
+        ("list" | "get" | "enable" | "disable", _) => {
+            Err(std::format!("wrong arguments of {command}"))
+        }
+        _ => Err(std::format!("unknown command {command}")),
+    };
+    response.unwrap_or_else(|err| error_response(&err))
+}
+
+/// Answer commands of a client until it disconnects
+///
+/// # Safety
+///
+/// See [`serve`].
+unsafe fn handle_client(stream: UnixStream) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut response = unsafe { execute(&LocalTarget, &line) };
+        response.push('\n');
+        writer.write_all(response.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Listen on the Unix domain socket at `path`, and answer commands of clients to query and modify
+/// named static keys. See [module-level documentation](self) for the protocol.
+///
+/// Each client is served by a new thread. This function only returns if the socket cannot be
+/// created or accepting clients fails, so it is usually called in a dedicated thread. The socket
+/// file is not removed when returning.
+///
+/// On Linux, clients cannot modify static keys before [`global_init`][crate::global_init] is
+/// called. On other OSs, it is called before modifying instructions if it has not been called yet.
+///
+/// # Safety
+///
+/// On Linux, the instructions are modified in the same way as
+/// [`enable_live`][crate::GenericStaticKey::enable_live], so this function can be called while other
+/// threads are running. On other OSs, never let clients modify static keys when other threads may be
+/// executing codes in the same code page.
+///
+/// Anyone who can connect to the socket can modify static keys, so the socket should be placed
+/// in a directory only accessible by trusted users.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// std::thread::spawn(|| unsafe { static_keys::control::serve("/run/my-daemon/static-keys.sock") });
+/// ```
+pub unsafe fn serve(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let listener = UnixListener::bind(path)?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        std::thread::spawn(move || unsafe { handle_client(stream) });
+    }
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! Reference-counted static keys, modelled on `static_key_slow_inc` and `static_key_slow_dec` of
+//! Linux kernel.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{
+    GenericStaticKey, StaticKeyError, code_manipulate::CodeManipulator, patch_lock::PatchGuard,
+};
+
+/// Static key enabled as long as it is acquired by anyone.
+///
+/// Each [`acquire`][Self::acquire] increases the reference count, and dropping the returned
+/// [`CountedStaticKeyGuard`] decreases it. The instructions are only modified when the count
+/// changes between 0 and 1, so independent users can each turn the key on without knowing
+/// about each other, and it is turned off only when the last one releases it.
+///
+/// Like the kernel, a counted static key with initial status `true` starts with count 1. This
+/// initial reference is not held by any guard. Use [`take_initial`][Self::take_initial] to get a
+/// guard of it, and drop the guard to release it.
+///
+/// The layout begins with a [`GenericStaticKey`], so it can be used in [`static_branch_likely`]
+/// and [`static_branch_unlikely`] directly.
+///
+/// Counted static keys have no names and are not placed in the `__static_keys` section, since
+/// setting them by name would bypass the reference count. As a result, they cannot be found by
+/// [`registry`][crate::registry], `config`, `control`, the `static-keys-ctl` binary or the
+/// `static-keys-bake` binary.
+#[repr(C)]
+pub struct GenericCountedStaticKey<M: CodeManipulator, const S: bool> {
+    /// Underlying static key. Must be the first field, since jump entries record the address of
+    /// this struct as the address of static key.
+    key: GenericStaticKey<M, S>,
+    /// Reference count
+    count: AtomicUsize,
+    /// Whether the initial reference has been taken by [`take_initial`][Self::take_initial]
+    initial_taken: AtomicBool,
+}
+
+/// Counted static key to hold data about current status, reference count and which jump entries are
+/// associated with this key.
+pub type CountedStaticKey<const S: bool> =
+    GenericCountedStaticKey<crate::os::ArchCodeManipulator, S>;
+/// A [`CountedStaticKey`] with initial status `true`.
+pub type CountedStaticTrueKey = CountedStaticKey<true>;
+/// A [`CountedStaticKey`] with initial status `false`.
+pub type CountedStaticFalseKey = CountedStaticKey<false>;
+
+/// Guard of an acquired [`GenericCountedStaticKey`]. The reference is released when dropped.
+#[must_use = "the static key is released immediately if the guard is unused"]
+pub struct CountedStaticKeyGuard<'a, M: CodeManipulator, const S: bool> {
+    /// The acquired static key
+    key: &'a GenericCountedStaticKey<M, S>,
+}
+
+impl<M: CodeManipulator, const S: bool> GenericCountedStaticKey<M, S> {
+    /// Whether initial status is `true`
+    #[inline(always)]
+    pub const fn initial_enabled(&self) -> bool {
+        S
+    }
+
+    /// Create a new counted static key with initial status `S`
+    const fn new() -> Self {
+        Self {
+            key: GenericStaticKey::new(S),
+            count: AtomicUsize::new(S as usize),
+            initial_taken: AtomicBool::new(false),
+        }
+    }
+
+    /// Take the initial reference of a counted static key with initial status `true`, which is
+    /// released when the returned guard is dropped. The reference count is not changed.
+    ///
+    /// Return `None` if initial status is `false`, or the initial reference has been taken.
+    ///
+    /// # Safety
+    ///
+    /// See [`acquire`][Self::acquire].
+    pub unsafe fn take_initial(&self) -> Option<CountedStaticKeyGuard<'_, M, S>> {
+        if !S || self.initial_taken.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        Some(CountedStaticKeyGuard { key: self })
+    }
+
+    /// Increase the reference count, and enable this static key if it is the first reference.
+    ///
+    /// Modifications of static keys are serialized by a process-wide lock shared by all copies of this crate,
+    /// so this method can be called in parallel. If [`global_init`][crate::global_init] has not been called
+    /// yet, it is called before modifying instructions.
+    ///
+    /// # Safety
+    ///
+    /// Never call this method or drop the returned guard when other threads may be executing codes in the
+    /// same code page. This method may manipulate code region memory protection, and it may lead to unexpected
+    /// behaviors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the instructions cannot be modified. Use [`try_acquire`][Self::try_acquire] to handle
+    /// such error.
+    pub unsafe fn acquire(&self) -> CountedStaticKeyGuard<'_, M, S> {
+        match unsafe { self.try_acquire() } {
+            Ok(guard) => guard,
+            Err(err) => panic!("Failed to enable static key: {err}"),
+        }
+    }
+
+    /// Increase the reference count, and enable this static key if it is the first reference.
+    ///
+    /// Same as [`acquire`][Self::acquire], but return an error if the instructions cannot be modified. In
+    /// such case, the reference count is not changed.
+    ///
+    /// # Safety
+    ///
+    /// See [`acquire`][Self::acquire].
+    pub unsafe fn try_acquire(&self) -> Result<CountedStaticKeyGuard<'_, M, S>, StaticKeyError> {
+        // Fast path: the static key is already enabled
+        if self
+            .count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count > 0).then_some(count + 1)
+            })
+            .is_ok()
+        {
+            return Ok(CountedStaticKeyGuard { key: self });
+        }
+
+        crate::ensure_global_init()?;
+        let _guard = PatchGuard::lock();
+        if self.count.load(Ordering::Relaxed) == 0 {
+            // The count stays 0 while modifying, so no one takes the fast path
+            unsafe { crate::static_key_update_locked(&self.key, true) }?;
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        Ok(CountedStaticKeyGuard { key: self })
+    }
+
+    /// Decrease the reference count, and disable this static key if it is the last reference.
+    ///
+    /// # Safety
+    ///
+    /// See [`acquire`][Self::acquire].
+    unsafe fn release(&self) -> Result<(), StaticKeyError> {
+        // Fast path: the static key is still referenced by others
+        if self
+            .count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count > 1).then_some(count - 1)
+            })
+            .is_ok()
+        {
+            return Ok(());
+        }
+
+        let _guard = PatchGuard::lock();
+        if self.count.fetch_sub(1, Ordering::Relaxed) == 1 {
+            // The count stays 0 while modifying, so no one takes the fast path
+            if let Err(err) = unsafe { crate::static_key_update_locked(&self.key, false) } {
+                // Still enabled, keep the count consistent
+                self.count.fetch_add(1, Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Current reference count
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Get the current status of this static key
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_enabled()
+    }
+}
+
+impl<M: CodeManipulator, const S: bool> core::fmt::Debug for GenericCountedStaticKey<M, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("GenericCountedStaticKey")
+            .field("key", &self.key)
+            .field("count", &self.count())
+            .finish()
+    }
+}
+
+impl<M: CodeManipulator, const S: bool> Drop for CountedStaticKeyGuard<'_, M, S> {
+    /// Release the reference. The safety requirements of [`GenericCountedStaticKey::acquire`]
+    /// apply here as well.
+    ///
+    /// Panics if the static key should be disabled but the instructions cannot be modified. In such
+    /// case, the reference is kept.
+    fn drop(&mut self) {
+        if let Err(err) = unsafe { self.key.release() } {
+            panic!("Failed to disable static key: {err}");
+        }
+    }
+}
+
+/// Create a new counted static key with `false` as initial value.
+///
+/// This method should be called to initialize a static counted static key. It is UB to use this method
+/// to create a static key on stack or heap, and use this static key to control branches.
+///
+/// Use [`define_counted_static_key_false`] for short.
+pub const fn new_counted_static_false_key() -> CountedStaticFalseKey {
+    CountedStaticFalseKey::new()
+}
+
+/// Create a new counted static key with `true` as initial value.
+///
+/// This method should be called to initialize a static counted static key. It is UB to use this method
+/// to create a static key on stack or heap, and use this static key to control branches.
+///
+/// Use [`define_counted_static_key_true`] for short.
+pub const fn new_counted_static_true_key() -> CountedStaticTrueKey {
+    CountedStaticTrueKey::new()
+}
+
+/// Define a counted static key with `false` as initial value.
+///
+/// This macro will define a static variable without documentations and visibility modifiers.
+/// Use [`new_counted_static_false_key`] for customization. Unlike
+/// [`define_static_key_false`][crate::define_static_key_false], the key has no name. See
+/// [`GenericCountedStaticKey`] for details.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::{define_counted_static_key_false, static_branch_unlikely};
+///
+/// define_counted_static_key_false!(MY_COUNTED_STATIC_KEY);
+///
+/// fn main() {
+///     static_keys::global_init();
+///     let guard = unsafe { MY_COUNTED_STATIC_KEY.acquire() };
+///     assert!(static_branch_unlikely!(MY_COUNTED_STATIC_KEY));
+///     drop(guard);
+///     assert!(!static_branch_unlikely!(MY_COUNTED_STATIC_KEY));
+/// }
+/// ```
+#[macro_export]
+macro_rules! define_counted_static_key_false {
+    ($key: ident) => {
+        #[used]
+        static $key: $crate::CountedStaticFalseKey = $crate::new_counted_static_false_key();
+    };
+}
+
+/// Define a counted static key with `true` as initial value.
+///
+/// This macro will define a static variable without documentations and visibility modifiers.
+/// Use [`new_counted_static_true_key`] for customization. Unlike
+/// [`define_static_key_true`][crate::define_static_key_true], the key has no name. See
+/// [`GenericCountedStaticKey`] for details.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::define_counted_static_key_true;
+///
+/// define_counted_static_key_true!(MY_COUNTED_STATIC_KEY);
+/// ```
+#[macro_export]
+macro_rules! define_counted_static_key_true {
+    ($key: ident) => {
+        #[used]
+        static $key: $crate::CountedStaticTrueKey = $crate::new_counted_static_true_key();
+    };
+}
@@ -0,0 +1,450 @@
+//! Static keys backed by CPU features.
+//!
+//! Static keys defined by [`define_cpu_feature_key`][crate::define_cpu_feature_key] record their
+//! CPU features in the `__static_cpu_features` section. In [`global_init`][crate::global_init], every
+//! such static key is enabled if its CPU feature of the current architecture is available.
+
+use crate::{StaticFalseKey, os, patch_lock::PatchGuard};
+
+/// A CPU feature which can be named in [`define_cpu_feature_key`][crate::define_cpu_feature_key]
+#[cfg_attr(
+    any(
+        feature = "std",
+        not(any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            all(target_arch = "aarch64", target_os = "linux")
+        ))
+    ),
+    allow(unused)
+)]
+struct CpuFeature {
+    /// Name of the feature, which is the same as the one used in `std::arch`
+    name: &'static str,
+    /// Bits which must all be set if the feature is available, used without `std`. See
+    /// [`X86_FEATURES`] and [`AARCH64_FEATURES`] for the registers of each mask.
+    masks: [u64; 4],
+}
+
+/// Pad `masks` with zeros to the length of [`CpuFeature::masks`]
+const fn pad_masks<const N: usize>(masks: [u64; N]) -> [u64; 4] {
+    let mut padded = [0; 4];
+    let mut index = 0;
+    while index < N {
+        padded[index] = masks[index];
+        index += 1;
+    }
+    padded
+}
+
+/// Define the table of CPU features of an architecture, and the detection function used with `std`
+macro_rules! cpu_features {
+    (
+        $(#[$attr: meta])*
+        $table: ident,
+        #[cfg($arch: meta)]
+        $detect: ident => $detected: ident,
+        // The names are captured as `tt`, so that `std::arch` macros can match them
+        [$($name: tt => [$($mask: expr),* $(,)?]),* $(,)?]
+    ) => {
+        $(#[$attr])*
+        const $table: &[CpuFeature] = &[$(CpuFeature {
+            name: $name,
+            masks: pad_masks([$($mask),*]),
+        }),*];
+
+        /// Whether the CPU feature at `index` of the table is available, detected by `std::arch`
+        #[cfg(all(feature = "std", $arch))]
+        fn $detect(index: usize) -> bool {
+            let detected: &[fn() -> bool] = &[$(|| std::arch::$detected!($name)),*];
+            detected[index]()
+        }
+    };
+}
+
+/// `CPUID.01H:ECX.OSXSAVE`, set if the OS enables XSAVE, so that XCR0 can be read by XGETBV
+const OSXSAVE: u64 = 1 << (32 + 27);
+/// `CPUID.01H:ECX.XSAVE` and [`OSXSAVE`], required by all XSAVE instructions
+const XSAVE: u64 = 1 << (32 + 26) | OSXSAVE;
+/// XCR0 bits of SSE and AVX states, which must be enabled by the OS before using AVX
+const XCR0_AVX: u64 = 1 << 1 | 1 << 2;
+/// XCR0 bits of opmask and ZMM states in addition to [`XCR0_AVX`], which must be enabled by the OS
+/// before using AVX-512
+const XCR0_AVX512: u64 = XCR0_AVX | 1 << 5 | 1 << 6 | 1 << 7;
+
+// The conditions follow `is_x86_feature_detected` of std
+cpu_features! {
+    /// CPU features of x86 and x86_64. The masks are `CPUID.01H:ECX` and `CPUID.01H:EDX`,
+    /// `CPUID.(EAX=07H,ECX=0):ECX` and `CPUID.(EAX=07H,ECX=0):EBX`, `CPUID.80000001H:ECX` and
+    /// `CPUID.(EAX=0DH,ECX=1):EAX`, and XCR0 enabled by the OS, where the former register of each
+    /// pair is the high 32 bits.
+    X86_FEATURES,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    x86_detected => is_x86_feature_detected,
+    [
+        "aes" => [1 << 57],
+        "pclmulqdq" => [1 << 33],
+        "rdrand" => [1 << 62],
+        "rdseed" => [0, 1 << 18],
+        "tsc" => [1 << 4],
+        "mmx" => [1 << 23],
+        "sse" => [1 << 25],
+        "sse2" => [1 << 26],
+        "sse3" => [1 << 32],
+        "ssse3" => [1 << 41],
+        "sse4.1" => [1 << 51],
+        "sse4.2" => [1 << 52],
+        "sse4a" => [0, 0, 1 << (32 + 6)],
+        "sha" => [0, 1 << 29],
+        "avx" => [1 << 60 | OSXSAVE, 0, 0, XCR0_AVX],
+        "avx2" => [OSXSAVE, 1 << 5, 0, XCR0_AVX],
+        "avx512f" => [OSXSAVE, 1 << 16, 0, XCR0_AVX512],
+        "avx512cd" => [OSXSAVE, 1 << 28, 0, XCR0_AVX512],
+        "avx512bw" => [OSXSAVE, 1 << 30, 0, XCR0_AVX512],
+        "avx512dq" => [OSXSAVE, 1 << 17, 0, XCR0_AVX512],
+        "avx512vl" => [OSXSAVE, 1 << 31, 0, XCR0_AVX512],
+        "avx512ifma" => [OSXSAVE, 1 << 21, 0, XCR0_AVX512],
+        "avx512vbmi" => [OSXSAVE, 1 << (32 + 1), 0, XCR0_AVX512],
+        "avx512vpopcntdq" => [OSXSAVE, 1 << (32 + 14), 0, XCR0_AVX512],
+        "f16c" => [1 << 61 | OSXSAVE, 0, 0, XCR0_AVX],
+        "fma" => [1 << 44 | OSXSAVE, 0, 0, XCR0_AVX],
+        "bmi1" => [0, 1 << 3],
+        "bmi2" => [0, 1 << 8],
+        "abm" => [0, 0, 1 << (32 + 5)],
+        "lzcnt" => [0, 0, 1 << (32 + 5)],
+        "tbm" => [0, 0, 1 << (32 + 21)],
+        "popcnt" => [1 << 55],
+        "fxsr" => [1 << 24],
+        "xsave" => [XSAVE],
+        "xsaveopt" => [XSAVE, 0, 1 << 0],
+        "xsaves" => [XSAVE, 0, 1 << 3],
+        "xsavec" => [XSAVE, 0, 1 << 1],
+        "cmpxchg16b" => [1 << 45],
+        "adx" => [0, 1 << 19],
+        "movbe" => [1 << 54],
+    ]
+}
+
+// The bits are taken from arch/arm64/include/uapi/asm/hwcap.h of Linux kernel
+cpu_features! {
+    /// CPU features of aarch64. The masks are `AT_HWCAP` and `AT_HWCAP2`.
+    AARCH64_FEATURES,
+    #[cfg(target_arch = "aarch64")]
+    aarch64_detected => is_aarch64_feature_detected,
+    [
+        "fp" => [1 << 0],
+        "asimd" => [1 << 1],
+        "aes" => [1 << 3 | 1 << 4],
+        "sha2" => [1 << 5 | 1 << 6],
+        "crc" => [1 << 7],
+        "lse" => [1 << 8],
+        "fp16" => [1 << 9 | 1 << 10],
+        "rdm" => [1 << 12],
+        "jsconv" => [1 << 13],
+        "fcma" => [1 << 14],
+        "rcpc" => [1 << 15],
+        "dpb" => [1 << 16],
+        "sha3" => [1 << 17 | 1 << 21],
+        "sm4" => [1 << 18 | 1 << 19],
+        "dotprod" => [1 << 20],
+        "sve" => [1 << 22],
+        "fhm" => [1 << 23],
+        "dit" => [1 << 24],
+        "lse2" => [1 << 25],
+        "rcpc2" => [1 << 26],
+        "flagm" => [1 << 27],
+        "ssbs" => [1 << 28],
+        "sb" => [1 << 29],
+        "paca" => [1 << 30],
+        "pacg" => [1 << 31],
+        "dpb2" => [0, 1 << 0],
+        "sve2" => [0, 1 << 1],
+        "sve2-aes" => [0, 1 << 2 | 1 << 3],
+        "sve2-bitperm" => [0, 1 << 4],
+        "sve2-sha3" => [0, 1 << 5],
+        "sve2-sm4" => [0, 1 << 6],
+        "frintts" => [0, 1 << 8],
+        "f32mm" => [0, 1 << 10],
+        "f64mm" => [0, 1 << 11],
+        "i8mm" => [0, 1 << 13],
+        "bf16" => [0, 1 << 14],
+        "rand" => [0, 1 << 16],
+        "bti" => [0, 1 << 17],
+        "mte" => [0, 1 << 18],
+    ]
+}
+
+/// Index of features not available on the current architecture
+const NO_FEATURE: usize = usize::MAX;
+
+/// Whether `a` and `b` are the same string in const context
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut index = 0;
+    while index < a.len() {
+        if a[index] != b[index] {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
+
+/// Index of the CPU feature named `name` in `table`
+///
+/// # Panics
+///
+/// Panics if there is no such feature. Since it is called in const context, the panic is a
+/// compilation error.
+const fn feature_index(table: &[CpuFeature], name: &str) -> usize {
+    let mut index = 0;
+    while index < table.len() {
+        if str_eq(table[index].name, name) {
+            return index;
+        }
+        index += 1;
+    }
+    panic!("Unknown CPU feature");
+}
+
+/// Index of the only feature in `names`, or [`NO_FEATURE`] if `names` is empty
+const fn optional_feature_index(table: &[CpuFeature], names: &[&str]) -> usize {
+    match names {
+        [] => NO_FEATURE,
+        [name] => feature_index(table, name),
+        _ => panic!("Only one CPU feature is allowed for each architecture"),
+    }
+}
+
+/// Whether the CPU feature at `index` of the table of the current architecture is available
+#[cfg(feature = "std")]
+fn feature_detected(index: usize) -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    let detected = x86_detected(index);
+    #[cfg(target_arch = "aarch64")]
+    let detected = aarch64_detected(index);
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    let detected = {
+        let _ = index;
+        false
+    };
+    detected
+}
+
+// glibc replaces AT_HWCAP with its own bits on x86, so CPUID is used instead
+/// Whether the CPU feature at `index` of the table of the current architecture is available
+#[cfg(all(not(feature = "std"), any(target_arch = "x86", target_arch = "x86_64")))]
+fn feature_detected(index: usize) -> bool {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::{__cpuid, __cpuid_count, __get_cpuid_max, _xgetbv, CpuidResult};
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::{__cpuid, __cpuid_count, __get_cpuid_max, _xgetbv, CpuidResult};
+
+    /// Result of leaves not supported by the CPU
+    const NO_LEAF: CpuidResult = CpuidResult {
+        eax: 0,
+        ebx: 0,
+        ecx: 0,
+        edx: 0,
+    };
+
+    let feature = &X86_FEATURES[index];
+    // These functions are safe in newer Rust
+    #[allow(unused_unsafe)]
+    let bits = unsafe {
+        let max_leaf = __get_cpuid_max(0).0;
+        let leaf1 = __cpuid(1);
+        let leaf7 = if max_leaf >= 7 {
+            __cpuid_count(7, 0)
+        } else {
+            NO_LEAF
+        };
+        let xsave_leaf = if max_leaf >= 0xd {
+            __cpuid_count(0xd, 1)
+        } else {
+            NO_LEAF
+        };
+        let extended_leaf = if __get_cpuid_max(0x8000_0000).0 >= 0x8000_0001 {
+            __cpuid(0x8000_0001)
+        } else {
+            NO_LEAF
+        };
+        let leaf1_bits = ((leaf1.ecx as u64) << 32) | leaf1.edx as u64;
+        // XGETBV raises #UD unless the OS enables XSAVE
+        let xcr0 = if leaf1_bits & OSXSAVE != 0 {
+            _xgetbv(0)
+        } else {
+            0
+        };
+        [
+            leaf1_bits,
+            ((leaf7.ecx as u64) << 32) | leaf7.ebx as u64,
+            ((extended_leaf.ecx as u64) << 32) | xsave_leaf.eax as u64,
+            xcr0,
+        ]
+    };
+    bits.iter()
+        .zip(feature.masks)
+        .all(|(bits, mask)| bits & mask == mask)
+}
+
+/// Whether the CPU feature at `index` of the table of the current architecture is available
+#[cfg(all(not(feature = "std"), target_arch = "aarch64", target_os = "linux"))]
+fn feature_detected(index: usize) -> bool {
+    let feature = &AARCH64_FEATURES[index];
+    let hwcap = unsafe { libc::getauxval(libc::AT_HWCAP) } as u64;
+    let hwcap2 = unsafe { libc::getauxval(libc::AT_HWCAP2) } as u64;
+    [hwcap, hwcap2]
+        .iter()
+        .zip(feature.masks)
+        .all(|(bits, mask)| bits & mask == mask)
+}
+
+/// Whether the CPU feature at `index` of the table of the current architecture is available.
+/// Without `std`, CPU features can only be detected on x86 and on aarch64 Linux.
+#[cfg(all(
+    not(feature = "std"),
+    not(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_os = "linux")
+    ))
+))]
+fn feature_detected(_index: usize) -> bool {
+    false
+}
+
+/// Entries in the __static_cpu_features section, used to record the CPU feature of a static key.
+///
+/// Constructed by [`define_cpu_feature_key`][crate::define_cpu_feature_key]. Never use it directly.
+#[doc(hidden)]
+#[repr(C)]
+pub struct CpuFeatureEntry {
+    /// Address of the static key, or null for dummy entries
+    key: *const StaticFalseKey,
+    /// Index of the CPU feature in the table of the current architecture
+    feature: usize,
+}
+
+// The entry is never modified, and the static key is Sync
+unsafe impl Sync for CpuFeatureEntry {}
+
+impl CpuFeatureEntry {
+    /// Create an entry enabling `key` if the CPU feature is available. `x86` and `aarch64` are
+    /// the names of the CPU feature on each architecture, which contain at most one name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any name is unknown, even if it is not for the current architecture.
+    pub const fn new(key: &'static StaticFalseKey, x86: &[&str], aarch64: &[&str]) -> Self {
+        let x86 = optional_feature_index(X86_FEATURES, x86);
+        let aarch64 = optional_feature_index(AARCH64_FEATURES, aarch64);
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let feature = {
+            let _ = aarch64;
+            x86
+        };
+        #[cfg(target_arch = "aarch64")]
+        let feature = {
+            let _ = x86;
+            aarch64
+        };
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+        let feature = {
+            let _ = (x86, aarch64);
+            NO_FEATURE
+        };
+        Self { key, feature }
+    }
+
+    /// Create a dummy entry
+    pub(crate) const fn dummy() -> Self {
+        Self {
+            key: core::ptr::null(),
+            feature: NO_FEATURE,
+        }
+    }
+
+    /// Whether this entry is dummy
+    fn is_dummy(&self) -> bool {
+        self.key.is_null()
+    }
+}
+
+// Insert a dummy entry here, so that the section is always defined even if there is no CPU
+// feature key. See the comment of DUMMY_STATIC_KEY.
+#[used]
+#[unsafe(link_section = crate::os_cpu_feature_sec_name_attr!())]
+static DUMMY_CPU_FEATURE_ENTRY: CpuFeatureEntry = CpuFeatureEntry::dummy();
+
+/// Enable static keys in __static_cpu_features section whose CPU features are available. Called
+/// at the end of [`global_init`][crate::global_init].
+///
+/// # Panics
+///
+/// Panics if the instructions cannot be modified.
+pub(crate) fn global_init() {
+    let start_addr = &raw const os::CPU_FEATURE_ENTRY_START;
+    let stop_addr = &raw const os::CPU_FEATURE_ENTRY_STOP;
+    let entries = unsafe {
+        core::slice::from_raw_parts(start_addr, stop_addr.offset_from(start_addr) as usize)
+    };
+    let _guard = PatchGuard::lock();
+    for entry in entries {
+        if entry.is_dummy() || entry.feature == NO_FEATURE || !feature_detected(entry.feature) {
+            continue;
+        }
+        let key = unsafe { &*entry.key };
+        if let Err(err) = unsafe { crate::static_key_update_locked(key, true) } {
+            panic!("Failed to enable static key for CPU feature: {err}");
+        }
+    }
+}
+
+/// Define a static key with `false` as initial value, which is enabled in
+/// [`global_init`][crate::global_init] if the given CPU feature is available.
+///
+/// The CPU feature is given for each architecture as `x86: "name"` (used for both x86 and x86_64)
+/// and `aarch64: "name"`, in this order, and each of them can be omitted. The names are the same as
+/// [`is_x86_feature_detected!`](https://doc.rust-lang.org/std/arch/macro.is_x86_feature_detected.html)
+/// and [`is_aarch64_feature_detected!`](https://doc.rust-lang.org/std/arch/macro.is_aarch64_feature_detected.html),
+/// and unknown names fail to compile. On other architectures, the static key is never enabled.
+///
+/// With `std` feature, the CPU features are detected by `std::arch`. Otherwise, they are detected by
+/// `getauxval(AT_HWCAP)` and `getauxval(AT_HWCAP2)` on aarch64 Linux, and by `CPUID` on x86, where
+/// features needing support of OS, such as AVX and AVX-512, are only available if the OS enables
+/// their states in XCR0 as read by `XGETBV`. All features of aarch64 on other OSs are considered
+/// unavailable.
+///
+/// Like [`define_static_key_false`][crate::define_static_key_false], the static key is named by its
+/// module path and identifier in [`registry`][crate::registry], and it can still be modified later.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::{define_cpu_feature_key, static_branch_unlikely};
+///
+/// define_cpu_feature_key!(HAS_AES, x86: "aes", aarch64: "aes");
+///
+/// fn main() {
+///     static_keys::global_init();
+///     if static_branch_unlikely!(HAS_AES) {
+///         // Use AES instructions
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! define_cpu_feature_key {
+    ($key: ident $(, x86: $x86: literal)? $(, aarch64: $aarch64: literal)? $(,)?) => {
+        $crate::define_static_key_false!($key);
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = $crate::os_cpu_feature_sec_name_attr!())]
+            static CPU_FEATURE_ENTRY: $crate::CpuFeatureEntry =
+                $crate::CpuFeatureEntry::new(&$key, &[$($x86)?], &[$($aarch64)?]);
+        };
+    };
+}
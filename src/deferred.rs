@@ -0,0 +1,311 @@
+//! Deferred static keys, modelled on `static_key_deferred` of Linux kernel.
+//!
+//! Disabling a deferred static key only takes effect after its timeout, and enabling it again
+//! within the timeout cancels the pending disable, so a key oscillating quickly does not modify
+//! instructions for each transition. Enabling always takes effect immediately.
+//!
+//! Pending disables are applied by [`tick`]. With `std` feature, a background timer thread calls
+//! [`tick`] automatically. Otherwise, the application should call [`tick`] periodically with its
+//! own clock.
+
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    GenericStaticKey, StaticKeyError,
+    code_manipulate::{CodeManipulator, DummyCodeManipulator},
+    patch_lock::PatchGuard,
+};
+
+/// No disable is pending
+const NO_DEADLINE: u64 = u64::MAX;
+/// A disable is pending, and its deadline will be decided by next [`tick`]
+const UNSTAMPED_DEADLINE: u64 = u64::MAX - 1;
+
+/// Address of the first deferred static key which has ever been requested to disable. Only
+/// modified with [`PatchGuard`] held.
+static DEFERRED_KEYS: AtomicUsize = AtomicUsize::new(0);
+
+/// Static key whose disabling is deferred by a timeout.
+///
+/// See [module-level documentation](self) for details.
+///
+/// On Linux, the instructions are modified in the same way as [`enable_live`][GenericStaticKey::enable_live],
+/// since the pending disable may be applied at any time.
+///
+/// The layout begins with a [`GenericStaticKey`], so it can be used in [`static_branch_likely`][crate::static_branch_likely]
+/// and [`static_branch_unlikely`][crate::static_branch_unlikely] directly.
+#[repr(C)]
+pub struct GenericDeferredStaticKey<M: CodeManipulator, const S: bool> {
+    /// Underlying static key. Must be the first field, since jump entries record the address of
+    /// this struct as the address of static key.
+    key: GenericStaticKey<M, S>,
+    /// Timeout of disabling in nanoseconds
+    timeout_ns: AtomicU64,
+    /// Deadline of pending disable in nanoseconds, or [`NO_DEADLINE`] and [`UNSTAMPED_DEADLINE`].
+    /// Only modified with [`PatchGuard`] held.
+    deadline_ns: AtomicU64,
+    /// Address of next deferred static key in [`DEFERRED_KEYS`]. Only modified with [`PatchGuard`] held.
+    next: AtomicUsize,
+    /// Whether this key is in [`DEFERRED_KEYS`]. Only modified with [`PatchGuard`] held.
+    registered: AtomicBool,
+    /// [`static_key_update_timed_locked`][crate::static_key_update_timed_locked] for `M` and `S`,
+    /// since [`tick`] does not know them
+    update: unsafe fn(usize, bool) -> Result<(), StaticKeyError>,
+}
+
+/// Deferred static key to hold data about current status, timeout and which jump entries are
+/// associated with this key.
+pub type DeferredStaticKey<const S: bool> =
+    GenericDeferredStaticKey<crate::os::ArchCodeManipulator, S>;
+/// A [`DeferredStaticKey`] with initial status `true`.
+pub type DeferredStaticTrueKey = DeferredStaticKey<true>;
+/// A [`DeferredStaticKey`] with initial status `false`.
+pub type DeferredStaticFalseKey = DeferredStaticKey<false>;
+
+/// Convert duration to nanoseconds, saturating to the largest valid deadline
+const fn duration_to_ns(duration: Duration) -> u64 {
+    let ns = duration.as_nanos();
+    if ns >= UNSTAMPED_DEADLINE as u128 {
+        UNSTAMPED_DEADLINE - 1
+    } else {
+        ns as u64
+    }
+}
+
+impl<M: CodeManipulator, const S: bool> GenericDeferredStaticKey<M, S> {
+    /// Whether initial status is `true`
+    #[inline(always)]
+    pub const fn initial_enabled(&self) -> bool {
+        S
+    }
+
+    /// Create a new deferred static key with initial status `S`
+    const fn new(timeout: Duration) -> Self {
+        Self {
+            key: GenericStaticKey::new(S),
+            timeout_ns: AtomicU64::new(duration_to_ns(timeout)),
+            deadline_ns: AtomicU64::new(NO_DEADLINE),
+            next: AtomicUsize::new(0),
+            registered: AtomicBool::new(false),
+            update: crate::static_key_update_timed_locked::<M, S>,
+        }
+    }
+
+    /// Timeout of disabling
+    pub fn timeout(&self) -> Duration {
+        Duration::from_nanos(self.timeout_ns.load(Ordering::Relaxed))
+    }
+
+    /// Set timeout of disabling. Pending disables whose deadline has been decided are not affected.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.timeout_ns
+            .store(duration_to_ns(timeout), Ordering::Relaxed);
+    }
+
+    /// Enable this static key immediately, and cancel the pending disable if any.
+    ///
+    /// On Linux, [`global_init`][crate::global_init] must have been called before. On other OSs, it is
+    /// called before modifying instructions if it has not been called yet.
+    ///
+    /// # Safety
+    ///
+    /// On Linux, the instructions are modified live, so this method can be called while other threads
+    /// are running. On other OSs, never call this method when other threads may be executing codes in
+    /// the same code page.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`global_init`][crate::global_init] has not been called on Linux, or the
+    /// instructions cannot be modified. Use [`try_enable`][Self::try_enable] to handle
+    /// such error.
+    pub unsafe fn enable(&self) {
+        if let Err(err) = unsafe { self.try_enable() } {
+            panic!("Failed to enable static key: {err}");
+        }
+    }
+
+    /// Enable this static key immediately, and cancel the pending disable if any.
+    ///
+    /// Same as [`enable`][Self::enable], but return an error if the instructions cannot be modified.
+    ///
+    /// # Safety
+    ///
+    /// See [`enable`][Self::enable].
+    pub unsafe fn try_enable(&self) -> Result<(), StaticKeyError> {
+        crate::ensure_global_init_timed()?;
+        let _guard = PatchGuard::lock();
+        self.deadline_ns.store(NO_DEADLINE, Ordering::Relaxed);
+        unsafe { (self.update)(self as *const _ as usize, true) }
+    }
+
+    /// Request to disable this static key after its timeout. Do nothing if current static key is
+    /// already disabled or a disable is already pending, so requests within the timeout are merged.
+    ///
+    /// # Safety
+    ///
+    /// The instructions are modified by [`tick`] later. On Linux, the instructions are modified live,
+    /// so this method can be called while other threads are running. On other OSs, never let [`tick`]
+    /// modify the instructions when other threads may be executing codes in the same code page.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`global_init`][crate::global_init] has not been called on Linux.
+    pub unsafe fn disable(&'static self) {
+        if let Err(err) = crate::ensure_global_init_timed() {
+            panic!("Failed to disable static key: {err}");
+        }
+        {
+            let _guard = PatchGuard::lock();
+            if !self.key.is_enabled() || self.deadline_ns.load(Ordering::Relaxed) != NO_DEADLINE {
+                return;
+            }
+            self.deadline_ns
+                .store(UNSTAMPED_DEADLINE, Ordering::Relaxed);
+            if !self.registered.load(Ordering::Relaxed) {
+                self.next
+                    .store(DEFERRED_KEYS.load(Ordering::Relaxed), Ordering::Relaxed);
+                DEFERRED_KEYS.store(self as *const _ as usize, Ordering::Relaxed);
+                self.registered.store(true, Ordering::Relaxed);
+            }
+        }
+        #[cfg(feature = "std")]
+        crate::timer::wake();
+    }
+
+    /// Whether a disable is pending
+    pub fn is_disable_pending(&self) -> bool {
+        self.deadline_ns.load(Ordering::Relaxed) != NO_DEADLINE
+    }
+
+    /// Get the current status of this static key. It is still `true` when a disable is pending.
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_enabled()
+    }
+}
+
+impl<M: CodeManipulator, const S: bool> core::fmt::Debug for GenericDeferredStaticKey<M, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("GenericDeferredStaticKey")
+            .field("key", &self.key)
+            .field("timeout", &self.timeout())
+            .field("disable_pending", &self.is_disable_pending())
+            .finish()
+    }
+}
+
+/// Apply pending disables of deferred static keys whose deadline has passed, and return the
+/// earliest deadline of remaining pending disables.
+///
+/// `now` is the time elapsed since an arbitrary epoch chosen by the application, which should be
+/// monotonic. The deadline of a disable is decided by the first call after the disable is
+/// requested, as `now` plus the timeout of the key, and the returned deadline uses the same epoch.
+///
+/// With `std` feature, a background timer thread calls this function, and the application should
+/// never call it.
+///
+/// If the instructions of some static keys cannot be modified, they stay enabled with a rescheduled
+/// pending disable, and the first error is returned after handling all keys.
+///
+/// # Safety
+///
+/// On Linux, the instructions are modified live, so this function can be called while other threads
+/// are running. On other OSs, never call this function when other threads may be executing codes in
+/// the same code page.
+pub unsafe fn tick(now: Duration) -> Result<Option<Duration>, StaticKeyError> {
+    let now_ns = duration_to_ns(now);
+    let _guard = PatchGuard::lock();
+    let mut next_deadline = NO_DEADLINE;
+    let mut result = Ok(());
+    let mut key_addr = DEFERRED_KEYS.load(Ordering::Relaxed);
+    while key_addr != 0 {
+        // The M and S generic is useless here
+        let key =
+            unsafe { &*(key_addr as *const GenericDeferredStaticKey<DummyCodeManipulator, true>) };
+        let mut deadline = key.deadline_ns.load(Ordering::Relaxed);
+        if deadline == UNSTAMPED_DEADLINE {
+            deadline = now_ns
+                .saturating_add(key.timeout_ns.load(Ordering::Relaxed))
+                .min(UNSTAMPED_DEADLINE - 1);
+            key.deadline_ns.store(deadline, Ordering::Relaxed);
+        }
+        if deadline <= now_ns {
+            key.deadline_ns.store(NO_DEADLINE, Ordering::Relaxed);
+            if let Err(err) = unsafe { (key.update)(key_addr, false) } {
+                // Retry after another timeout
+                key.deadline_ns.store(UNSTAMPED_DEADLINE, Ordering::Relaxed);
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        } else if deadline != NO_DEADLINE {
+            next_deadline = next_deadline.min(deadline);
+        }
+        key_addr = key.next.load(Ordering::Relaxed);
+    }
+    result?;
+    Ok((next_deadline != NO_DEADLINE).then(|| Duration::from_nanos(next_deadline)))
+}
+
+/// Create a new deferred static key with `false` as initial value and given timeout of disabling.
+///
+/// This method should be called to initialize a static deferred static key. It is UB to use this method
+/// to create a static key on stack or heap, and use this static key to control branches.
+///
+/// Use [`define_deferred_static_key_false`] for short.
+pub const fn new_deferred_static_false_key(timeout: Duration) -> DeferredStaticFalseKey {
+    DeferredStaticFalseKey::new(timeout)
+}
+
+/// Create a new deferred static key with `true` as initial value and given timeout of disabling.
+///
+/// This method should be called to initialize a static deferred static key. It is UB to use this method
+/// to create a static key on stack or heap, and use this static key to control branches.
+///
+/// Use [`define_deferred_static_key_true`] for short.
+pub const fn new_deferred_static_true_key(timeout: Duration) -> DeferredStaticTrueKey {
+    DeferredStaticTrueKey::new(timeout)
+}
+
+/// Define a deferred static key with `false` as initial value and given timeout of disabling.
+///
+/// This macro will define a static variable without documentations and visibility modifiers.
+/// Use [`new_deferred_static_false_key`] for customization.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::define_deferred_static_key_false;
+///
+/// define_deferred_static_key_false!(MY_DEFERRED_STATIC_KEY, core::time::Duration::from_secs(1));
+/// ```
+#[macro_export]
+macro_rules! define_deferred_static_key_false {
+    ($key: ident, $timeout: expr) => {
+        #[used]
+        static $key: $crate::DeferredStaticFalseKey =
+            $crate::new_deferred_static_false_key($timeout);
+    };
+}
+
+/// Define a deferred static key with `true` as initial value and given timeout of disabling.
+///
+/// This macro will define a static variable without documentations and visibility modifiers.
+/// Use [`new_deferred_static_true_key`] for customization.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::define_deferred_static_key_true;
+///
+/// define_deferred_static_key_true!(MY_DEFERRED_STATIC_KEY, core::time::Duration::from_secs(1));
+/// ```
+#[macro_export]
+macro_rules! define_deferred_static_key_true {
+    ($key: ident, $timeout: expr) => {
+        #[used]
+        static $key: $crate::DeferredStaticTrueKey = $crate::new_deferred_static_true_key($timeout);
+    };
+}
@@ -0,0 +1,470 @@
+//! Offline inspection and modification of static keys in ELF files. Only available with `std`
+//! feature.
+//!
+//! [`Image`] parses the `__static_keys` section of an executable or shared object built for
+//! current architecture in the same way as [`global_init`][crate::global_init], without loading it.
+//! It can be used before deployment to check that a release build contains the expected static keys,
+//! and that none of their branch sites is dropped by LTO or `--gc-sections`. Use the
+//! `static-keys-dump` binary of this crate to print them from shell.
+//!
+//! [`Image::set_matching`] pre-bakes the default status of static keys into the file, so that
+//! different deployments of the same build can start with different status without modifying any
+//! instruction at runtime. Use the `static-keys-bake` binary of this crate to do it from shell.
+//!
+//! Addresses are virtual addresses in the file, i.e., as if the file is loaded at address 0.
+
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    string::String,
+    vec::Vec,
+};
+
+use crate::{
+    GenericStaticKey, InstructionKind, JumpEntry,
+    arch::ARCH_JUMP_INS_LENGTH,
+    code_manipulate::DummyCodeManipulator,
+    elf::{Elf, STT_FUNC, STT_OBJECT, Symbol},
+};
+
+/// Name of the section storing jump entries
+const JUMP_ENTRY_SECTION: &str = "__static_keys";
+/// Name of the section storing names of static keys
+const KEY_NAME_SECTION: &str = "__static_key_names";
+/// Demangled symbol of the dummy static key used by this crate itself
+const DUMMY_STATIC_KEY_SYMBOL: &str = "static_keys::DUMMY_STATIC_KEY";
+/// Count of bytes preceding the instruction at a jump entry, which may be read by the encoders of
+/// far JMP instructions
+const PRECEDING_LENGTH: usize = 4;
+/// Size of a word
+const WORD: usize = core::mem::size_of::<usize>();
+/// Offset of the status in a static key
+const ENABLED_OFFSET: usize =
+    core::mem::offset_of!(GenericStaticKey<DummyCodeManipulator, true>, enabled);
+/// Offset of the initial status in a static key
+const INITIAL_OFFSET: usize =
+    core::mem::offset_of!(GenericStaticKey<DummyCodeManipulator, true>, initial);
+
+/// Error occurred when inspecting an ELF file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DumpError {
+    /// Failed to read the file
+    Io {
+        /// Path of the file
+        path: PathBuf,
+        /// Error reported by the OS
+        error: std::io::Error,
+    },
+    /// The file is not an ELF file of current architecture
+    InvalidElf(&'static str),
+    /// The file does not use static keys
+    NoStaticKeys,
+    /// The static key is zero-initialized, i.e., it occupies no space in file, and thus cannot be
+    /// modified. Only static keys defined by
+    /// [`define_static_key_false`][crate::define_static_key_false] and
+    /// [`define_static_key_true`][crate::define_static_key_true] are always placed in a data
+    /// section.
+    ZeroInitialized {
+        /// Name, symbol or address of the static key
+        key: String,
+    },
+    /// The instruction at a jump entry matches neither status of its static key
+    UnexpectedInstruction {
+        /// Address of the instruction
+        site: usize,
+    },
+}
+
+impl core::fmt::Display for DumpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "failed to read {}: {error}", path.display()),
+            Self::InvalidElf(reason) => write!(f, "invalid ELF file: {reason}"),
+            Self::NoStaticKeys => write!(f, "the file does not use static keys"),
+            Self::ZeroInitialized { key } => {
+                write!(
+                    f,
+                    "static key {key} is zero-initialized and cannot be modified"
+                )
+            }
+            Self::UnexpectedInstruction { site } => {
+                write!(f, "unexpected instruction at {site:#x}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for DumpError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// A static key found in an ELF file, yielded by [`Image::keys`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageKey {
+    /// Address of the static key
+    addr: usize,
+    /// Demangled symbol of the static key, if not stripped
+    symbol: Option<String>,
+    /// Name of the static key, if recorded
+    name: Option<String>,
+    /// Initial status
+    initial: bool,
+    /// Branch sites sorted by address
+    sites: Vec<ImageSite>,
+}
+
+impl ImageKey {
+    /// Address of the static key
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Demangled symbol of the static key, such as `my_crate::MY_STATIC_KEY`, if the file is not
+    /// stripped
+    pub fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    /// Name of the static key, if it is defined by
+    /// [`define_static_key_false`][crate::define_static_key_false] or
+    /// [`define_static_key_true`][crate::define_static_key_true]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Initial status of the static key
+    pub fn initial_enabled(&self) -> bool {
+        self.initial
+    }
+
+    /// Branch sites associated with the static key, sorted by address
+    pub fn sites(&self) -> &[ImageSite] {
+        &self.sites
+    }
+}
+
+/// A branch site found in an ELF file, yielded by [`ImageKey::sites`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageSite {
+    /// Address of the JMP/NOP instruction
+    code_addr: usize,
+    /// Address of the JMP destination
+    target_addr: usize,
+    /// Demangled symbol of the function containing the site, and the offset in it
+    function: Option<(String, usize)>,
+    /// Whether the likely branch is true branch
+    likely_branch_is_true: bool,
+    /// Whether the site is generated by far variants
+    is_far: bool,
+    /// Kind of the instruction in the file
+    instruction: InstructionKind,
+}
+
+impl ImageSite {
+    /// Address of the JMP/NOP instruction
+    pub fn code_addr(&self) -> usize {
+        self.code_addr
+    }
+
+    /// Address of the JMP destination, which is the start of the unlikely branch
+    pub fn target_addr(&self) -> usize {
+        self.target_addr
+    }
+
+    /// Demangled symbol of the function containing the site, and the offset of the site in it, if
+    /// the file is not stripped. Sites in inlined functions are reported in their callers.
+    pub fn function(&self) -> Option<(&str, usize)> {
+        self.function
+            .as_ref()
+            .map(|(function, offset)| (function.as_str(), *offset))
+    }
+
+    /// Whether the likely branch is true branch, i.e., the site is generated by
+    /// [`static_branch_likely`][crate::static_branch_likely] or its variants
+    pub fn likely_branch_is_true(&self) -> bool {
+        self.likely_branch_is_true
+    }
+
+    /// Whether the site is generated by [`static_branch_likely_far`][crate::static_branch_likely_far]
+    /// or [`static_branch_unlikely_far`][crate::static_branch_unlikely_far]
+    pub fn is_far(&self) -> bool {
+        self.is_far
+    }
+
+    /// Kind of the instruction in the file, which is decided by the initial status of the static
+    /// key
+    pub fn instruction(&self) -> InstructionKind {
+        self.instruction
+    }
+}
+
+/// The instruction at a jump entry in the file
+struct FileInstruction<'a> {
+    /// The instruction and its preceding bytes
+    bytes: Cow<'a, [u8]>,
+    /// The jump entry rebased onto `bytes`.
+    ///
+    /// Encoders of far JMP instructions on some architectures read the preceding instruction at the
+    /// address of the jump entry, so the jump entry is rebased to let them read the file instead of
+    /// the memory of current process. Relative addresses are kept unchanged.
+    rebased: JumpEntry,
+}
+
+impl FileInstruction<'_> {
+    /// The instruction in the file
+    fn current(&self) -> &[u8] {
+        &self.bytes[PRECEDING_LENGTH..]
+    }
+
+    /// The instruction when the static key has given status
+    fn expected(&self, enabled: bool) -> [u8; ARCH_JUMP_INS_LENGTH] {
+        crate::jump_entry_instruction(&self.rebased, enabled)
+    }
+
+    /// Kind of the instruction in the file
+    fn kind(&self) -> InstructionKind {
+        InstructionKind::of(&self.rebased, self.current())
+    }
+}
+
+/// An ELF executable or shared object using static keys.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// use static_keys::dump::Image;
+///
+/// let image = Image::open("target/release/my-app").unwrap();
+/// for key in image.keys() {
+///     println!("{:?}: {} sites", key.name(), key.sites().len());
+/// }
+///
+/// let mut image = image;
+/// image.set_matching("net.*", true).unwrap();
+/// std::fs::write("target/release/my-app-traced", image.data()).unwrap();
+/// ```
+pub struct Image {
+    /// The parsed file
+    elf: Elf,
+    /// Symbols in the file
+    symbols: Vec<Symbol>,
+}
+
+impl Image {
+    /// Read and parse the ELF file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DumpError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|error| DumpError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::parse(data)
+    }
+
+    /// Parse the content of an ELF file.
+    pub fn parse(data: Vec<u8>) -> Result<Self, DumpError> {
+        let elf = Elf::parse(data).map_err(DumpError::InvalidElf)?;
+        if elf.section(JUMP_ENTRY_SECTION).is_none() {
+            return Err(DumpError::NoStaticKeys);
+        }
+        let symbols = elf.symbols();
+        Ok(Self { elf, symbols })
+    }
+
+    /// Demangled symbol of the object at `addr`
+    fn object_symbol(&self, addr: usize) -> Option<String> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.kind == STT_OBJECT && symbol.value == addr)
+            .map(|symbol| crate::elf::demangle(&symbol.name))
+    }
+
+    /// Demangled symbol of the function containing `addr`, and the offset of `addr` in it
+    fn function_symbol(&self, addr: usize) -> Option<(String, usize)> {
+        self.symbols
+            .iter()
+            .find(|symbol| {
+                symbol.kind == STT_FUNC && symbol.value <= addr && addr - symbol.value < symbol.size
+            })
+            .map(|symbol| (crate::elf::demangle(&symbol.name), addr - symbol.value))
+    }
+
+    /// The instruction at given jump entry in the file
+    fn instruction(&self, jump_entry: &JumpEntry) -> Option<FileInstruction<'_>> {
+        let start = jump_entry.code_addr().checked_sub(PRECEDING_LENGTH)?;
+        let bytes = self
+            .elf
+            .read_at(start, PRECEDING_LENGTH + ARCH_JUMP_INS_LENGTH)?;
+        let code = bytes.as_ptr() as usize + PRECEDING_LENGTH;
+        let rebased = JumpEntry {
+            code,
+            target: code.wrapping_add(
+                jump_entry
+                    .target_addr()
+                    .wrapping_sub(jump_entry.code_addr()),
+            ),
+            key: jump_entry.key,
+        };
+        Some(FileInstruction { bytes, rebased })
+    }
+
+    /// Branch site of given jump entry
+    fn site(&self, jump_entry: &JumpEntry) -> ImageSite {
+        let instruction = self.instruction(jump_entry);
+        ImageSite {
+            code_addr: jump_entry.code_addr(),
+            target_addr: jump_entry.target_addr(),
+            function: self.function_symbol(jump_entry.code_addr()),
+            likely_branch_is_true: jump_entry.likely_branch_is_true(),
+            is_far: jump_entry.is_far(),
+            instruction: instruction
+                .map_or(InstructionKind::Unknown, |instruction| instruction.kind()),
+        }
+    }
+
+    /// Static key addresses and names recorded in the companion section
+    fn names(&self) -> Vec<(usize, String)> {
+        let Some(section) = self.elf.section(KEY_NAME_SECTION) else {
+            return Vec::new();
+        };
+        (section.addr..section.addr + section.size)
+            .step_by(3 * WORD)
+            .filter_map(|entry_addr| {
+                let key_addr = self.elf.read_relocated_word(entry_addr)?;
+                let name_addr = self.elf.read_relocated_word(entry_addr + WORD)?;
+                let name_len = self.elf.read_relocated_word(entry_addr + 2 * WORD)?;
+                if key_addr == 0 {
+                    return None;
+                }
+                let name = self.elf.read_at(name_addr, name_len)?;
+                Some((key_addr, String::from_utf8_lossy(&name).into_owned()))
+            })
+            .collect()
+    }
+
+    /// Non-dummy jump entries with absolute addresses, sorted by key address
+    fn jump_entries(&self) -> Vec<JumpEntry> {
+        let section = self
+            .elf
+            .section(JUMP_ENTRY_SECTION)
+            .expect("__static_keys section is checked when parsing");
+        let data = self.elf.section_data(section).unwrap_or_default();
+        crate::elf::decode_jump_entries(data, section.addr, true)
+    }
+
+    /// All static keys used by any branch or named, sorted by address.
+    ///
+    /// A named static key without sites means all its branches are dropped by the compiler or
+    /// linker.
+    pub fn keys(&self) -> Vec<ImageKey> {
+        let jump_entries = self.jump_entries();
+        let names = self.names();
+
+        let mut addrs = jump_entries
+            .iter()
+            .map(|jump_entry| jump_entry.key_addr())
+            .chain(names.iter().map(|(addr, _)| *addr))
+            .collect::<Vec<_>>();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+            .into_iter()
+            .map(|addr| {
+                let initial = self
+                    .elf
+                    .read_at(addr + INITIAL_OFFSET, 1)
+                    .is_some_and(|initial| initial[0] != 0);
+                let sites = jump_entries
+                    .iter()
+                    .filter(|jump_entry| jump_entry.key_addr() == addr)
+                    .map(|jump_entry| self.site(jump_entry))
+                    .collect();
+                ImageKey {
+                    addr,
+                    symbol: self.object_symbol(addr),
+                    name: names
+                        .iter()
+                        .find(|(key_addr, _)| *key_addr == addr)
+                        .map(|(_, name)| name.clone()),
+                    initial,
+                    sites,
+                }
+            })
+            .filter(|key| key.symbol.as_deref() != Some(DUMMY_STATIC_KEY_SYMBOL))
+            .collect()
+    }
+
+    /// Set the default status of all static keys whose name or symbol matches `pattern` in the
+    /// file, and return the count of matched static keys. See
+    /// [`Registry::matching`][crate::Registry::matching] for the syntax of `pattern`.
+    ///
+    /// The instructions of branch sites are modified as if the static keys are enabled or disabled
+    /// at runtime, and the status and initial status of the static keys are updated, so processes
+    /// started from the modified file need no modification at runtime. The compile-time initial
+    /// status, i.e., [`initial_enabled`][crate::GenericStaticKey::initial_enabled], is unchanged.
+    ///
+    /// Nothing is modified if any matched static key is zero-initialized, or any instruction is
+    /// unexpected. Use [`data`][Self::data] to get the modified file.
+    pub fn set_matching(&mut self, pattern: &str, enabled: bool) -> Result<usize, DumpError> {
+        let keys = self
+            .keys()
+            .into_iter()
+            .filter(|key| {
+                key.name()
+                    .into_iter()
+                    .chain(key.symbol())
+                    .any(|name| crate::registry::wildcard_match(pattern, name))
+            })
+            .collect::<Vec<_>>();
+        let jump_entries = self.jump_entries();
+        let key_size = core::mem::size_of::<GenericStaticKey<DummyCodeManipulator, true>>();
+        let mut patches = Vec::new();
+        for key in &keys {
+            if !self.elf.is_in_file(key.addr, key_size) {
+                let key = key
+                    .name()
+                    .or(key.symbol())
+                    .map_or_else(|| std::format!("{:#x}", key.addr), String::from);
+                return Err(DumpError::ZeroInitialized { key });
+            }
+            let current = self
+                .elf
+                .read_at(key.addr + ENABLED_OFFSET, 1)
+                .is_some_and(|current| current[0] != 0);
+            if current != enabled {
+                for jump_entry in jump_entries
+                    .iter()
+                    .filter(|jump_entry| jump_entry.key_addr() == key.addr)
+                {
+                    let site = jump_entry.code_addr();
+                    let instruction = self
+                        .instruction(jump_entry)
+                        .ok_or(DumpError::UnexpectedInstruction { site })?;
+                    if instruction.current() != instruction.expected(current) {
+                        return Err(DumpError::UnexpectedInstruction { site });
+                    }
+                    patches.push((site, instruction.expected(enabled).to_vec()));
+                }
+            }
+            patches.push((key.addr + ENABLED_OFFSET, std::vec![enabled as u8]));
+            patches.push((key.addr + INITIAL_OFFSET, std::vec![enabled as u8]));
+        }
+        for (addr, bytes) in patches {
+            self.elf
+                .write_at(addr, &bytes)
+                .expect("patched addresses are checked before");
+        }
+        Ok(keys.len())
+    }
+
+    /// Content of the file, including modifications by [`set_matching`][Self::set_matching]
+    pub fn data(&self) -> &[u8] {
+        self.elf.data()
+    }
+}
@@ -0,0 +1,474 @@
+//! Minimal parser of ELF files of current architecture.
+//!
+//! Only the ELF class and byte order of current architecture are accepted, since the contents of
+//! `__static_keys` section are decoded as [`JumpEntry`][crate::JumpEntry] of current architecture.
+
+use std::{borrow::Cow, string::String, vec::Vec};
+
+use crate::JumpEntry;
+
+/// `e_ident[EI_CLASS]` of current architecture
+#[cfg(target_pointer_width = "64")]
+const ELF_CLASS: u8 = 2;
+/// `e_ident[EI_CLASS]` of current architecture
+#[cfg(target_pointer_width = "32")]
+const ELF_CLASS: u8 = 1;
+/// `e_ident[EI_DATA]` of current architecture
+#[cfg(target_endian = "little")]
+const ELF_DATA: u8 = 1;
+/// `e_ident[EI_DATA]` of current architecture
+#[cfg(target_endian = "big")]
+const ELF_DATA: u8 = 2;
+
+/// `e_machine` of current architecture
+#[cfg(target_arch = "x86_64")]
+const ELF_MACHINE: u16 = 62;
+/// `e_machine` of current architecture
+#[cfg(target_arch = "x86")]
+const ELF_MACHINE: u16 = 3;
+/// `e_machine` of current architecture
+#[cfg(target_arch = "aarch64")]
+const ELF_MACHINE: u16 = 183;
+/// `e_machine` of current architecture
+#[cfg(target_arch = "riscv64")]
+const ELF_MACHINE: u16 = 243;
+/// `e_machine` of current architecture
+#[cfg(target_arch = "loongarch64")]
+const ELF_MACHINE: u16 = 258;
+
+/// `ET_DYN`, type of position-independent executables and shared objects
+const ET_DYN: u16 = 3;
+/// `PT_LOAD`, type of loadable segments
+#[cfg_attr(not(target_os = "linux"), allow(unused))]
+pub(crate) const PT_LOAD: u32 = 1;
+/// `SHT_SYMTAB`, type of symbol table sections
+const SHT_SYMTAB: u32 = 2;
+/// `SHT_DYNSYM`, type of dynamic symbol table sections
+const SHT_DYNSYM: u32 = 11;
+/// `SHT_NOBITS`, type of sections occupying no space in file
+const SHT_NOBITS: u32 = 8;
+/// `SHT_RELA`, type of relocation sections with explicit addends
+const SHT_RELA: u32 = 4;
+/// `STT_OBJECT`, type of data object symbols
+pub(crate) const STT_OBJECT: u8 = 1;
+/// `STT_FUNC`, type of function symbols
+pub(crate) const STT_FUNC: u8 = 2;
+
+/// Type of relocations adjusting by the load base on current architecture. Relocations without
+/// explicit addends keep the value in place, so only relocations with explicit addends are applied.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const R_RELATIVE: usize = 8;
+/// Type of relocations adjusting by the load base on current architecture. Relocations without
+/// explicit addends keep the value in place, so only relocations with explicit addends are applied.
+#[cfg(target_arch = "aarch64")]
+const R_RELATIVE: usize = 1027;
+/// Type of relocations adjusting by the load base on current architecture. Relocations without
+/// explicit addends keep the value in place, so only relocations with explicit addends are applied.
+#[cfg(any(target_arch = "riscv64", target_arch = "loongarch64"))]
+const R_RELATIVE: usize = 3;
+
+/// Size of a word in ELF file of current architecture
+const WORD: usize = core::mem::size_of::<usize>();
+
+/// A section of ELF file
+#[derive(Debug, Clone)]
+pub(crate) struct Section {
+    /// Name of the section
+    pub(crate) name: String,
+    /// `sh_type`
+    pub(crate) kind: u32,
+    /// Virtual address of the section
+    pub(crate) addr: usize,
+    /// Offset of the section in file
+    pub(crate) offset: usize,
+    /// Size of the section
+    pub(crate) size: usize,
+    /// `sh_link`
+    link: u32,
+}
+
+/// A segment of ELF file
+#[cfg_attr(not(target_os = "linux"), allow(unused))]
+#[derive(Debug, Clone)]
+pub(crate) struct Segment {
+    /// `p_type`
+    pub(crate) kind: u32,
+    /// Virtual address of the segment
+    pub(crate) vaddr: usize,
+}
+
+/// A symbol of ELF file
+#[derive(Debug, Clone)]
+pub(crate) struct Symbol {
+    /// Name of the symbol, which may be mangled
+    pub(crate) name: String,
+    /// Virtual address of the symbol
+    pub(crate) value: usize,
+    /// Size of the symbol
+    pub(crate) size: usize,
+    /// Type of the symbol
+    pub(crate) kind: u8,
+}
+
+/// A parsed ELF file
+pub(crate) struct Elf {
+    /// Content of the file
+    data: Vec<u8>,
+    /// Whether the file is position-independent
+    #[cfg_attr(not(target_os = "linux"), allow(unused))]
+    is_dyn: bool,
+    /// All sections
+    sections: Vec<Section>,
+    /// All segments
+    #[cfg_attr(not(target_os = "linux"), allow(unused))]
+    segments: Vec<Segment>,
+}
+
+/// Read a native-endian integer at `offset` of `data`
+fn read<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// Read a native-endian `u16` at `offset` of `data`
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    read(data, offset).map(u16::from_ne_bytes)
+}
+
+/// Read a native-endian `u32` at `offset` of `data`
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    read(data, offset).map(u32::from_ne_bytes)
+}
+
+/// Read a native-endian word at `offset` of `data`
+pub(crate) fn read_word(data: &[u8], offset: usize) -> Option<usize> {
+    read(data, offset).map(usize::from_ne_bytes)
+}
+
+/// Read a NUL-terminated string at `offset` of `data`
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+impl Elf {
+    /// Parse an ELF file. Return a description of the problem if it is not an ELF file of current
+    /// architecture.
+    pub(crate) fn parse(data: Vec<u8>) -> Result<Self, &'static str> {
+        if data.get(..4) != Some(b"\x7fELF") {
+            return Err("not an ELF file");
+        }
+        if data.get(4) != Some(&ELF_CLASS) || data.get(5) != Some(&ELF_DATA) {
+            return Err("ELF class or byte order does not match current architecture");
+        }
+        let malformed = "malformed ELF header";
+        // Offsets of fields in ELF header differ between ELF32 and ELF64 after `e_entry`
+        let (phoff, shoff, rest) = (16 + 8 + WORD, 16 + 8 + 2 * WORD, 16 + 8 + 3 * WORD + 4);
+        let e_type = read_u16(&data, 16).ok_or(malformed)?;
+        if read_u16(&data, 18).ok_or(malformed)? != ELF_MACHINE {
+            return Err("ELF machine does not match current architecture");
+        }
+        let e_phoff = read_word(&data, phoff).ok_or(malformed)?;
+        let e_shoff = read_word(&data, shoff).ok_or(malformed)?;
+        let e_phentsize = read_u16(&data, rest + 2).ok_or(malformed)? as usize;
+        let e_phnum = read_u16(&data, rest + 4).ok_or(malformed)? as usize;
+        let e_shentsize = read_u16(&data, rest + 6).ok_or(malformed)? as usize;
+        let e_shnum = read_u16(&data, rest + 8).ok_or(malformed)? as usize;
+        let e_shstrndx = read_u16(&data, rest + 10).ok_or(malformed)? as usize;
+
+        let malformed = "malformed program header";
+        let mut segments = Vec::with_capacity(e_phnum);
+        for index in 0..e_phnum {
+            let phdr = index
+                .checked_mul(e_phentsize)
+                .and_then(|offset| offset.checked_add(e_phoff))
+                .ok_or(malformed)?;
+            // `p_vaddr` follows `p_type`, `p_flags` and `p_offset` in ELF64, and follows `p_type`
+            // and `p_offset` in ELF32
+            let vaddr = phdr
+                .checked_add(if WORD == 8 { 16 } else { 8 })
+                .ok_or(malformed)?;
+            segments.push(Segment {
+                kind: read_u32(&data, phdr).ok_or(malformed)?,
+                vaddr: read_word(&data, vaddr).ok_or(malformed)?,
+            });
+        }
+
+        let malformed = "malformed section header";
+        let mut sections = Vec::with_capacity(e_shnum);
+        let mut name_offsets = Vec::with_capacity(e_shnum);
+        for index in 0..e_shnum {
+            let shdr = index
+                .checked_mul(e_shentsize)
+                .and_then(|offset| offset.checked_add(e_shoff))
+                .filter(|shdr| shdr.checked_add(8 + 4 * WORD + 4).is_some())
+                .ok_or(malformed)?;
+            name_offsets.push(read_u32(&data, shdr).ok_or(malformed)? as usize);
+            sections.push(Section {
+                name: String::new(),
+                kind: read_u32(&data, shdr + 4).ok_or(malformed)?,
+                addr: read_word(&data, shdr + 8 + WORD).ok_or(malformed)?,
+                offset: read_word(&data, shdr + 8 + 2 * WORD).ok_or(malformed)?,
+                size: read_word(&data, shdr + 8 + 3 * WORD).ok_or(malformed)?,
+                link: read_u32(&data, shdr + 8 + 4 * WORD).ok_or(malformed)?,
+            });
+        }
+        if let Some(shstrtab) = sections.get(e_shstrndx).map(|section| section.offset) {
+            let malformed = "malformed section name";
+            for (section, name_offset) in sections.iter_mut().zip(name_offsets) {
+                let offset = shstrtab.checked_add(name_offset).ok_or(malformed)?;
+                section.name = read_str(&data, offset).ok_or(malformed)?.into();
+            }
+        }
+
+        Ok(Self {
+            data,
+            is_dyn: e_type == ET_DYN,
+            sections,
+            segments,
+        })
+    }
+
+    /// Whether the file is position-independent, so it is loaded at a base address
+    #[cfg_attr(not(target_os = "linux"), allow(unused))]
+    pub(crate) fn is_dyn(&self) -> bool {
+        self.is_dyn
+    }
+
+    /// All segments
+    #[cfg_attr(not(target_os = "linux"), allow(unused))]
+    pub(crate) fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The section with given name
+    pub(crate) fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// Content of given section in file. Empty for sections occupying no space in file.
+    pub(crate) fn section_data(&self, section: &Section) -> Option<&[u8]> {
+        if section.kind == SHT_NOBITS {
+            return Some(&[]);
+        }
+        self.data
+            .get(section.offset..section.offset.checked_add(section.size)?)
+    }
+
+    /// Content of the file
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The section containing virtual address `addr` with length `len`
+    fn section_at(&self, addr: usize, len: usize) -> Option<&Section> {
+        self.sections.iter().find(|section| {
+            section.addr != 0
+                && section.addr <= addr
+                && addr
+                    .checked_add(len)
+                    .zip(section.addr.checked_add(section.size))
+                    .is_some_and(|(end, section_end)| end <= section_end)
+        })
+    }
+
+    /// Offset in file of virtual address `addr` with length `len`, if it resides in a section
+    /// occupying space in file
+    fn file_offset(&self, addr: usize, len: usize) -> Option<usize> {
+        let section = self.section_at(addr, len)?;
+        let offset = section.offset.checked_add(addr - section.addr)?;
+        (section.kind != SHT_NOBITS && offset.checked_add(len)? <= self.data.len())
+            .then_some(offset)
+    }
+
+    /// Whether virtual address `addr` with length `len` resides in a section occupying space in
+    /// file, so that it can be written by [`write_at`][Self::write_at]
+    pub(crate) fn is_in_file(&self, addr: usize, len: usize) -> bool {
+        self.file_offset(addr, len).is_some()
+    }
+
+    /// Content of file at virtual address `addr` with length `len`, if it resides in a section.
+    /// Sections occupying no space in file are read as zeros.
+    pub(crate) fn read_at(&self, addr: usize, len: usize) -> Option<Cow<'_, [u8]>> {
+        if self.section_at(addr, len)?.kind == SHT_NOBITS {
+            return Some(Cow::Owned(std::vec![0; len]));
+        }
+        let offset = self.file_offset(addr, len)?;
+        Some(Cow::Borrowed(&self.data[offset..offset + len]))
+    }
+
+    /// Overwrite content of file at virtual address `addr`. Return `None` if it does not reside in
+    /// a section occupying space in file.
+    pub(crate) fn write_at(&mut self, addr: usize, bytes: &[u8]) -> Option<()> {
+        let offset = self.file_offset(addr, bytes.len())?;
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Some(())
+    }
+
+    /// Word at virtual address `addr` after applying relocations adjusting by the load base, as if
+    /// the file is loaded at address 0
+    pub(crate) fn read_relocated_word(&self, addr: usize) -> Option<usize> {
+        // `r_offset`, `r_info` and `r_addend` are words, and the type of relocation is the low 32
+        // bits of `r_info` in ELF64, and the low 8 bits in ELF32
+        let type_mask = if WORD == 8 { 0xffff_ffff } else { 0xff };
+        let addend = self
+            .sections
+            .iter()
+            .filter(|section| section.kind == SHT_RELA)
+            .filter_map(|section| self.section_data(section))
+            .flat_map(|relocations| relocations.chunks_exact(3 * WORD))
+            .find_map(|relocation| {
+                let r_offset = read_word(relocation, 0)?;
+                let r_info = read_word(relocation, WORD)?;
+                (r_offset == addr && r_info & type_mask == R_RELATIVE)
+                    .then(|| read_word(relocation, 2 * WORD))?
+            });
+        match addend {
+            Some(addend) => Some(addend),
+            None => read_word(&self.read_at(addr, WORD)?, 0),
+        }
+    }
+
+    /// All symbols in symbol tables
+    pub(crate) fn symbols(&self) -> Vec<Symbol> {
+        // Layout of symbols differs between ELF32 and ELF64
+        let (value_offset, size_offset, info_offset, sym_size) = if WORD == 8 {
+            (8, 16, 4, 24)
+        } else {
+            (4, 8, 12, 16)
+        };
+        self.sections
+            .iter()
+            .filter(|section| section.kind == SHT_SYMTAB || section.kind == SHT_DYNSYM)
+            .filter_map(|section| Some((self.sections.get(section.link as usize)?, section)))
+            .filter_map(|(strtab, section)| Some((strtab, self.section_data(section)?)))
+            .flat_map(|(strtab, symbols)| {
+                symbols.chunks_exact(sym_size).filter_map(|symbol| {
+                    let name_offset = read_u32(symbol, 0)? as usize;
+                    Some(Symbol {
+                        name: read_str(&self.data, strtab.offset.checked_add(name_offset)?)?.into(),
+                        value: read_word(symbol, value_offset)?,
+                        size: read_word(symbol, size_offset)?,
+                        kind: symbol.get(info_offset)? & 0xf,
+                    })
+                })
+            })
+            .filter(|symbol| symbol.value != 0 && !symbol.name.is_empty())
+            .collect()
+    }
+
+    /// Virtual address of the symbol with given name in symbol tables
+    #[cfg_attr(not(target_os = "linux"), allow(unused))]
+    pub(crate) fn symbol(&self, name: &str) -> Option<usize> {
+        self.symbols()
+            .into_iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.value)
+    }
+}
+
+/// Decode the content of `__static_keys` section at `section_addr`, and return non-dummy jump
+/// entries sorted by key address and code address.
+///
+/// If `relative` is `true`, the fields are relative addresses as in the file, and are made absolute
+/// in the same way as `make_relative_address_absolute`.
+pub(crate) fn decode_jump_entries(
+    data: &[u8],
+    section_addr: usize,
+    relative: bool,
+) -> Vec<JumpEntry> {
+    let entry_size = core::mem::size_of::<JumpEntry>();
+    let mut jump_entries = data
+        .chunks_exact(entry_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let field = |offset: usize| {
+                let value = read_word(chunk, offset).unwrap_or(0);
+                if relative && value != 0 {
+                    let field_addr = section_addr + index * entry_size + offset;
+                    field_addr.wrapping_add(value)
+                } else {
+                    value
+                }
+            };
+            JumpEntry {
+                code: field(0),
+                target: field(WORD),
+                key: field(2 * WORD),
+            }
+        })
+        .filter(|jump_entry| !jump_entry.is_dummy() && jump_entry.is_key_aligned())
+        .collect::<Vec<_>>();
+    jump_entries.sort_unstable_by_key(|jump_entry| (jump_entry.key_addr(), jump_entry.code_addr()));
+    jump_entries
+}
+
+/// Demangle a Rust symbol in legacy mangling scheme. Other symbols are returned as is.
+pub(crate) fn demangle(symbol: &str) -> String {
+    let Some(mut rest) = symbol.strip_prefix("_ZN") else {
+        return symbol.into();
+    };
+    let mut components = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let Some(len) = rest[..digits].parse::<usize>().ok() else {
+            return symbol.into();
+        };
+        let Some(component) = digits
+            .checked_add(len)
+            .and_then(|end| rest.get(digits..end))
+        else {
+            return symbol.into();
+        };
+        components.push(component);
+        rest = &rest[digits + len..];
+    }
+    // The last component is the hash of the symbol
+    if components.last().is_some_and(|last| {
+        last.len() == 17
+            && last.starts_with('h')
+            && last[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    }) {
+        components.pop();
+    }
+    let mut demangled = String::new();
+    for (index, component) in components.iter().enumerate() {
+        if index > 0 {
+            demangled.push_str("::");
+        }
+        // Components starting with `$` are prefixed with `_`
+        let mut component = component
+            .strip_prefix('_')
+            .filter(|c| c.starts_with('$'))
+            .unwrap_or(component);
+        while !component.is_empty() {
+            let escape = [
+                ("$LT$", "<"),
+                ("$GT$", ">"),
+                ("$RF$", "&"),
+                ("$BP$", "*"),
+                ("$SP$", "@"),
+                ("$C$", ","),
+                ("$u20$", " "),
+                ("$u27$", "'"),
+                ("$u5b$", "["),
+                ("$u5d$", "]"),
+                ("$u7b$", "{"),
+                ("$u7d$", "}"),
+                ("$u7e$", "~"),
+                ("..", "::"),
+            ]
+            .into_iter()
+            .find(|(escaped, _)| component.starts_with(escaped));
+            if let Some((escaped, unescaped)) = escape {
+                demangled.push_str(unescaped);
+                component = &component[escaped.len()..];
+            } else {
+                let c = component.chars().next().unwrap_or_default();
+                demangled.push(c);
+                component = &component[c.len_utf8()..];
+            }
+        }
+    }
+    demangled
+}
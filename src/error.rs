@@ -0,0 +1,115 @@
+//! Errors occurred when modifying static keys.
+
+use crate::code_manipulate::CodeManipulateError;
+
+/// Error occurred when modifying static keys.
+///
+/// When this error is returned, the status of static keys and the instructions are both rolled
+/// back, so they are always consistent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StaticKeyError {
+    /// Failed to write the instruction of a jump entry
+    WriteCode {
+        /// Address of the instruction failed to be written. For instructions written together,
+        /// it is the address of the first one.
+        site: usize,
+        /// Name of the failed system call
+        syscall: &'static str,
+        /// Error code reported by the OS, such as `errno` on Linux. 0 if not available.
+        errno: i32,
+    },
+    /// The instruction of a jump entry does not match the status of its static key, so it is not
+    /// written to avoid overwriting unrelated code. Nothing is modified in such case.
+    UnexpectedInstruction {
+        /// Address of the mismatched instruction
+        site: usize,
+    },
+    /// The new target of a static call cannot be encoded in the instruction of its trampoline, or
+    /// [`try_global_init`][crate::try_global_init] finds a branch, static call or static switch
+    /// whose JMP destination cannot be encoded in its instruction. Nothing is modified in such case.
+    TargetOutOfRange {
+        /// Address of the instruction
+        site: usize,
+        /// Address of the JMP destination
+        target: usize,
+    },
+    /// [`global_init`][crate::global_init] has not been called before modifying static keys while
+    /// other threads may be running. Nothing is modified in such case.
+    NotInitialized,
+    /// Failed to install the signal handler needed to modify instructions while other threads are
+    /// running. Nothing is modified in such case.
+    #[cfg(target_os = "linux")]
+    SignalHandler {
+        /// The signal whose handler cannot be installed
+        signum: i32,
+        /// Error code of `sigaction` reported by the OS
+        errno: i32,
+    },
+    /// Other threads cannot be stopped, for example, when some of them do not acknowledge the stop
+    /// request
+    #[cfg(target_os = "linux")]
+    StopTheWorld(crate::StopTheWorldError),
+}
+
+impl StaticKeyError {
+    /// Create a [`StaticKeyError::WriteCode`] with the error reported by code manipulator
+    pub(crate) fn write_code(site: usize, error: CodeManipulateError) -> Self {
+        Self::WriteCode {
+            site,
+            syscall: error.syscall,
+            errno: error.errno,
+        }
+    }
+}
+
+impl core::fmt::Display for StaticKeyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::WriteCode {
+                site,
+                syscall,
+                errno,
+            } => write!(
+                f,
+                "failed to write instruction at {site:#x}: {syscall} failed with error code {errno}"
+            ),
+            Self::UnexpectedInstruction { site } => {
+                write!(f, "unexpected instruction at {site:#x}")
+            }
+            Self::TargetOutOfRange { site, target } => {
+                write!(f, "instruction at {site:#x} cannot jump to {target:#x}")
+            }
+            Self::NotInitialized => write!(f, "global_init has not been called"),
+            #[cfg(target_os = "linux")]
+            Self::SignalHandler { signum, errno } => write!(
+                f,
+                "failed to install handler of signal {signum}: sigaction failed with error code {errno}"
+            ),
+            #[cfg(target_os = "linux")]
+            Self::StopTheWorld(err) => write!(f, "failed to stop the world: {err}"),
+        }
+    }
+}
+
+impl core::error::Error for StaticKeyError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::WriteCode { .. }
+            | Self::UnexpectedInstruction { .. }
+            | Self::TargetOutOfRange { .. }
+            | Self::NotInitialized => None,
+            #[cfg(target_os = "linux")]
+            Self::SignalHandler { .. } => None,
+            #[cfg(target_os = "linux")]
+            Self::StopTheWorld(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<crate::StopTheWorldError> for StaticKeyError {
+    fn from(err: crate::StopTheWorldError) -> Self {
+        Self::StopTheWorld(err)
+    }
+}
@@ -0,0 +1,89 @@
+//! Expiry of static keys enabled for a limited time. Only available with `std` feature.
+
+use std::{
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+    vec::Vec,
+};
+
+use crate::{StaticKeyError, patch_lock::PatchGuard};
+
+/// Interval to retry disabling an expired static key whose instructions cannot be modified
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A static key to be disabled at deadline
+struct Expiry {
+    /// Address of the static key
+    key_addr: usize,
+    /// When to disable the static key
+    deadline: Instant,
+    /// [`static_key_update_timed_locked`][crate::static_key_update_timed_locked] for the static key
+    update: unsafe fn(usize, bool) -> Result<(), StaticKeyError>,
+}
+
+/// All registered expiries. Always acquired after [`PatchGuard`] if both are needed.
+static EXPIRIES: Mutex<Vec<Expiry>> = Mutex::new(Vec::new());
+
+/// Lock [`EXPIRIES`]. The list is always consistent, so poisoning is ignored.
+fn expiries() -> MutexGuard<'static, Vec<Expiry>> {
+    EXPIRIES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Disable the static key at `key_addr` at `deadline`, replacing its previous deadline if any
+pub(crate) fn register(
+    key_addr: usize,
+    deadline: Instant,
+    update: unsafe fn(usize, bool) -> Result<(), StaticKeyError>,
+) {
+    let mut expiries = expiries();
+    expiries.retain(|expiry| expiry.key_addr != key_addr);
+    expiries.push(Expiry {
+        key_addr,
+        deadline,
+        update,
+    });
+}
+
+/// Cancel the deadline of the static key at `key_addr` if any
+pub(crate) fn cancel(key_addr: usize) {
+    expiries().retain(|expiry| expiry.key_addr != key_addr);
+}
+
+/// Deadline of the static key at `key_addr`
+pub(crate) fn deadline(key_addr: usize) -> Option<Instant> {
+    expiries()
+        .iter()
+        .find(|expiry| expiry.key_addr == key_addr)
+        .map(|expiry| expiry.deadline)
+}
+
+/// Disable static keys whose deadline has passed, and return the earliest remaining deadline.
+///
+/// If the instructions of some static keys cannot be modified, they are retried after
+/// [`RETRY_INTERVAL`].
+///
+/// # Safety
+///
+/// See [`static_key_update_timed_locked`][crate::static_key_update_timed_locked].
+pub(crate) unsafe fn tick(now: Instant) -> Option<Instant> {
+    let _guard = PatchGuard::lock();
+    loop {
+        let expired = {
+            let mut expiries = expiries();
+            expiries
+                .iter()
+                .position(|expiry| expiry.deadline <= now)
+                .map(|index| expiries.swap_remove(index))
+        };
+        let Some(expiry) = expired else {
+            break;
+        };
+        if unsafe { (expiry.update)(expiry.key_addr, false) }.is_err() {
+            expiries().push(Expiry {
+                deadline: now + RETRY_INTERVAL,
+                ..expiry
+            });
+        }
+    }
+    expiries().iter().map(|expiry| expiry.deadline).min()
+}
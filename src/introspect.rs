@@ -0,0 +1,251 @@
+//! Introspection of static keys and their branch sites.
+
+use crate::{
+    GenericStaticKey, JumpEntry, JumpLabelType, code_manipulate::DummyCodeManipulator,
+    patch_lock::PatchGuard,
+};
+
+/// Information about a static key used by any branch, yielded by [`keys`].
+#[derive(Clone, Copy)]
+pub struct KeyInfo {
+    /// The static key. The M and S generic is useless here
+    key: &'static GenericStaticKey<DummyCodeManipulator, true>,
+}
+
+impl KeyInfo {
+    /// Address of the static key
+    pub fn addr(&self) -> usize {
+        self.key as *const _ as usize
+    }
+
+    /// Current status of the static key
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_enabled()
+    }
+
+    /// Initial status of the static key
+    pub fn initial_enabled(&self) -> bool {
+        self.key.initial
+    }
+
+    /// Count of branch sites associated with the static key
+    pub fn site_count(&self) -> usize {
+        self.key.jump_entries().len()
+    }
+
+    /// Branch sites associated with the static key
+    pub fn sites(&self) -> impl Iterator<Item = SiteInfo> + use<> {
+        self.key.sites()
+    }
+}
+
+impl core::fmt::Debug for KeyInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("KeyInfo")
+            .field("addr", &format_args!("{:#x}", self.addr()))
+            .field("enabled", &self.is_enabled())
+            .field("initial_enabled", &self.initial_enabled())
+            .field("site_count", &self.site_count())
+            .finish()
+    }
+}
+
+/// Kind of the instruction currently at a branch site
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionKind {
+    /// NOP instruction, so the likely branch is taken
+    Nop,
+    /// JMP instruction, so the unlikely branch is taken
+    Jmp,
+    /// Neither NOP nor JMP instruction, which means the code has been tampered
+    Unknown,
+}
+
+impl InstructionKind {
+    /// Kind of `instruction` found at given jump entry
+    pub(crate) fn of(jump_entry: &JumpEntry, instruction: &[u8]) -> Self {
+        if instruction == crate::arch::arch_jump_entry_instruction(JumpLabelType::Nop, jump_entry) {
+            Self::Nop
+        } else if instruction
+            == crate::arch::arch_jump_entry_instruction(JumpLabelType::Jmp, jump_entry)
+        {
+            Self::Jmp
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+/// Information about a branch site generated by [`static_branch_likely`][crate::static_branch_likely]
+/// and its variants, yielded by [`GenericStaticKey::sites`] and [`KeyInfo::sites`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SiteInfo {
+    /// Address of the JMP/NOP instruction
+    code_addr: usize,
+    /// Address of the JMP destination
+    target_addr: usize,
+    /// Whether the likely branch is true branch
+    likely_branch_is_true: bool,
+    /// Kind of the current instruction
+    instruction: InstructionKind,
+}
+
+impl SiteInfo {
+    /// Read the current instruction at given jump entry
+    fn new(jump_entry: &JumpEntry) -> Self {
+        let instruction = {
+            let _guard = PatchGuard::lock();
+            crate::verify::read_instruction(jump_entry)
+        };
+        Self {
+            code_addr: jump_entry.code_addr(),
+            target_addr: jump_entry.target_addr(),
+            likely_branch_is_true: jump_entry.likely_branch_is_true(),
+            instruction: InstructionKind::of(jump_entry, &instruction),
+        }
+    }
+
+    /// Address of the JMP/NOP instruction
+    pub fn code_addr(&self) -> usize {
+        self.code_addr
+    }
+
+    /// Address of the JMP destination, which is the start of the unlikely branch
+    pub fn target_addr(&self) -> usize {
+        self.target_addr
+    }
+
+    /// Whether the likely branch is true branch, i.e., the site is generated by
+    /// [`static_branch_likely`][crate::static_branch_likely] or its variants
+    pub fn likely_branch_is_true(&self) -> bool {
+        self.likely_branch_is_true
+    }
+
+    /// Kind of the instruction when this information is collected
+    pub fn instruction(&self) -> InstructionKind {
+        self.instruction
+    }
+}
+
+impl<M: crate::code_manipulate::CodeManipulator, const S: bool> GenericStaticKey<M, S> {
+    /// Branch sites associated with current static key, sorted by address.
+    ///
+    /// If [`global_init`][crate::global_init] has not been called yet, it is called first, and
+    /// nothing is yielded if it fails. Each instruction is read with the process-wide patch lock
+    /// held.
+    pub fn sites(&self) -> impl Iterator<Item = SiteInfo> + use<M, S> {
+        // The jump entries of this static key stay unknown if global_init fails
+        let _ = crate::ensure_global_init();
+        self.jump_entries().iter().map(SiteInfo::new)
+    }
+}
+
+/// Static keys used by branches, without calling [`global_init`][crate::global_init]
+fn keys_unchecked() -> impl Iterator<Item = KeyInfo> {
+    let dummy_key_addr = &raw const crate::DUMMY_STATIC_KEY as usize;
+    let mut last_key_addr = 0;
+    crate::jump_entries().iter().filter_map(move |jump_entry| {
+        let key_addr = jump_entry.key_addr();
+        if jump_entry.is_dummy()
+            || !jump_entry.is_key_aligned()
+            || key_addr == last_key_addr
+            || key_addr == dummy_key_addr
+        {
+            return None;
+        }
+        // Jump entries are sorted by key address, so each static key is visited once
+        last_key_addr = key_addr;
+        Some(KeyInfo {
+            // The M and S generic is useless here
+            key: unsafe { &*(key_addr as *const GenericStaticKey<DummyCodeManipulator, true>) },
+        })
+    })
+}
+
+/// All static keys used by at least one branch, sorted by address.
+///
+/// Static keys never used by any branch are not known to this crate, and thus never yielded.
+///
+/// If [`global_init`][crate::global_init] has not been called yet, it is called first, and nothing
+/// is yielded if it fails.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::{define_static_key_false, static_branch_unlikely};
+///
+/// define_static_key_false!(MY_STATIC_KEY);
+///
+/// fn main() {
+///     if static_branch_unlikely!(MY_STATIC_KEY) {
+///         println!("enabled");
+///     }
+///     let key = static_keys::keys()
+///         .find(|key| key.addr() == &raw const MY_STATIC_KEY as usize)
+///         .unwrap();
+///     assert!(!key.is_enabled());
+///     assert!(!key.initial_enabled());
+/// }
+/// ```
+pub fn keys() -> impl Iterator<Item = KeyInfo> {
+    crate::ensure_global_init()
+        .is_ok()
+        .then(keys_unchecked)
+        .into_iter()
+        .flatten()
+}
+
+/// Whether `addr` lies in instructions which may be modified by this crate, which are:
+///
+/// * JMP/NOP instructions of static keys. Only the modified instruction itself is considered, not
+///   the instructions loading the JMP destination of
+///   [`static_branch_likely_far`][crate::static_branch_likely_far].
+/// * JMP instructions of [`define_static_call`][crate::define_static_call] trampolines.
+/// * JMP instructions of [`static_switch`][crate::static_switch] sites.
+/// * Instructions of [`runtime_const`][crate::runtime_const] sites.
+/// * Original instructions of [`alternative`][crate::alternative], which are only modified in
+///   [`global_init`][crate::global_init].
+///
+/// Other code patchers can use this function to avoid modifying the same instructions, and crash
+/// handlers can use it to tell whether a faulting address is being patched.
+///
+/// This function takes no lock and never allocates, so it can be called in signal handlers. It
+/// always returns `false` before [`global_init`][crate::global_init] finishes.
+pub fn is_patch_site(addr: usize) -> bool {
+    if !crate::is_global_init_done() {
+        return false;
+    }
+    crate::jump_entries().iter().any(|jump_entry| {
+        !jump_entry.is_dummy()
+            && jump_entry.is_key_aligned()
+            && (jump_entry.code_addr()..jump_entry.code_addr() + crate::arch::ARCH_JUMP_INS_LENGTH)
+                .contains(&addr)
+    }) || crate::static_call::is_patch_site(addr)
+        || crate::static_switch::is_patch_site(addr)
+        || crate::runtime_const::is_patch_site(addr)
+        || crate::alternative::is_patch_site(addr)
+}
+
+/// Install a panic hook which prints all enabled static keys to stderr, and then calls the
+/// previous panic hook.
+///
+/// Nothing is printed if [`global_init`][crate::global_init] has not finished. The patch lock is
+/// not acquired, so the hook never deadlocks even if the panic happens while modifying static keys.
+#[cfg(feature = "std")]
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(std::boxed::Box::new(move |info| {
+        if crate::is_global_init_done() {
+            std::eprintln!("Enabled static keys:");
+            for key in keys_unchecked().filter(KeyInfo::is_enabled) {
+                std::eprintln!(
+                    "  {:#x} (initial: {}, sites: {})",
+                    key.addr(),
+                    key.initial_enabled(),
+                    key.site_count()
+                );
+            }
+        }
+        previous(info);
+    }));
+}
@@ -2,9 +2,66 @@
 #![no_std]
 #![allow(clippy::needless_doctest_main)]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+mod alternative;
 mod arch;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod attach;
 pub mod code_manipulate;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(all(feature = "std", unix))]
+pub mod control;
+mod counted;
+mod cpu_feature;
+pub mod deferred;
+#[cfg(feature = "std")]
+pub mod dump;
+#[cfg(feature = "std")]
+mod elf;
+mod error;
+#[cfg(feature = "std")]
+mod expiry;
+mod introspect;
 mod os;
+mod patch_lock;
+mod registry;
+pub mod runtime_const;
+pub mod static_call;
+pub mod static_switch;
+#[cfg(feature = "std")]
+mod timer;
+mod transaction;
+mod validate;
+mod verify;
+
+pub use counted::{
+    CountedStaticFalseKey, CountedStaticKey, CountedStaticKeyGuard, CountedStaticTrueKey,
+    GenericCountedStaticKey, new_counted_static_false_key, new_counted_static_true_key,
+};
+#[doc(hidden)]
+pub use cpu_feature::CpuFeatureEntry;
+pub use deferred::{
+    DeferredStaticFalseKey, DeferredStaticKey, DeferredStaticTrueKey, GenericDeferredStaticKey,
+    new_deferred_static_false_key, new_deferred_static_true_key,
+};
+pub use error::StaticKeyError;
+#[cfg(feature = "std")]
+pub use introspect::install_panic_hook;
+pub use introspect::{InstructionKind, KeyInfo, SiteInfo, is_patch_site, keys};
+#[cfg(target_os = "linux")]
+pub use os::StopTheWorldError;
+#[doc(hidden)]
+pub use registry::KeyNameEntry;
+pub use registry::{NamedKey, Registry, registry};
+pub use runtime_const::{GenericRuntimeConst, RuntimeConst, new_runtime_const, runtime_const_init};
+pub use static_call::{GenericStaticCall, StaticCall};
+pub use static_switch::{GenericStaticSwitch, StaticSwitch, new_static_switch};
+pub use transaction::{MAX_TRANSACTION_KEYS, Transaction, batch};
+pub use validate::{ValidationIssue, validate_jump_entries};
+pub use verify::{SiteMismatch, verify_all};
 
 use code_manipulate::CodeManipulator;
 
@@ -15,7 +72,7 @@ use code_manipulate::CodeManipulator;
 ///
 /// The relative addresses will be updated to absolute address after calling [`global_init`]. This
 /// is because we want to sort the jump entries in place.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct JumpEntry {
     /// Address of the JMP/NOP instruction to be modified.
     code: usize,
@@ -23,32 +80,41 @@ struct JumpEntry {
     target: usize,
     /// Address of associated static key.
     ///
-    /// Since the static key has at least 8-byte alignment, the LSB bit of this address is used
+    /// Since the static key has at least 4-byte alignment, the LSB bit of this address is used
     /// to record whether the likely branch is true branch or false branch in order to get right instruction
-    /// to replace old one.
+    /// to replace old one, and the second lowest bit is used to record whether the far JMP/NOP instruction
+    /// is used.
     key: usize,
 }
 
+/// Absolute address recorded by `field` as a relative address to itself
+#[cfg(not(all(target_os = "windows", target_arch = "x86_64")))]
+fn absolute_address(field: &usize) -> usize {
+    (field as *const usize as usize).wrapping_add(*field)
+}
+
+// For Win64, the relative address is truncated into 32bit.
+// See https://github.com/llvm/llvm-project/blob/862d837e483437b33f5588f89e62085de3a806b9/llvm/lib/Target/X86/MCTargetDesc/X86WinCOFFObjectWriter.cpp#L48-L51
+/// Absolute address recorded by `field` as a relative address to itself
+#[cfg(all(target_os = "windows", target_arch = "x86_64"))]
+fn absolute_address(field: &usize) -> usize {
+    let relative_addr = (*field as i32) as i64 as usize;
+    (field as *const usize as usize).wrapping_add(relative_addr)
+}
+
 impl JumpEntry {
-    /// Update fields to be absolute address
-    #[cfg(not(all(target_os = "windows", target_arch = "x86_64")))]
-    fn make_relative_address_absolute(&mut self) {
-        self.code = ((&raw const self.code) as usize).wrapping_add(self.code);
-        self.target = ((&raw const self.target) as usize).wrapping_add(self.target);
-        self.key = ((&raw const self.key) as usize).wrapping_add(self.key);
+    /// Copy of this jump entry with absolute addresses, whose fields are still relative addresses
+    fn to_absolute(&self) -> Self {
+        Self {
+            code: absolute_address(&self.code),
+            target: absolute_address(&self.target),
+            key: absolute_address(&self.key),
+        }
     }
 
-    // For Win64, the relative address is truncated into 32bit.
-    // See https://github.com/llvm/llvm-project/blob/862d837e483437b33f5588f89e62085de3a806b9/llvm/lib/Target/X86/MCTargetDesc/X86WinCOFFObjectWriter.cpp#L48-L51
     /// Update fields to be absolute address
-    #[cfg(all(target_os = "windows", target_arch = "x86_64"))]
     fn make_relative_address_absolute(&mut self) {
-        let code = (self.code as i32) as i64 as usize;
-        self.code = ((&raw const self.code) as usize).wrapping_add(code);
-        let target = (self.target as i32) as i64 as usize;
-        self.target = ((&raw const self.target) as usize).wrapping_add(target);
-        let key = (self.key as i32) as i64 as usize;
-        self.key = ((&raw const self.key) as usize).wrapping_add(key);
+        *self = self.to_absolute();
     }
 
     /// Absolute address of the JMP/NOP instruction to be modified
@@ -63,7 +129,7 @@ impl JumpEntry {
 
     /// Absolute address of the associated static key
     fn key_addr(&self) -> usize {
-        self.key & !1usize
+        self.key & !3usize
     }
 
     /// Return `true` if the likely branch is true branch.
@@ -71,6 +137,13 @@ impl JumpEntry {
         (self.key & 1usize) != 0
     }
 
+    /// Return `true` if the instruction is generated by [`static_branch_likely_far`] or
+    /// [`static_branch_unlikely_far`], whose JMP destination is loaded by preceding instructions.
+    #[cfg_attr(any(target_arch = "x86", target_arch = "x86_64"), allow(unused))]
+    fn is_far(&self) -> bool {
+        (self.key & 2usize) != 0
+    }
+
     /// Unique reference to associated key
     fn key_mut<M: CodeManipulator, const S: bool>(&self) -> &'static mut GenericStaticKey<M, S> {
         unsafe { &mut *(self.key_addr() as *mut GenericStaticKey<M, S>) }
@@ -81,6 +154,14 @@ impl JumpEntry {
         self.code == 0
     }
 
+    /// Whether the address of associated key is aligned. Otherwise, the jump entry is corrupted, and
+    /// it is never associated with any static key.
+    fn is_key_aligned(&self) -> bool {
+        self.key_addr().is_multiple_of(core::mem::align_of::<
+            GenericStaticKey<code_manipulate::DummyCodeManipulator, true>,
+        >())
+    }
+
     /// Create a dummy jump entry
     #[allow(unused)]
     const fn dummy() -> Self {
@@ -109,6 +190,9 @@ pub struct GenericStaticKey<M: CodeManipulator, const S: bool> {
     /// This field is defined as `AtomicBool` to allow interior mutability of static variables to avoid
     /// creating mutable static.
     enabled: core::sync::atomic::AtomicBool,
+    /// Initial status, which is the same as `S`. It is recorded here since `S` is unknown when the
+    /// static key is found through jump entries.
+    initial: bool,
     /// Start address of associated jump entries.
     ///
     /// The jump entries are sorted based on associated static key address in [`global_init`][Self::global_init]
@@ -122,9 +206,6 @@ pub struct GenericStaticKey<M: CodeManipulator, const S: bool> {
 }
 
 /// Static key to hold data about current status and which jump entries are associated with this key.
-///
-/// For now, it is not encouraged to modify static key in a multi-thread application (which I don't think
-/// is a common situation).
 pub type StaticKey<const S: bool> = GenericStaticKey<crate::os::ArchCodeManipulator, S>;
 /// A [`StaticKey`] with initial status `true`.
 pub type StaticTrueKey = StaticKey<true>;
@@ -153,6 +234,7 @@ impl<M: CodeManipulator, const S: bool> GenericStaticKey<M, S> {
     const fn new(enabled: bool) -> Self {
         Self {
             enabled: core::sync::atomic::AtomicBool::new(enabled),
+            initial: enabled,
             entries: 0,
             phantom: core::marker::PhantomData,
         }
@@ -163,88 +245,471 @@ impl<M: CodeManipulator, const S: bool> GenericStaticKey<M, S> {
         self.entries as *const _
     }
 
+    /// All jump entries associated with current static key.
+    ///
+    /// This slice is empty before [`global_init`] or if this static key is never used.
+    fn jump_entries(&self) -> &'static [JumpEntry] {
+        let jump_entry_start_addr = self.entries();
+        if jump_entry_start_addr.is_null() {
+            // This static key is never used
+            return &[];
+        }
+        let jump_entry_stop_addr = &raw const os::JUMP_ENTRY_STOP;
+        let mut jump_entry_addr = jump_entry_start_addr;
+        while jump_entry_addr < jump_entry_stop_addr {
+            let jump_entry = unsafe { &*jump_entry_addr };
+            // Not the same key
+            if self as *const _ as usize != jump_entry.key_addr() {
+                break;
+            }
+            jump_entry_addr = unsafe { jump_entry_addr.add(1) };
+        }
+        unsafe {
+            core::slice::from_raw_parts(
+                jump_entry_start_addr,
+                jump_entry_addr.offset_from(jump_entry_start_addr) as usize,
+            )
+        }
+    }
+
     /// Enable this static key (make the value to be `true`). Do nothing if current static key is already enabled.
     ///
+    /// Modifications of static keys are serialized by a process-wide lock shared by all copies of this crate,
+    /// so this method can be called in parallel. If [`global_init`] has not been called yet, it is called
+    /// before modifying instructions.
+    ///
     /// # Safety
     ///
-    /// This method may be UB if called before [`global_init`] or called in parallel. Never call this method when
-    /// there are multi-threads running. Spawn threads after this method is called. This method may manipulate
-    /// code region memory protection, and if other threads are executing codes in the same code page, it may
-    /// lead to unexpected behaviors.
+    /// Never call this method when other threads may be executing codes in the same code page. This method may manipulate code region memory protection, and
+    /// it may lead to unexpected behaviors. Use [`enable_live`][Self::enable_live] or
+    /// [`enable_stop_the_world`][Self::enable_stop_the_world] on Linux for such situation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the instructions cannot be modified. Use [`try_enable`][Self::try_enable] to handle
+    /// such error.
     pub unsafe fn enable(&self) {
-        unsafe { static_key_update(self, true) }
+        if let Err(err) = unsafe { static_key_update(self, true) } {
+            panic!("Failed to enable static key: {err}");
+        }
     }
 
     /// Disable this static key (make the value to be `false`). Do nothing if current static key is already disabled.
     ///
+    /// Modifications of static keys are serialized by a process-wide lock shared by all copies of this crate,
+    /// so this method can be called in parallel. If [`global_init`] has not been called yet, it is called
+    /// before modifying instructions.
+    ///
     /// # Safety
     ///
-    /// This method may be UB if called before [`global_init`] or called in parallel. Never call this method when
-    /// there are multi-threads running. Spawn threads after this method is called. This method may manipulate
-    /// code region memory protection, and if other threads are executing codes in the same code page, it may
-    /// lead to unexpected behaviors.
+    /// Never call this method when other threads may be executing codes in the same code page. This method may manipulate code region memory protection, and
+    /// it may lead to unexpected behaviors. Use [`disable_live`][Self::disable_live] or
+    /// [`disable_stop_the_world`][Self::disable_stop_the_world] on Linux for such situation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the instructions cannot be modified. Use [`try_disable`][Self::try_disable] to handle
+    /// such error.
     pub unsafe fn disable(&self) {
+        if let Err(err) = unsafe { static_key_update(self, false) } {
+            panic!("Failed to disable static key: {err}");
+        }
+    }
+
+    /// Enable this static key (make the value to be `true`). Do nothing if current static key is already enabled.
+    ///
+    /// Same as [`enable`][Self::enable], but return an error if the instructions cannot be modified. In
+    /// such case, the status of this static key and all modified instructions are rolled back.
+    ///
+    /// # Safety
+    ///
+    /// See [`enable`][Self::enable].
+    pub unsafe fn try_enable(&self) -> Result<(), StaticKeyError> {
+        unsafe { static_key_update(self, true) }
+    }
+
+    /// Disable this static key (make the value to be `false`). Do nothing if current static key is already disabled.
+    ///
+    /// Same as [`disable`][Self::disable], but return an error if the instructions cannot be modified. In
+    /// such case, the status of this static key and all modified instructions are rolled back.
+    ///
+    /// # Safety
+    ///
+    /// See [`disable`][Self::disable].
+    pub unsafe fn try_disable(&self) -> Result<(), StaticKeyError> {
         unsafe { static_key_update(self, false) }
     }
 
+    /// Enable this static key (make the value to be `true`) while other threads may be executing
+    /// the associated branches. Do nothing if current static key is already enabled.
+    ///
+    /// On x86 and x86_64, each JMP/NOP instruction is replaced following the `text_poke_bp` protocol
+    /// of Linux kernel: an `int3` is written to the first byte, then the remaining bytes are written,
+    /// and finally the first byte is replaced, with all cores synchronized between each step. Threads
+    /// hitting an `int3` during the update are redirected to the correct branch by a `SIGTRAP` handler,
+    /// which is installed at the first invocation and chains to the previously installed handler
+    /// for breakpoints not belonging to static keys. This is much slower than [`enable`][Self::enable],
+    /// since each instruction is written three times.
+    ///
+    /// On other architectures, the JMP/NOP instructions are single aligned instructions which can be
+    /// replaced while being executed, and all cores are synchronized by
+    /// `membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE)` after each replacement. If such barrier is
+    /// not supported, this method falls back to [`enable_stop_the_world`][Self::enable_stop_the_world].
+    ///
+    /// [`global_init`] must have been called before, since it modifies instructions without
+    /// synchronizing with other threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`global_init`] has not been called, the instructions cannot be modified, or the
+    /// fallback fails to stop the world.
+    #[cfg(target_os = "linux")]
+    pub fn enable_live(&self) {
+        if let Err(err) = static_key_update_live(self, true) {
+            panic!("Failed to enable static key: {err}");
+        }
+    }
+
+    /// Disable this static key (make the value to be `false`) while other threads may be executing
+    /// the associated branches. Do nothing if current static key is already disabled.
+    ///
+    /// See [`enable_live`][Self::enable_live] for details.
+    #[cfg(target_os = "linux")]
+    pub fn disable_live(&self) {
+        if let Err(err) = static_key_update_live(self, false) {
+            panic!("Failed to disable static key: {err}");
+        }
+    }
+
+    /// Enable this static key (make the value to be `true`) while all other threads of current process
+    /// are parked. Do nothing if current static key is already enabled.
+    ///
+    /// Each thread listed in `/proc/self/task` is sent a `SIGRTMIN+8` signal, whose handler holds the
+    /// thread on a futex until the instructions are updated. If some threads do not acknowledge
+    /// the signal within one second, for example, when they block `SIGRTMIN+8`, nothing is modified,
+    /// all parked threads are released and an error is returned. The same applies when a system call
+    /// needed to stop threads fails. Parked threads may see `EINTR` from interrupted syscalls which
+    /// are not restartable.
+    ///
+    /// This method works on every architecture without any breakpoint protocol. [`global_init`] must
+    /// have been called before, otherwise [`StaticKeyError::NotInitialized`] is returned. If the
+    /// instructions cannot be modified, the status of this key and all modified instructions are
+    /// rolled back before releasing threads.
+    #[cfg(target_os = "linux")]
+    pub fn enable_stop_the_world(&self) -> Result<(), StaticKeyError> {
+        static_key_update_stop_the_world(self, true)
+    }
+
+    /// Disable this static key (make the value to be `false`) while all other threads of current process
+    /// are parked. Do nothing if current static key is already disabled.
+    ///
+    /// See [`enable_stop_the_world`][Self::enable_stop_the_world] for details.
+    #[cfg(target_os = "linux")]
+    pub fn disable_stop_the_world(&self) -> Result<(), StaticKeyError> {
+        static_key_update_stop_the_world(self, false)
+    }
+
+    /// Enable this static key (make the value to be `true`), and disable it after `duration`.
+    ///
+    /// See [`enable_until`][Self::enable_until] for details.
+    ///
+    /// # Safety
+    ///
+    /// See [`enable_until`][Self::enable_until].
+    #[cfg(feature = "std")]
+    pub unsafe fn enable_for(&'static self, duration: core::time::Duration) {
+        unsafe { self.enable_until(std::time::Instant::now() + duration) }
+    }
+
+    /// Enable this static key (make the value to be `true`), and disable it at `deadline`.
+    ///
+    /// The disable is applied by a background timer thread. Calling this method again replaces the
+    /// deadline, and any other modification of this static key, such as [`enable`][Self::enable] or
+    /// [`disable`][Self::disable], cancels it. If the instructions cannot be modified at the deadline,
+    /// the disable is retried every second.
+    ///
+    /// On Linux, [`global_init`] must have been called before. On other OSs, it is called before
+    /// modifying instructions if it has not been called yet.
+    ///
+    /// # Safety
+    ///
+    /// On Linux, the instructions are modified in the same way as [`enable_live`][Self::enable_live],
+    /// so this method can be called while other threads are running. On other OSs, never call this method
+    /// or let the deadline pass when other threads may be executing codes in the same code page.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`global_init`] has not been called on Linux, or the instructions cannot be modified
+    /// when enabling.
+    #[cfg(feature = "std")]
+    pub unsafe fn enable_until(&'static self, deadline: std::time::Instant) {
+        if let Err(err) = ensure_global_init_timed() {
+            panic!("Failed to enable static key: {err}");
+        }
+        {
+            let _guard = patch_lock::PatchGuard::lock();
+            let key_addr = self as *const _ as usize;
+            if let Err(err) = unsafe { static_key_update_timed_locked::<M, S>(key_addr, true) } {
+                panic!("Failed to enable static key: {err}");
+            }
+            expiry::register(key_addr, deadline, static_key_update_timed_locked::<M, S>);
+        }
+        timer::wake();
+    }
+
+    /// Remaining time before this static key is disabled by [`enable_for`][Self::enable_for] or
+    /// [`enable_until`][Self::enable_until], or `None` if no disable is scheduled.
+    #[cfg(feature = "std")]
+    pub fn expires_in(&self) -> Option<core::time::Duration> {
+        expiry::deadline(self as *const _ as usize)
+            .map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()))
+    }
+
     /// Get the current status of this static key
     pub fn is_enabled(&self) -> bool {
         self.enabled.load(core::sync::atomic::Ordering::Relaxed)
     }
 }
 
+impl<M: CodeManipulator, const S: bool> core::fmt::Debug for GenericStaticKey<M, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("GenericStaticKey")
+            .field("enabled", &self.is_enabled())
+            .field("initial_enabled", &self.initial)
+            .field("site_count", &self.jump_entries().len())
+            .finish()
+    }
+}
+
 /// Count of jump entries in __static_keys section. Note that
-/// there will be several dummy jump entries inside this section.
+/// there will be several dummy jump entries inside this section. Use [`keys`] to inspect static keys
+/// and their branch sites.
 pub fn jump_entries_count() -> usize {
     let jump_entry_start_addr = &raw mut os::JUMP_ENTRY_START;
     let jump_entry_stop_addr = &raw mut os::JUMP_ENTRY_STOP;
     unsafe { jump_entry_stop_addr.offset_from(jump_entry_start_addr) as usize }
 }
 
+/// All jump entries in __static_keys section, including dummy jump entries.
+fn jump_entries() -> &'static [JumpEntry] {
+    let jump_entry_start_addr = &raw const os::JUMP_ENTRY_START;
+    unsafe { core::slice::from_raw_parts(jump_entry_start_addr, jump_entries_count()) }
+}
+
 // ---------------------------- Create ----------------------------
 /// Global state to make sure [`global_init`] is called only once
 static GLOBAL_INIT_STATE: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
 const UNINITIALIZED: usize = 0;
 const INITIALIZING: usize = 1;
 const INITIALIZED: usize = 2;
+/// The jump entries are read before initialization by one reader. Each additional reader adds 1.
+const READING_UNINITIALIZED: usize = 3;
 
 /// Initialize the static keys data. Always call this method at beginning of application, before using any static key related
 /// functionalities.
 ///
 /// This function should be called only once. If calling this method multiple times in multi-threads, only the first invocation
 /// will take effect.
+///
+/// Modifying a static key calls this function if it has not been called yet. However, modifying a static key while another
+/// thread is still running this function is a misuse, and panics in debug builds.
+///
+/// Static keys defined by [`define_cpu_feature_key`] are enabled here if their CPU features are
+/// available.
+///
+/// # Panics
+///
+/// Panics if the JMP destination of any [`static_branch_likely`] or [`static_branch_unlikely`] is out of
+/// the range of jump instruction on current architecture, such as +/-1MB on riscv64 and +/-128MB on aarch64
+/// and loongarch64. Use [`static_branch_likely_far`] and [`static_branch_unlikely_far`] for such branches.
+/// Also panics if the default target of any [`define_static_call`] is out of the range of its trampoline,
+/// or any arm of a [`static_switch`] is out of the range of jump instruction. Use [`try_global_init`] to
+/// handle such error.
+///
+/// Also panics if the instructions of any [`runtime_const`] are not the expected length, or the
+/// replacement of any [`alternative`] is longer than its original instructions.
 pub fn global_init() {
+    if let Err(err) = try_global_init() {
+        panic!("Failed to initialize static keys: {err}");
+    }
+}
+
+/// Same as [`global_init`], but return [`StaticKeyError::TargetOutOfRange`] instead of panicking if
+/// the JMP destination of any branch, static call or static switch is out of range.
+///
+/// The JMP destinations are checked before modifying anything, so nothing is initialized on error,
+/// and static keys can never be modified. Functions calling [`global_init`] lazily, such as
+/// [`try_enable`][GenericStaticKey::try_enable], return the same error, and introspection
+/// functions, such as [`keys`], yield nothing.
+///
+/// # Panics
+///
+/// Panics if the instructions of any [`runtime_const`] are not the expected length, or the
+/// replacement of any [`alternative`] is longer than its original instructions.
+pub fn try_global_init() -> Result<(), StaticKeyError> {
     // DUMMY_STATIC_KEY will never changed, and this will always be a NOP.
     // Doing this to make sure there are at least one jump entry.
     if static_branch_unlikely!(DUMMY_STATIC_KEY) {
-        return;
+        return Ok(());
     }
 
     // This logic is taken from log::set_logger_inner
-    match GLOBAL_INIT_STATE.compare_exchange(
-        UNINITIALIZED,
-        INITIALIZING,
-        core::sync::atomic::Ordering::Acquire,
-        core::sync::atomic::Ordering::Relaxed,
-    ) {
-        Ok(UNINITIALIZED) => {
-            global_init_inner();
-            GLOBAL_INIT_STATE.store(INITIALIZED, core::sync::atomic::Ordering::Release);
-            // Successful init
+    loop {
+        match GLOBAL_INIT_STATE.compare_exchange(
+            UNINITIALIZED,
+            INITIALIZING,
+            core::sync::atomic::Ordering::Acquire,
+            core::sync::atomic::Ordering::Relaxed,
+        ) {
+            Ok(_) => {
+                if let Err(err) = check_jump_targets() {
+                    // Nothing is modified, so later calls fail in the same way
+                    GLOBAL_INIT_STATE.store(UNINITIALIZED, core::sync::atomic::Ordering::Release);
+                    return Err(err);
+                }
+                patch_lock::register_fork_handlers();
+                #[cfg(target_os = "linux")]
+                os::register_sync_core();
+                global_init_inner();
+                GLOBAL_INIT_STATE.store(INITIALIZED, core::sync::atomic::Ordering::Release);
+                // Successful init
+                return Ok(());
+            }
+            Err(INITIALIZED) => {
+                // Other has inited
+                return Ok(());
+            }
+            Err(_) => {
+                // Wait for other initializing, or reading uninitialized jump entries
+                while !matches!(
+                    GLOBAL_INIT_STATE.load(core::sync::atomic::Ordering::Relaxed),
+                    UNINITIALIZED | INITIALIZED
+                ) {
+                    core::hint::spin_loop();
+                }
+            }
         }
-        Err(INITIALIZING) => {
-            while GLOBAL_INIT_STATE.load(core::sync::atomic::Ordering::Relaxed) == INITIALIZING {
-                core::hint::spin_loop();
+    }
+}
+
+/// Guard preventing [`global_init`] from running, so the jump entries keep relative addresses
+/// while being read. [`global_init`] waits until all guards are dropped.
+struct UninitializedGuard {
+    /// Make sure this guard can only be created by [`UninitializedGuard::acquire`]
+    _private: (),
+}
+
+impl UninitializedGuard {
+    /// Acquire the guard if [`global_init`] has not been called yet. Return `None` if it has
+    /// finished, and wait for it if it is running.
+    fn acquire() -> Option<Self> {
+        loop {
+            let res = GLOBAL_INIT_STATE.fetch_update(
+                core::sync::atomic::Ordering::Acquire,
+                core::sync::atomic::Ordering::Relaxed,
+                |state| match state {
+                    UNINITIALIZED => Some(READING_UNINITIALIZED),
+                    INITIALIZING | INITIALIZED => None,
+                    readers => Some(readers + 1),
+                },
+            );
+            match res {
+                Ok(_) => return Some(Self { _private: () }),
+                Err(INITIALIZED) => return None,
+                Err(_) => core::hint::spin_loop(),
             }
-            // Other has inited
         }
-        _ => {
-            // Other has inited
+    }
+}
+
+impl Drop for UninitializedGuard {
+    fn drop(&mut self) {
+        let _ = GLOBAL_INIT_STATE.fetch_update(
+            core::sync::atomic::Ordering::Release,
+            core::sync::atomic::Ordering::Relaxed,
+            |state| match state {
+                READING_UNINITIALIZED => Some(UNINITIALIZED),
+                readers => Some(readers - 1),
+            },
+        );
+    }
+}
+
+/// Whether [`global_init`] has finished
+pub(crate) fn is_global_init_done() -> bool {
+    GLOBAL_INIT_STATE.load(core::sync::atomic::Ordering::Acquire) == INITIALIZED
+}
+
+/// Make sure [`global_init`] has been called before modifying static keys.
+///
+/// Otherwise, the jump entries of static keys are not initialized, and modifying a static key would
+/// only change its status without modifying any instruction. Return the error of
+/// [`try_global_init`] if it fails.
+pub(crate) fn ensure_global_init() -> Result<(), StaticKeyError> {
+    match GLOBAL_INIT_STATE.load(core::sync::atomic::Ordering::Acquire) {
+        INITIALIZED => Ok(()),
+        INITIALIZING => {
+            // If it is the current thread initializing, waiting for it will never return. The only
+            // legitimate case is racing with another thread calling `global_init`, which should
+            // have been called at the beginning of application.
+            debug_assert!(
+                false,
+                "Static keys must not be modified while global_init is running."
+            );
+            try_global_init()
+        }
+        _ => try_global_init(),
+    }
+}
+
+/// Make sure [`global_init`] has finished before modifying static keys while other threads may be
+/// running.
+///
+/// Unlike [`ensure_global_init`], [`global_init`] is never called lazily here, since it modifies
+/// instructions without synchronizing with other threads.
+#[cfg(target_os = "linux")]
+pub(crate) fn require_global_init() -> Result<(), StaticKeyError> {
+    if is_global_init_done() {
+        Ok(())
+    } else {
+        Err(StaticKeyError::NotInitialized)
+    }
+}
+
+/// Make sure [`global_init`] has finished before modifying static keys by
+/// [`static_key_update_timed_locked`].
+///
+/// On Linux, the instructions are modified live, so it is the same as [`require_global_init`].
+/// Otherwise, it is the same as [`ensure_global_init`].
+pub(crate) fn ensure_global_init_timed() -> Result<(), StaticKeyError> {
+    #[cfg(target_os = "linux")]
+    let res = require_global_init();
+    #[cfg(not(target_os = "linux"))]
+    let res = ensure_global_init();
+    res
+}
+
+/// Check the JMP destinations of all jump entries, static calls and static switches before
+/// [`global_init_inner`] modifies anything.
+fn check_jump_targets() -> Result<(), StaticKeyError> {
+    for jump_entry in jump_entries() {
+        if jump_entry.is_dummy() {
+            continue;
+        }
+        let jump_entry = jump_entry.to_absolute();
+        if jump_entry.is_key_aligned() && !arch::arch_jump_target_in_range(&jump_entry) {
+            return Err(StaticKeyError::TargetOutOfRange {
+                site: jump_entry.code_addr(),
+                target: jump_entry.target_addr(),
+            });
         }
     }
+    static_call::check_targets()?;
+    static_switch::check_targets()
 }
 
-/// Inner function to [`global_init`]
+/// Inner function to [`global_init`]. The JMP destinations are checked by [`check_jump_targets`]
+/// before.
 fn global_init_inner() {
     let jump_entry_start_addr = &raw mut os::JUMP_ENTRY_START;
     let jump_entry_stop_addr = &raw mut os::JUMP_ENTRY_STOP;
@@ -259,12 +724,16 @@ fn global_init_inner() {
         }
         jump_entry.make_relative_address_absolute();
     }
+    static_call::global_init();
+    static_switch::global_init();
+    runtime_const::global_init();
+    alternative::global_init();
     // The jump entries are sorted by key address and code address
     jump_entries.sort_unstable_by_key(|jump_entry| (jump_entry.key_addr(), jump_entry.code_addr()));
     // Update associated static keys
     let mut last_key_addr = 0;
     for jump_entry in jump_entries {
-        if jump_entry.is_dummy() {
+        if jump_entry.is_dummy() || !jump_entry.is_key_aligned() {
             continue;
         }
         let key_addr = jump_entry.key_addr();
@@ -278,6 +747,18 @@ fn global_init_inner() {
         key.entries = entries_start_addr;
         last_key_addr = key_addr;
     }
+    cpu_feature::global_init();
+}
+
+/// Create a new static key with `S` as initial value, whose instructions are modified by `M`.
+///
+/// This method should be called to initialize a static mut static key. It is UB to use this method
+/// to create a static key on stack or heap, and use this static key to control branches.
+///
+/// Use [`new_static_false_key`] and [`new_static_true_key`] for static keys with the default
+/// code manipulator.
+pub const fn new_static_key<M: CodeManipulator, const S: bool>() -> GenericStaticKey<M, S> {
+    GenericStaticKey::new(S)
 }
 
 /// Create a new static key with `false` as initial value.
@@ -305,18 +786,40 @@ pub const fn new_static_true_key() -> StaticTrueKey {
 /// This macro will define a static mut variable without documentations and visibility modifiers.
 /// Use [`new_static_false_key`] for customization.
 ///
+/// An optional name can be given to find the static key in [`registry`]. By default, the name is
+/// the module path and identifier of the static key, such as `my_crate::MY_FALSE_STATIC_KEY`.
+/// Names must not be empty, start or end with whitespaces, or contain `*`, `?`, `,`, `=`, `#` and
+/// `"`, which are special in wildcard patterns and configurations.
+///
 /// # Usage
 ///
 /// ```rust
 /// use static_keys::define_static_key_false;
 ///
 /// define_static_key_false!(MY_FALSE_STATIC_KEY);
+/// define_static_key_false!(MY_NAMED_FALSE_STATIC_KEY, "net.tcp.trace");
+/// ```
+///
+/// Invalid names are rejected at compile time:
+///
+/// ```compile_fail
+/// use static_keys::define_static_key_false;
+///
+/// define_static_key_false!(MY_FALSE_STATIC_KEY, "net.*.trace");
 /// ```
 #[macro_export]
 macro_rules! define_static_key_false {
     ($key: ident) => {
+        $crate::define_static_key_false!(
+            $key,
+            ::core::concat!(::core::module_path!(), "::", ::core::stringify!($key))
+        );
+    };
+    ($key: ident, $name: expr) => {
         #[used]
+        #[unsafe(link_section = $crate::os_static_key_data_sec_name_attr!())]
         static $key: $crate::StaticFalseKey = $crate::new_static_false_key();
+        $crate::static_key_name!($key, $name);
     };
 }
 
@@ -325,62 +828,178 @@ macro_rules! define_static_key_false {
 /// This macro will define a static mut variable without documentations and visibility modifiers.
 /// Use [`new_static_true_key`] for customization.
 ///
+/// An optional name can be given to find the static key in [`registry`]. By default, the name is
+/// the module path and identifier of the static key, such as `my_crate::MY_TRUE_STATIC_KEY`.
+/// Names must not be empty, start or end with whitespaces, or contain `*`, `?`, `,`, `=`, `#` and
+/// `"`, which are special in wildcard patterns and configurations.
+///
 /// # Usage
 ///
 /// ```rust
 /// use static_keys::define_static_key_true;
 ///
 /// define_static_key_true!(MY_TRUE_STATIC_KEY);
+/// define_static_key_true!(MY_NAMED_TRUE_STATIC_KEY, "db.slow_log");
 /// ```
 #[macro_export]
 macro_rules! define_static_key_true {
     ($key: ident) => {
+        $crate::define_static_key_true!(
+            $key,
+            ::core::concat!(::core::module_path!(), "::", ::core::stringify!($key))
+        );
+    };
+    ($key: ident, $name: expr) => {
         #[used]
+        #[unsafe(link_section = $crate::os_static_key_data_sec_name_attr!())]
         static $key: $crate::StaticTrueKey = $crate::new_static_true_key();
+        $crate::static_key_name!($key, $name);
+    };
+}
+
+/// Record the name of a static key in the companion section of `__static_keys`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! static_key_name {
+    ($key: ident, $name: expr) => {
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = $crate::os_static_key_name_sec_name_attr!())]
+            static STATIC_KEY_NAME: $crate::KeyNameEntry = $crate::KeyNameEntry::new(&$key, $name);
+        };
     };
 }
 
 // ---------------------------- Update ----------------------------
 /// The internal method used for [`GenericStaticKey::enable`] and [`GenericStaticKey::disable`].
 ///
-/// This method will update instructions recorded in each jump entries that associated with thie static key
+/// This method will update instructions recorded in each jump entries that associated with thie static key.
+/// [`global_init`] is called first if not called yet.
 ///
 /// # Safety
 ///
-/// This method may be UB if called before [`global_init`] or called in parallel. Never call this method when
-/// there are multi-threads running. Spawn threads after this method is called. This method may manipulate
-/// code region memory protection, and if other threads are executing codes in the same code page, it may
-/// lead to unexpected behaviors.
+/// Never call this method when other threads may be executing codes in the same code page. This method may manipulate code region memory protection, and
+/// it may lead to unexpected behaviors.
 unsafe fn static_key_update<M: CodeManipulator, const S: bool>(
     key: &GenericStaticKey<M, S>,
     enabled: bool,
-) {
+) -> Result<(), StaticKeyError> {
+    ensure_global_init()?;
+    let _guard = patch_lock::PatchGuard::lock();
+    #[cfg(feature = "std")]
+    expiry::cancel(key as *const _ as usize);
+    unsafe { static_key_update_locked(key, enabled) }
+}
+
+/// Same as [`static_key_update`], but the patch lock is already held.
+///
+/// The update is a transaction with only one static key, so instructions in the same code page
+/// are written at once, and rolled back on failure.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`][patch_lock::PatchGuard] held. See [`static_key_update`] for other
+/// requirements.
+unsafe fn static_key_update_locked<M: CodeManipulator, const S: bool>(
+    key: &GenericStaticKey<M, S>,
+    enabled: bool,
+) -> Result<(), StaticKeyError> {
     if key.enabled.load(core::sync::atomic::Ordering::Relaxed) == enabled {
-        return;
+        return Ok(());
     }
-    key.enabled
-        .store(enabled, core::sync::atomic::Ordering::Relaxed);
-    let jump_entry_stop_addr = &raw const os::JUMP_ENTRY_STOP;
-    let mut jump_entry_addr = key.entries();
-    if jump_entry_addr.is_null() {
-        // This static key is never used
-        return;
+    unsafe { transaction::apply_changes::<M>(&[transaction::KeyChange::new(key, enabled)]) }
+}
+
+/// The internal method used for [`GenericStaticKey::enable_live`] and [`GenericStaticKey::disable_live`].
+///
+/// Live updates are serialized with each other, so this method can be called in parallel.
+#[cfg(target_os = "linux")]
+fn static_key_update_live<M: CodeManipulator, const S: bool>(
+    key: &GenericStaticKey<M, S>,
+    enabled: bool,
+) -> Result<(), StaticKeyError> {
+    require_global_init()?;
+    let _guard = patch_lock::PatchGuard::lock();
+    #[cfg(feature = "std")]
+    expiry::cancel(key as *const _ as usize);
+    unsafe { static_key_update_live_locked(key, enabled) }
+}
+
+/// Same as [`static_key_update_live`], but the patch lock is already held.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`][patch_lock::PatchGuard] held.
+#[cfg(target_os = "linux")]
+unsafe fn static_key_update_live_locked<M: CodeManipulator, const S: bool>(
+    key: &GenericStaticKey<M, S>,
+    enabled: bool,
+) -> Result<(), StaticKeyError> {
+    if key.enabled.load(core::sync::atomic::Ordering::Relaxed) == enabled {
+        return Ok(());
     }
-    loop {
-        if jump_entry_addr >= jump_entry_stop_addr {
-            break;
-        }
-        let jump_entry = unsafe { &*jump_entry_addr };
-        // Not the same key
-        if key as *const _ as usize != jump_entry.key_addr() {
-            break;
-        }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    if !os::is_sync_core_registered() {
+        return unsafe {
+            os::stop_the_world(|| {
+                // No other thread is running now
+                static_key_update_locked(key, enabled)
+            })
+        }?;
+    }
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    let res = unsafe { os::text_poke_bp(key, enabled) };
+    // The code manipulator issues the core serializing barrier after modifying each code page
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    let res = unsafe { static_key_update_locked(key, enabled) };
+    res
+}
 
-        unsafe {
-            jump_entry_update::<M>(jump_entry, enabled);
-            jump_entry_addr = jump_entry_addr.add(1);
-        }
+/// Update the static key at `key_addr`, when other threads may be running. Used by static keys
+/// modified in background, such as deferred static keys.
+///
+/// On Linux, the instructions are modified live. Otherwise, it is the same as [`static_key_update_locked`].
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`][patch_lock::PatchGuard] held, and `key_addr` is a
+/// `GenericStaticKey<M, S>` or starts with one. On other OSs than Linux, see [`static_key_update`] for
+/// other requirements.
+unsafe fn static_key_update_timed_locked<M: CodeManipulator, const S: bool>(
+    key_addr: usize,
+    enabled: bool,
+) -> Result<(), StaticKeyError> {
+    let key = unsafe { &*(key_addr as *const GenericStaticKey<M, S>) };
+    #[cfg(target_os = "linux")]
+    let res = unsafe { static_key_update_live_locked(key, enabled) };
+    #[cfg(not(target_os = "linux"))]
+    let res = unsafe { static_key_update_locked(key, enabled) };
+    res
+}
+
+/// The internal method used for [`GenericStaticKey::enable_stop_the_world`] and
+/// [`GenericStaticKey::disable_stop_the_world`].
+///
+/// Stop-the-world updates are serialized with each other and with live updates, so this method
+/// can be called in parallel.
+#[cfg(target_os = "linux")]
+fn static_key_update_stop_the_world<M: CodeManipulator, const S: bool>(
+    key: &GenericStaticKey<M, S>,
+    enabled: bool,
+) -> Result<(), StaticKeyError> {
+    require_global_init()?;
+    let _guard = patch_lock::PatchGuard::lock();
+    #[cfg(feature = "std")]
+    expiry::cancel(key as *const _ as usize);
+    if key.enabled.load(core::sync::atomic::Ordering::Relaxed) == enabled {
+        return Ok(());
     }
+    unsafe {
+        os::stop_the_world(|| {
+            // No other thread is running now
+            static_key_update_locked(key, enabled)
+        })
+    }?
 }
 
 /// Type of the instructions to be modified
@@ -392,27 +1011,23 @@ enum JumpLabelType {
     Jmp = 1,
 }
 
-/// Update instruction recorded in a single jump entry. This is where magic happens
-///
-/// # Safety
-///
-/// This method may be UB if called in parallel. Never call this method when
-/// there are multi-threads running. Spawn threads after this method is called. This method may manipulate
-/// code region memory protection, and if other threads are executing codes in the same code page, it may
-/// lead to unexpected behaviors.
-unsafe fn jump_entry_update<M: CodeManipulator>(jump_entry: &JumpEntry, enabled: bool) {
-    let jump_label_type = if enabled ^ jump_entry.likely_branch_is_true() {
+/// Type of the instruction at given jump entry when its static key has given status
+fn jump_entry_label_type(jump_entry: &JumpEntry, enabled: bool) -> JumpLabelType {
+    if enabled ^ jump_entry.likely_branch_is_true() {
         JumpLabelType::Jmp
     } else {
         JumpLabelType::Nop
-    };
-    let code_bytes = arch::arch_jump_entry_instruction(jump_label_type, jump_entry);
-
-    unsafe {
-        M::write_code(jump_entry.code_addr() as *mut _, &code_bytes);
     }
 }
 
+/// Instruction at given jump entry when its static key has given status
+fn jump_entry_instruction(
+    jump_entry: &JumpEntry,
+    enabled: bool,
+) -> [u8; arch::ARCH_JUMP_INS_LENGTH] {
+    arch::arch_jump_entry_instruction(jump_entry_label_type(jump_entry, enabled), jump_entry)
+}
+
 // ---------------------------- Use ----------------------------
 /// With given branch as likely branch, initialize the instruction here as JMP instruction
 #[doc(hidden)]
@@ -476,13 +1091,63 @@ macro_rules! static_key_init_nop_with_given_branch_likely {
     }};
 }
 
+/// With given branch as likely branch, initialize the far instruction here as JMP instruction
+#[doc(hidden)]
+#[macro_export]
+macro_rules! static_key_init_far_jmp_with_given_branch_likely {
+    ($key:path, $branch:expr) => {'my_label: {
+        // The JMP instruction on x86 and x86_64 already covers all destinations
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        break 'my_label $crate::static_key_init_jmp_with_given_branch_likely! { $key, $branch };
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        ::core::arch::asm!(
+            $crate::arch_static_key_init_far_jmp_asm_template!(),
+            label {
+                break 'my_label !$branch;
+            },
+            sym $key,
+            const $branch as usize | 2,
+            out(reg) _,
+        );
+
+        // This branch will be adjcent to the NOP/JMP instruction
+        #[allow(unreachable_code)]
+        break 'my_label $branch;
+    }};
+}
+
+/// With given branch as likely branch, initialize the far instruction here as NOP instruction
+#[doc(hidden)]
+#[macro_export]
+macro_rules! static_key_init_far_nop_with_given_branch_likely {
+    ($key:path, $branch:expr) => {'my_label: {
+        // The JMP instruction on x86 and x86_64 already covers all destinations
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        break 'my_label $crate::static_key_init_nop_with_given_branch_likely! { $key, $branch };
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        ::core::arch::asm!(
+            $crate::arch_static_key_init_far_nop_asm_template!(),
+            label {
+                break 'my_label !$branch;
+            },
+            sym $key,
+            const $branch as usize | 2,
+            out(reg) _,
+        );
+
+        // This branch will be adjcent to the NOP/JMP instruction
+        #[allow(unreachable_code)]
+        break 'my_label $branch;
+    }};
+}
+
 /// Use this in a `if` condition, just like the common [`likely`][core::intrinsics::likely]
 /// and [`unlikely`][core::intrinsics::unlikely] intrinsics
 #[macro_export]
 macro_rules! static_branch_unlikely {
     ($key:path) => {{
         unsafe {
-            if $key.initial_enabled() {
+            if const { $key.initial_enabled() } {
                 $crate::static_key_init_jmp_with_given_branch_likely! { $key, false }
             } else {
                 $crate::static_key_init_nop_with_given_branch_likely! { $key, false }
@@ -497,7 +1162,7 @@ macro_rules! static_branch_unlikely {
 macro_rules! static_branch_likely {
     ($key:path) => {{
         unsafe {
-            if $key.initial_enabled() {
+            if const { $key.initial_enabled() } {
                 $crate::static_key_init_nop_with_given_branch_likely! { $key, true }
             } else {
                 $crate::static_key_init_jmp_with_given_branch_likely! { $key, true }
@@ -505,3 +1170,41 @@ macro_rules! static_branch_likely {
         }
     }};
 }
+
+/// Same as [`static_branch_unlikely`], but the branch can be arbitrarily far away from the
+/// condition, for very large functions.
+///
+/// On riscv64, aarch64 and loongarch64, the jump instruction only covers +/-1MB or +/-128MB, and
+/// [`global_init`] panics if a branch of [`static_branch_unlikely`] is out of range. Here, the
+/// address of the branch is loaded to a scratch register by two extra instructions, such as
+/// `auipc`+`addi` on riscv64, and the modified instruction jumps to the register. On x86 and x86_64,
+/// this is the same as [`static_branch_unlikely`].
+#[macro_export]
+macro_rules! static_branch_unlikely_far {
+    ($key:path) => {{
+        unsafe {
+            if const { $key.initial_enabled() } {
+                $crate::static_key_init_far_jmp_with_given_branch_likely! { $key, false }
+            } else {
+                $crate::static_key_init_far_nop_with_given_branch_likely! { $key, false }
+            }
+        }
+    }};
+}
+
+/// Same as [`static_branch_likely`], but the branch can be arbitrarily far away from the
+/// condition, for very large functions.
+///
+/// See [`static_branch_unlikely_far`] for details.
+#[macro_export]
+macro_rules! static_branch_likely_far {
+    ($key:path) => {{
+        unsafe {
+            if const { $key.initial_enabled() } {
+                $crate::static_key_init_far_nop_with_given_branch_likely! { $key, true }
+            } else {
+                $crate::static_key_init_far_jmp_with_given_branch_likely! { $key, true }
+            }
+        }
+    }};
+}
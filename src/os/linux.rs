@@ -1,6 +1,22 @@
 //! Linux-specific implementations
 
-use crate::{JumpEntry, code_manipulate::CodeManipulator};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    JumpEntry,
+    alternative::AlternativeEntry,
+    code_manipulate::{CodeManipulateError, CodeManipulator},
+    cpu_feature::CpuFeatureEntry,
+    registry::KeyNameEntry,
+};
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod text_poke;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub use text_poke::*;
+
+mod stop_the_world;
+pub use stop_the_world::*;
 
 // See https://sourceware.org/binutils/docs/as/Section.html
 /// Name and attribute of section storing jump entries
@@ -12,6 +28,89 @@ macro_rules! os_static_key_sec_name_attr {
     };
 }
 
+/// Name and attribute of the companion section storing names of static keys
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_key_name_sec_name_attr {
+    () => {
+        "__static_key_names"
+    };
+}
+
+/// Name and attribute of section storing CPU features of static keys
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_cpu_feature_sec_name_attr {
+    () => {
+        "__static_cpu_features"
+    };
+}
+
+/// Name and attribute of section storing entries of static call trampolines
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_call_sec_name_attr {
+    () => {
+        "__static_calls, \"awR\""
+    };
+}
+
+/// Name and attribute of section storing entries of static switch sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_switch_sec_name_attr {
+    () => {
+        "__static_switches, \"awR\""
+    };
+}
+
+/// Name and attribute of section storing the relative addresses of arms of static switch sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_switch_table_sec_name_attr {
+    () => {
+        ".rodata.static_switch_tables, \"a\""
+    };
+}
+
+/// Name and attribute of section storing entries of runtime constant sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_runtime_const_sec_name_attr {
+    () => {
+        "__runtime_consts, \"awR\""
+    };
+}
+
+/// Name and attribute of section storing entries of alternative instructions
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_alternative_sec_name_attr {
+    () => {
+        "__static_alternatives, \"awR\""
+    };
+}
+
+/// Name and attribute of section storing replacement instructions of alternatives
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_alternative_replacement_sec_name_attr {
+    () => {
+        ".rodata.static_alternatives, \"a\""
+    };
+}
+
+/// Name and attribute of the section storing static keys defined by macros. Static keys are always
+/// placed in a data section, even if they are zero-initialized, so that their status can be
+/// modified in the file.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_key_data_sec_name_attr {
+    () => {
+        ".data.static_keys"
+    };
+}
+
 // See https://sourceware.org/binutils/docs/ld/Input-Section-Example.html, modern linkers
 // will generate these two symbols indicating the start and end address of __static_keys
 // section. Note that the end address is excluded.
@@ -24,70 +123,266 @@ unsafe extern "Rust" {
     pub static mut JUMP_ENTRY_STOP: JumpEntry;
 }
 
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_key_names section
+    #[link_name = "__start___static_key_names"]
+    pub static KEY_NAME_ENTRY_START: KeyNameEntry;
+    /// Address of this static is the end address of __static_key_names section (excluded)
+    #[link_name = "__stop___static_key_names"]
+    pub static KEY_NAME_ENTRY_STOP: KeyNameEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_cpu_features section
+    #[link_name = "__start___static_cpu_features"]
+    pub static CPU_FEATURE_ENTRY_START: CpuFeatureEntry;
+    /// Address of this static is the end address of __static_cpu_features section (excluded)
+    #[link_name = "__stop___static_cpu_features"]
+    pub static CPU_FEATURE_ENTRY_STOP: CpuFeatureEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_calls section
+    #[link_name = "__start___static_calls"]
+    pub static mut STATIC_CALL_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __static_calls section (excluded)
+    #[link_name = "__stop___static_calls"]
+    pub static mut STATIC_CALL_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_switches section
+    #[link_name = "__start___static_switches"]
+    pub static mut SWITCH_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __static_switches section (excluded)
+    #[link_name = "__stop___static_switches"]
+    pub static mut SWITCH_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __runtime_consts section
+    #[link_name = "__start___runtime_consts"]
+    pub static mut RUNTIME_CONST_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __runtime_consts section (excluded)
+    #[link_name = "__stop___runtime_consts"]
+    pub static mut RUNTIME_CONST_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_alternatives section
+    #[link_name = "__start___static_alternatives"]
+    pub static mut ALTERNATIVE_ENTRY_START: AlternativeEntry;
+    /// Address of this static is the end address of __static_alternatives section (excluded)
+    #[link_name = "__stop___static_alternatives"]
+    pub static mut ALTERNATIVE_ENTRY_STOP: AlternativeEntry;
+}
+
+// The process-wide patch lock, see crate::patch_lock. Defined as weak symbol, so that all copies
+// of this crate share the same definition.
+core::arch::global_asm!(
+    ".pushsection .data.static_keys_patch_lock_v1,\"aw\"",
+    ".weak static_keys_patch_lock_v1",
+    ".type static_keys_patch_lock_v1, %object",
+    ".size static_keys_patch_lock_v1, 16",
+    ".balign 8",
+    "static_keys_patch_lock_v1:",
+    ".zero 16",
+    ".popsection",
+);
+
+/// Whether current process has registered for `MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE`
+static SYNC_CORE_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// Register for `MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE`, which is required before issuing
+/// such barrier. Called in [`global_init`][crate::global_init].
+///
+/// The registration fails on kernels older than 4.16, or on architectures not supporting it
+/// (riscv64 before 6.9 and loongarch64 for now).
+pub fn register_sync_core() {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_membarrier,
+            libc::MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE,
+            0,
+        )
+    };
+    SYNC_CORE_REGISTERED.store(res == 0, Ordering::Release);
+}
+
+/// Whether [`sync_core`] takes effect
+pub fn is_sync_core_registered() -> bool {
+    SYNC_CORE_REGISTERED.load(Ordering::Acquire)
+}
+
+/// Make sure all threads of current process execute a core serializing instruction before
+/// returning to user space, so that no core will execute stale instructions after this function.
+///
+/// Do nothing if [`register_sync_core`] failed.
+pub fn sync_core() {
+    if !is_sync_core_registered() {
+        return;
+    }
+    unsafe {
+        libc::syscall(
+            libc::SYS_membarrier,
+            libc::MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE,
+            0,
+        );
+    }
+}
+
+/// Yield the processor to other threads
+pub fn yield_now() {
+    unsafe {
+        libc::sched_yield();
+    }
+}
+
+/// Whether `addr` resides in an executable segment of any loaded ELF object
+pub fn is_executable(addr: usize) -> bool {
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        _size: libc::size_t,
+        data: *mut core::ffi::c_void,
+    ) -> libc::c_int {
+        let addr = unsafe { *data.cast::<usize>() };
+        let info = unsafe { &*info };
+        let phdrs =
+            unsafe { core::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize) };
+        let found = phdrs.iter().any(|phdr| {
+            let start = (info.dlpi_addr as usize).wrapping_add(phdr.p_vaddr as usize);
+            phdr.p_type == libc::PT_LOAD
+                && phdr.p_flags & libc::PF_X != 0
+                && (start..start.wrapping_add(phdr.p_memsz as usize)).contains(&addr)
+        });
+        // Non-zero return value stops the iteration
+        found as libc::c_int
+    }
+
+    let mut addr = addr;
+    unsafe { libc::dl_iterate_phdr(Some(callback), (&raw mut addr).cast()) != 0 }
+}
+
 /// Arch-specific [`CodeManipulator`] using [`libc`] with `mprotect`.
 pub struct ArchCodeManipulator;
 
-impl CodeManipulator for ArchCodeManipulator {
-    /// Due to limitation of Linux, we cannot get the original memory protection flags easily
-    /// without parsing `/proc/[pid]/maps`. As a result, we just make the code region non-writable.
-    unsafe fn write_code<const L: usize>(addr: *mut core::ffi::c_void, data: &[u8; L]) {
-        // TODO: page_size can be initialized once
-        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
-        let aligned_addr_val = (addr as usize) / page_size * page_size;
-        let aligned_addr = aligned_addr_val as *mut core::ffi::c_void;
-        let aligned_length = if (addr as usize) + L - aligned_addr_val > page_size {
-            page_size * 2
-        } else {
-            page_size
-        };
+/// Error code of last failed libc function
+fn errno() -> i32 {
+    unsafe { *libc::__errno_location() }
+}
 
-        // Create a temp mmap, which will store updated content of corresponding pages
-        let mmaped_addr = unsafe {
-            libc::mmap(
-                core::ptr::null_mut(),
-                aligned_length,
-                libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
-                -1,
-                0,
-            )
-        };
-        if mmaped_addr == libc::MAP_FAILED {
-            panic!("Failed to create temp mappings");
-        }
-        unsafe {
+/// Replace the code pages covering all `patches` with a copy where `patches` are written.
+///
+/// `patches` should be sorted, non-empty, and reside in the same page. A patch crossing the
+/// page boundary makes the next page covered too.
+unsafe fn remap_code<const L: usize>(
+    page_size: usize,
+    patches: &[(*mut core::ffi::c_void, [u8; L])],
+) -> Result<(), CodeManipulateError> {
+    let start = patches[0].0 as usize;
+    let end = patches[patches.len() - 1].0 as usize + L;
+    let aligned_addr_val = start / page_size * page_size;
+    let aligned_addr = aligned_addr_val as *mut core::ffi::c_void;
+    let aligned_length = (end - aligned_addr_val).div_ceil(page_size) * page_size;
+
+    // Create a temp mmap, which will store updated content of corresponding pages
+    let mmaped_addr = unsafe {
+        libc::mmap(
+            core::ptr::null_mut(),
+            aligned_length,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if mmaped_addr == libc::MAP_FAILED {
+        return Err(CodeManipulateError {
+            syscall: "mmap",
+            errno: errno(),
+        });
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(aligned_addr, mmaped_addr, aligned_length);
+        for (addr, data) in patches {
             let addr_in_mmap = mmaped_addr.offset(addr.offset_from(aligned_addr));
-            core::ptr::copy_nonoverlapping(aligned_addr, mmaped_addr, aligned_length);
             core::ptr::copy_nonoverlapping(data.as_ptr(), addr_in_mmap.cast(), L);
         }
-        let res = unsafe {
-            libc::mprotect(
-                mmaped_addr,
-                aligned_length,
-                libc::PROT_READ | libc::PROT_EXEC,
-            )
+    }
+    let res = unsafe {
+        libc::mprotect(
+            mmaped_addr,
+            aligned_length,
+            libc::PROT_READ | libc::PROT_EXEC,
+        )
+    };
+    if res != 0 {
+        let err = CodeManipulateError {
+            syscall: "mprotect",
+            errno: errno(),
         };
-        if res != 0 {
-            panic!("Unable to make mmaped mapping executable.");
+        unsafe {
+            libc::munmap(mmaped_addr, aligned_length);
         }
-        // Remap the created temp mmaping to replace old mapping
-        let res = unsafe {
-            libc::mremap(
-                mmaped_addr,
-                aligned_length,
-                aligned_length,
-                libc::MREMAP_MAYMOVE | libc::MREMAP_FIXED,
-                // Any previous mapping at the address range specified by new_address and new_size is unmapped.
-                // So, no memory leak
-                aligned_addr,
-            )
+        return Err(err);
+    }
+    // Remap the created temp mmaping to replace old mapping
+    let res = unsafe {
+        libc::mremap(
+            mmaped_addr,
+            aligned_length,
+            aligned_length,
+            libc::MREMAP_MAYMOVE | libc::MREMAP_FIXED,
+            // Any previous mapping at the address range specified by new_address and new_size is unmapped.
+            // So, no memory leak
+            aligned_addr,
+        )
+    };
+    if res == libc::MAP_FAILED {
+        let err = CodeManipulateError {
+            syscall: "mremap",
+            errno: errno(),
         };
-        if res == libc::MAP_FAILED {
-            panic!("Failed to mremap.");
+        unsafe {
+            libc::munmap(mmaped_addr, aligned_length);
         }
-        let res = unsafe { clear_cache::clear_cache(addr, addr.add(L)) };
-        if !res {
-            panic!("Failed to clear cache.");
+        return Err(err);
+    }
+    let res = unsafe { clear_cache::clear_cache(start as *const u8, end as *const u8) };
+    if !res {
+        return Err(CodeManipulateError {
+            syscall: "clear_cache",
+            errno: 0,
+        });
+    }
+    Ok(())
+}
+
+impl CodeManipulator for ArchCodeManipulator {
+    /// Due to limitation of Linux, we cannot get the original memory protection flags easily
+    /// without parsing `/proc/[pid]/maps`. As a result, we just make the code region non-writable.
+    unsafe fn write_code<const L: usize>(
+        addr: *mut core::ffi::c_void,
+        data: &[u8; L],
+    ) -> Result<(), CodeManipulateError> {
+        unsafe { Self::write_code_batch(&[(addr, *data)]) }
+    }
+
+    /// Patches in the same page are written with a single remapping of that page.
+    unsafe fn write_code_batch<const L: usize>(
+        patches: &[(*mut core::ffi::c_void, [u8; L])],
+    ) -> Result<(), CodeManipulateError> {
+        // TODO: page_size can be initialized once
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+        for page_patches in patches.chunk_by(|(first, _), (next, _)| {
+            (*first as usize) / page_size == (*next as usize) / page_size
+        }) {
+            unsafe { remap_code(page_size, page_patches)? };
         }
+        // On weakly ordered architectures, clearing cache does not stop other cores from executing
+        // stale instructions. The x86 breakpoint protocol synchronizes cores itself.
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        sync_core();
+        Ok(())
     }
 }
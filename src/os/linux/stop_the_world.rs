@@ -0,0 +1,482 @@
+//! Stop-the-world patching by parking all other threads of current process.
+//!
+//! Each thread listed in `/proc/self/task` is sent a signal by `tgkill`, whose handler parks the
+//! thread on a futex until the patching is done. Since no other thread is running when the code is
+//! modified, this approach works for every architecture without any breakpoint protocol.
+//!
+//! Everything here avoids heap allocation, since a parked thread may hold the lock of allocator.
+
+use core::sync::atomic::{AtomicI32, AtomicPtr, AtomicU8, AtomicU32, AtomicUsize, Ordering};
+
+/// Timeout for all threads to acknowledge the stop request
+const STOP_THE_WORLD_TIMEOUT_NS: i64 = 1_000_000_000;
+
+/// Interval to check whether unacknowledged threads have exited
+const POLL_INTERVAL_NS: i64 = 10_000_000;
+
+/// Maximum number of unacknowledged threads recorded in [`StopTheWorldError`]
+const MAX_REPORTED_THREADS: usize = 8;
+
+/// The thread has been signaled but not parked yet
+const THREAD_PENDING: u8 = 0;
+/// The thread is parked
+const THREAD_PARKED: u8 = 1;
+/// The thread has exited before being parked
+const THREAD_EXITED: u8 = 2;
+
+/// A thread to be parked
+struct ThreadSlot {
+    /// Thread ID
+    tid: AtomicI32,
+    /// One of [`THREAD_PENDING`], [`THREAD_PARKED`] and [`THREAD_EXITED`]
+    state: AtomicU8,
+}
+
+/// Stop generation used as futex word. Odd value means threads should be parked.
+static STOP_GENERATION: AtomicU32 = AtomicU32::new(0);
+
+/// Number of threads parked in current generation, used as futex word.
+///
+/// The higher 16 bits are the lower 16 bits of the generation, and the lower 16 bits are the
+/// count. The generation tag prevents a handler woken up late from being counted in a newer
+/// generation without being parked.
+static PARKED_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Generation tag in [`PARKED_COUNT`]
+fn parked_count_tag(generation: u32) -> u32 {
+    generation << 16
+}
+
+/// Start address of thread slots, allocated by `mmap`.
+///
+/// Old slots are never unmapped when growing, since late signal handlers may still read them.
+static THREAD_SLOTS: AtomicPtr<ThreadSlot> = AtomicPtr::new(core::ptr::null_mut());
+/// Number of thread slots in use
+static THREAD_SLOTS_LEN: AtomicUsize = AtomicUsize::new(0);
+/// Number of thread slots allocated. Only accessed with [`PatchGuard`][crate::patch_lock::PatchGuard] held
+static mut THREAD_SLOTS_CAPACITY: usize = 0;
+
+/// Whether the parking signal handler has been installed. Only accessed with [`PatchGuard`][crate::patch_lock::PatchGuard] held
+static mut PARK_HANDLER_INSTALLED: bool = false;
+
+/// Error returned when other threads cannot be stopped.
+///
+/// Nothing is patched when this error is returned, and all parked threads are released.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StopTheWorldError {
+    /// Some threads do not acknowledge the stop request within timeout
+    Unacknowledged {
+        /// Thread IDs of unacknowledged threads. Only the first 8 are recorded, and the rest are 0.
+        tids: [libc::pid_t; MAX_REPORTED_THREADS],
+        /// Total count of unacknowledged threads
+        count: usize,
+    },
+    /// A system call failed before all threads are parked, such as allocating memory by `mmap`,
+    /// listing threads in `/proc/self/task`, installing the signal handler by `sigaction`, or
+    /// sending the signal by `tgkill`
+    Syscall {
+        /// Name of the failed system call
+        syscall: &'static str,
+        /// Error code reported by the OS
+        errno: i32,
+    },
+}
+
+impl StopTheWorldError {
+    /// Create a [`StopTheWorldError::Syscall`] with current `errno`
+    fn syscall(syscall: &'static str) -> Self {
+        Self::Syscall {
+            syscall,
+            errno: unsafe { *libc::__errno_location() },
+        }
+    }
+
+    /// Count of threads not acknowledging the stop request. 0 if the error is not
+    /// [`StopTheWorldError::Unacknowledged`].
+    pub fn unacknowledged_count(&self) -> usize {
+        match self {
+            Self::Unacknowledged { count, .. } => *count,
+            Self::Syscall { .. } => 0,
+        }
+    }
+
+    /// Thread IDs of threads not acknowledging the stop request.
+    ///
+    /// At most 8 thread IDs are recorded, use [`unacknowledged_count`][Self::unacknowledged_count]
+    /// to get the total count.
+    pub fn unacknowledged_threads(&self) -> &[libc::pid_t] {
+        match self {
+            Self::Unacknowledged { tids, count } => &tids[..(*count).min(MAX_REPORTED_THREADS)],
+            Self::Syscall { .. } => &[],
+        }
+    }
+}
+
+impl core::fmt::Display for StopTheWorldError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Unacknowledged { count, .. } => write!(
+                f,
+                "{count} thread(s) did not acknowledge the stop request: {:?}",
+                self.unacknowledged_threads()
+            ),
+            Self::Syscall { syscall, errno } => {
+                write!(f, "{syscall} failed with error code {errno}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for StopTheWorldError {}
+
+/// Signal used to park threads.
+///
+/// Highest real-time signals are avoided, since qemu-user cannot map them to host signals.
+fn park_signal() -> libc::c_int {
+    libc::SIGRTMIN() + 8
+}
+
+/// Thread ID of current thread
+fn gettid() -> libc::pid_t {
+    unsafe { libc::syscall(libc::SYS_gettid) as libc::pid_t }
+}
+
+/// Current time of monotonic clock in nanoseconds
+fn monotonic_now_ns() -> i64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    // `time_t` and `c_long` are 32-bit on some targets
+    #[allow(clippy::unnecessary_cast)]
+    let now_ns = (ts.tv_sec as i64) * 1_000_000_000 + ts.tv_nsec as i64;
+    now_ns
+}
+
+/// Wait on `futex` while its value is `expected`, with optional relative timeout in nanoseconds
+fn futex_wait(futex: &AtomicU32, expected: u32, timeout_ns: Option<i64>) {
+    let ts = timeout_ns.map(|timeout_ns| libc::timespec {
+        tv_sec: (timeout_ns / 1_000_000_000) as _,
+        tv_nsec: (timeout_ns % 1_000_000_000) as _,
+    });
+    let ts_ptr = ts
+        .as_ref()
+        .map_or(core::ptr::null(), |ts| ts as *const libc::timespec);
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex.as_ptr(),
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            ts_ptr,
+        );
+    }
+}
+
+/// Wake all waiters of `futex`
+fn futex_wake_all(futex: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex.as_ptr(),
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            libc::c_int::MAX,
+        );
+    }
+}
+
+/// Thread slots in use
+fn thread_slots() -> &'static [ThreadSlot] {
+    let slots = THREAD_SLOTS.load(Ordering::Acquire);
+    if slots.is_null() {
+        return &[];
+    }
+    unsafe { core::slice::from_raw_parts(slots, THREAD_SLOTS_LEN.load(Ordering::Acquire)) }
+}
+
+/// Append a new thread slot. Must be called with [`PatchGuard`][crate::patch_lock::PatchGuard] held.
+unsafe fn push_thread_slot(tid: libc::pid_t) -> Result<(), StopTheWorldError> {
+    let len = THREAD_SLOTS_LEN.load(Ordering::Relaxed);
+    if len == unsafe { THREAD_SLOTS_CAPACITY } {
+        let new_capacity = (len * 2).max(64);
+        let new_slots = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                new_capacity * core::mem::size_of::<ThreadSlot>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if new_slots == libc::MAP_FAILED {
+            return Err(StopTheWorldError::syscall("mmap"));
+        }
+        let new_slots = new_slots.cast::<ThreadSlot>();
+        let old_slots = THREAD_SLOTS.load(Ordering::Relaxed);
+        if !old_slots.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(old_slots, new_slots, len);
+            }
+        }
+        THREAD_SLOTS.store(new_slots, Ordering::Release);
+        unsafe {
+            THREAD_SLOTS_CAPACITY = new_capacity;
+        }
+    }
+    unsafe {
+        THREAD_SLOTS
+            .load(Ordering::Relaxed)
+            .add(len)
+            .write(ThreadSlot {
+                tid: AtomicI32::new(tid),
+                state: AtomicU8::new(THREAD_PENDING),
+            });
+    }
+    THREAD_SLOTS_LEN.store(len + 1, Ordering::Release);
+    Ok(())
+}
+
+/// Call `f` with thread ID of each thread listed in `/proc/self/task`, and stop at the first error.
+///
+/// `getdents64` is used instead of `opendir` to avoid heap allocation.
+fn for_each_task(
+    mut f: impl FnMut(libc::pid_t) -> Result<(), StopTheWorldError>,
+) -> Result<(), StopTheWorldError> {
+    let fd = unsafe {
+        libc::open(
+            c"/proc/self/task".as_ptr(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(StopTheWorldError::syscall("open"));
+    }
+    let mut buf = [0u8; 1024];
+    let res = 'read: loop {
+        let nread = unsafe { libc::syscall(libc::SYS_getdents64, fd, buf.as_mut_ptr(), buf.len()) };
+        if nread < 0 {
+            break 'read Err(StopTheWorldError::syscall("getdents64"));
+        }
+        if nread == 0 {
+            break 'read Ok(());
+        }
+        let mut offset = 0;
+        while offset < nread as usize {
+            // struct linux_dirent64 { u64 d_ino; i64 d_off; u16 d_reclen; u8 d_type; char d_name[]; }
+            let reclen = u16::from_ne_bytes([buf[offset + 16], buf[offset + 17]]) as usize;
+            let name = &buf[offset + 19..offset + reclen];
+            let mut tid: libc::pid_t = 0;
+            let mut is_tid = false;
+            for &byte in name.iter().take_while(|&&byte| byte != 0) {
+                if !byte.is_ascii_digit() {
+                    is_tid = false;
+                    break;
+                }
+                tid = tid * 10 + (byte - b'0') as libc::pid_t;
+                is_tid = true;
+            }
+            if is_tid && let Err(err) = f(tid) {
+                break 'read Err(err);
+            }
+            offset += reclen;
+        }
+    };
+    unsafe {
+        libc::close(fd);
+    }
+    res
+}
+
+/// Install the parking signal handler if not installed. Must be called with [`PatchGuard`][crate::patch_lock::PatchGuard] held.
+unsafe fn install_park_handler() -> Result<(), StopTheWorldError> {
+    if unsafe { PARK_HANDLER_INSTALLED } {
+        return Ok(());
+    }
+    let mut action: libc::sigaction = unsafe { core::mem::zeroed() };
+    action.sa_sigaction = park_handler as *const () as usize;
+    action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+    let res = unsafe {
+        // Block all other signals while parked, so no other handler runs in the meanwhile
+        libc::sigfillset(&mut action.sa_mask);
+        libc::sigaction(park_signal(), &action, core::ptr::null_mut())
+    };
+    if res != 0 {
+        return Err(StopTheWorldError::syscall("sigaction"));
+    }
+    unsafe {
+        PARK_HANDLER_INSTALLED = true;
+    }
+    Ok(())
+}
+
+/// Handler of the parking signal
+extern "C" fn park_handler(
+    _signum: libc::c_int,
+    _info: *mut libc::siginfo_t,
+    _context: *mut core::ffi::c_void,
+) {
+    let generation = loop {
+        let generation = STOP_GENERATION.load(Ordering::Acquire);
+        if generation.is_multiple_of(2) {
+            // The signal arrives after the world has been released
+            return;
+        }
+        let parked_count = PARKED_COUNT.load(Ordering::Acquire);
+        if parked_count & 0xffff0000 != parked_count_tag(generation) {
+            // A new generation begins after loading the generation
+            continue;
+        }
+        if PARKED_COUNT
+            .compare_exchange(
+                parked_count,
+                parked_count + 1,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            break generation;
+        }
+    };
+    let saved_errno = unsafe { *libc::__errno_location() };
+    let tid = gettid();
+    if let Some(slot) = thread_slots()
+        .iter()
+        .find(|slot| slot.tid.load(Ordering::Relaxed) == tid)
+    {
+        slot.state.store(THREAD_PARKED, Ordering::Release);
+    }
+    futex_wake_all(&PARKED_COUNT);
+    while STOP_GENERATION.load(Ordering::Acquire) == generation {
+        futex_wait(&STOP_GENERATION, generation, None);
+    }
+    unsafe {
+        *libc::__errno_location() = saved_errno;
+    }
+}
+
+/// Guard releasing all parked threads when dropped, so they are released even if the patching
+/// panics
+struct ReleaseGuard {
+    /// Make sure this guard can only be created by [`stop_the_world`]
+    _private: (),
+}
+
+impl Drop for ReleaseGuard {
+    fn drop(&mut self) {
+        STOP_GENERATION.fetch_add(1, Ordering::Release);
+        futex_wake_all(&STOP_GENERATION);
+    }
+}
+
+/// Park all other threads in current process, and call `f` when all of them are parked. The result
+/// of `f` is returned.
+///
+/// New threads spawned while stopping are also parked, since `/proc/self/task` is listed again
+/// until no new thread appears. Threads exiting before being parked are ignored.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`][crate::patch_lock::PatchGuard] held.
+pub unsafe fn stop_the_world<R, F: FnOnce() -> R>(f: F) -> Result<R, StopTheWorldError> {
+    unsafe {
+        install_park_handler()?;
+    }
+    THREAD_SLOTS_LEN.store(0, Ordering::Release);
+    let generation = STOP_GENERATION.load(Ordering::Relaxed).wrapping_add(1);
+    PARKED_COUNT.store(parked_count_tag(generation), Ordering::Release);
+    STOP_GENERATION.store(generation, Ordering::Release);
+    let _release_guard = ReleaseGuard { _private: () };
+
+    let pid = unsafe { libc::getpid() };
+    let self_tid = gettid();
+    let deadline = monotonic_now_ns() + STOP_THE_WORLD_TIMEOUT_NS;
+    let mut expected_count = 0u32;
+    loop {
+        // Find threads not signaled yet
+        let start = THREAD_SLOTS_LEN.load(Ordering::Relaxed);
+        for_each_task(|tid| {
+            if tid != self_tid
+                && !thread_slots()
+                    .iter()
+                    .any(|slot| slot.tid.load(Ordering::Relaxed) == tid)
+            {
+                unsafe { push_thread_slot(tid) }?;
+            }
+            Ok(())
+        })?;
+        let new_slots = &thread_slots()[start..];
+        if new_slots.is_empty() {
+            // All threads are parked
+            break;
+        }
+        for slot in new_slots {
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_tgkill,
+                    pid,
+                    slot.tid.load(Ordering::Relaxed),
+                    park_signal(),
+                )
+            };
+            if res == 0 {
+                expected_count += 1;
+            } else if unsafe { *libc::__errno_location() } == libc::ESRCH {
+                slot.state.store(THREAD_EXITED, Ordering::Relaxed);
+            } else {
+                return Err(StopTheWorldError::syscall("tgkill"));
+            }
+        }
+
+        // Wait for acknowledgements
+        loop {
+            let parked_count = PARKED_COUNT.load(Ordering::Acquire);
+            if parked_count & 0xffff >= expected_count {
+                break;
+            }
+            let now = monotonic_now_ns();
+            if now >= deadline {
+                return Err(unacknowledged_error());
+            }
+            futex_wait(
+                &PARKED_COUNT,
+                parked_count,
+                Some(POLL_INTERVAL_NS.min(deadline - now)),
+            );
+            // Threads exiting before handling the signal will never acknowledge
+            for slot in thread_slots() {
+                if slot.state.load(Ordering::Acquire) == THREAD_PENDING {
+                    let res = unsafe {
+                        libc::syscall(libc::SYS_tgkill, pid, slot.tid.load(Ordering::Relaxed), 0)
+                    };
+                    if res != 0 && unsafe { *libc::__errno_location() } == libc::ESRCH {
+                        slot.state.store(THREAD_EXITED, Ordering::Relaxed);
+                        expected_count -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(f())
+}
+
+/// Collect threads still pending into an error
+fn unacknowledged_error() -> StopTheWorldError {
+    let mut tids = [0; MAX_REPORTED_THREADS];
+    let mut count = 0;
+    for slot in thread_slots() {
+        if slot.state.load(Ordering::Acquire) == THREAD_PENDING {
+            if count < MAX_REPORTED_THREADS {
+                tids[count] = slot.tid.load(Ordering::Relaxed);
+            }
+            count += 1;
+        }
+    }
+    StopTheWorldError::Unacknowledged { tids, count }
+}
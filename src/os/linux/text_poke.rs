@@ -0,0 +1,268 @@
+//! Breakpoint-based live patching for x86 and x86_64, modelled on `text_poke_bp` of Linux kernel.
+//!
+//! See https://github.com/torvalds/linux/blob/master/arch/x86/kernel/alternative.c for the kernel
+//! implementation.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::sync_core;
+use crate::{
+    GenericStaticKey, JumpEntry, JumpLabelType, StaticKeyError,
+    arch::ARCH_JUMP_INS_LENGTH,
+    code_manipulate::{CodeManipulator, DummyCodeManipulator},
+    transaction::MAX_BATCH_PATCHES,
+};
+
+/// The `int3` instruction
+const INT3_INS: u8 = 0xcc;
+
+// See arch/x86/include/uapi/asm/ucontext.h. `uc_mcontext` starts with general registers for
+// both glibc and musl, while musl for x86 does not expose them in libc crate.
+/// Index of instruction pointer in `uc_mcontext`
+#[cfg(target_arch = "x86_64")]
+const REG_PC: usize = 16;
+/// Index of instruction pointer in `uc_mcontext`
+#[cfg(target_arch = "x86")]
+const REG_PC: usize = 14;
+
+/// `si_code` of `SIGTRAP` sent by `int3`
+const SI_KERNEL: libc::c_int = 0x80;
+
+/// Address of the static key whose jump entries are being patched, or 0 if no patching is in progress
+static TEXT_POKE_KEY: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the `SIGTRAP` handler has been installed. Only accessed with [`PatchGuard`][crate::patch_lock::PatchGuard] held
+static mut SIGTRAP_HANDLER_INSTALLED: bool = false;
+
+/// Previous `SIGTRAP` action, which will be invoked for breakpoints not belonging to us
+static mut OLD_SIGTRAP_ACTION: core::mem::MaybeUninit<libc::sigaction> =
+    core::mem::MaybeUninit::zeroed();
+
+/// Install the `SIGTRAP` handler if not installed. Must be called with [`PatchGuard`][crate::patch_lock::PatchGuard] held.
+unsafe fn install_sigtrap_handler() -> Result<(), StaticKeyError> {
+    if unsafe { SIGTRAP_HANDLER_INSTALLED } {
+        return Ok(());
+    }
+    let mut action: libc::sigaction = unsafe { core::mem::zeroed() };
+    action.sa_sigaction = sigtrap_handler as *const () as usize;
+    action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK | libc::SA_RESTART;
+    let res = unsafe {
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(
+            libc::SIGTRAP,
+            &action,
+            (&raw mut OLD_SIGTRAP_ACTION).cast::<libc::sigaction>(),
+        )
+    };
+    if res != 0 {
+        return Err(StaticKeyError::SignalHandler {
+            signum: libc::SIGTRAP,
+            errno: unsafe { *libc::__errno_location() },
+        });
+    }
+    unsafe {
+        SIGTRAP_HANDLER_INSTALLED = true;
+    }
+    Ok(())
+}
+
+/// Address where the thread hitting `int3` at `code_addr` should continue, if `code_addr` is
+/// being patched.
+fn text_poke_destination(code_addr: usize) -> Option<usize> {
+    let key_addr = TEXT_POKE_KEY.load(Ordering::Acquire);
+    if key_addr == 0 {
+        return None;
+    }
+    // The M and S generic is useless here
+    let key = unsafe { &*(key_addr as *const GenericStaticKey<DummyCodeManipulator, true>) };
+    let jump_entries = key.jump_entries();
+    let index = jump_entries
+        .binary_search_by_key(&code_addr, |jump_entry| jump_entry.code_addr())
+        .ok()?;
+    let jump_entry = &jump_entries[index];
+    match crate::jump_entry_label_type(jump_entry, key.is_enabled()) {
+        JumpLabelType::Jmp => Some(jump_entry.target_addr()),
+        JumpLabelType::Nop => Some(jump_entry.code_addr() + ARCH_JUMP_INS_LENGTH),
+    }
+}
+
+/// Handler of `SIGTRAP`, emulating the instruction being patched
+extern "C" fn sigtrap_handler(
+    signum: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut core::ffi::c_void,
+) {
+    let ucontext = context.cast::<libc::ucontext_t>();
+    let pc = unsafe {
+        (&raw mut (*ucontext).uc_mcontext)
+            .cast::<usize>()
+            .add(REG_PC)
+    };
+    if unsafe { (*info).si_code } == SI_KERNEL {
+        // The instruction pointer is right after the `int3`
+        let code_addr = unsafe { *pc } - 1;
+        if let Some(destination) = text_poke_destination(code_addr) {
+            unsafe {
+                *pc = destination;
+            }
+            return;
+        }
+        // The patching finished after this thread hit the `int3`. Execute the new instruction
+        if is_finished_text_poke(code_addr) {
+            unsafe {
+                *pc = code_addr;
+            }
+            return;
+        }
+    }
+
+    // Not our breakpoint, forward to previous handler
+    let old_action = unsafe { &*(&raw const OLD_SIGTRAP_ACTION).cast::<libc::sigaction>() };
+    if old_action.sa_flags & libc::SA_SIGINFO != 0 {
+        let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut core::ffi::c_void) =
+            unsafe { core::mem::transmute(old_action.sa_sigaction) };
+        handler(signum, info, context);
+    } else if old_action.sa_sigaction == libc::SIG_DFL {
+        // Restore the default action and raise again, which will be delivered after returning
+        unsafe {
+            libc::sigaction(libc::SIGTRAP, old_action, core::ptr::null_mut());
+            libc::raise(libc::SIGTRAP);
+        }
+    } else if old_action.sa_sigaction != libc::SIG_IGN {
+        let handler: extern "C" fn(libc::c_int) =
+            unsafe { core::mem::transmute(old_action.sa_sigaction) };
+        handler(signum);
+    }
+}
+
+/// Whether `code_addr` is a jump entry whose `int3` has already been replaced.
+fn is_finished_text_poke(code_addr: usize) -> bool {
+    // The instruction is readable since the thread has just executed it
+    if unsafe { core::ptr::read_volatile(code_addr as *const u8) } == INT3_INS {
+        return false;
+    }
+    crate::jump_entries()
+        .iter()
+        .any(|jump_entry| !jump_entry.is_dummy() && jump_entry.code_addr() == code_addr)
+}
+
+/// Update the status of `key` and replace instructions at its jump entries, while other threads may
+/// be executing them.
+///
+/// Nothing is modified if the instructions at jump entries do not match the current status. If
+/// writing any instruction fails, the status is rolled back, and all instructions are restored
+/// with the same protocol, so that threads hitting remaining `int3` are redirected to the original
+/// branch. Panics if the rollback fails as well.
+///
+/// If `membarrier` is not supported, we rely on the TLB shootdown caused by remapping in
+/// [`ArchCodeManipulator`][super::ArchCodeManipulator] to synchronize all cores, which also
+/// interrupts all cores running this process.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`][crate::patch_lock::PatchGuard] held. Jump entries must be
+/// initialized by [`global_init`][crate::global_init].
+pub unsafe fn text_poke_bp<M: CodeManipulator, const S: bool>(
+    key: &GenericStaticKey<M, S>,
+    enabled: bool,
+) -> Result<(), StaticKeyError> {
+    let jump_entries = key.jump_entries();
+    crate::verify::check_jump_entries(jump_entries, !enabled)?;
+    unsafe {
+        install_sigtrap_handler()?;
+    }
+    TEXT_POKE_KEY.store(key as *const _ as usize, Ordering::Release);
+
+    key.enabled.store(enabled, Ordering::Relaxed);
+    let res = unsafe { text_poke_jump_entries::<M>(jump_entries, enabled) };
+    if let Err(err) = &res {
+        key.enabled.store(!enabled, Ordering::Relaxed);
+        if let Err(rollback_err) = unsafe { text_poke_jump_entries::<M>(jump_entries, !enabled) } {
+            panic!("Failed to roll back static key after {err}: {rollback_err}");
+        }
+    }
+
+    TEXT_POKE_KEY.store(0, Ordering::Release);
+    res
+}
+
+/// Replace instructions at `jump_entries` to the ones when their static key has status `enabled`.
+/// Instructions already up to date are skipped, so this function can restore jump entries left
+/// in any intermediate step.
+///
+/// Each step writes all outdated jump entries by [`CodeManipulator::write_code_batch`], so that
+/// each code page is remapped once per step.
+///
+/// # Safety
+///
+/// See [`text_poke_bp`].
+unsafe fn text_poke_jump_entries<M: CodeManipulator>(
+    jump_entries: &[JumpEntry],
+    enabled: bool,
+) -> Result<(), StaticKeyError> {
+    // 1. Write `int3` at the first byte
+    unsafe { write_outdated::<M, 1>(jump_entries, enabled, 0, |_| [INT3_INS]) }?;
+    sync_core();
+
+    // 2. Write the remaining bytes
+    unsafe {
+        write_outdated::<M, { ARCH_JUMP_INS_LENGTH - 1 }>(jump_entries, enabled, 1, |code_bytes| {
+            let mut tail_bytes = [0u8; ARCH_JUMP_INS_LENGTH - 1];
+            tail_bytes.copy_from_slice(&code_bytes[1..]);
+            tail_bytes
+        })
+    }?;
+    sync_core();
+
+    // 3. Replace the `int3` with the first byte
+    unsafe { write_outdated::<M, 1>(jump_entries, enabled, 0, |code_bytes| [code_bytes[0]]) }?;
+    sync_core();
+
+    Ok(())
+}
+
+/// Write `patch` of the new instruction at `offset` of each jump entry whose instruction is not
+/// the one when its static key has status `enabled`. The patches are written in batches of at most
+/// [`MAX_BATCH_PATCHES`].
+///
+/// # Safety
+///
+/// See [`text_poke_bp`].
+unsafe fn write_outdated<M: CodeManipulator, const L: usize>(
+    jump_entries: &[JumpEntry],
+    enabled: bool,
+    offset: usize,
+    patch: impl Fn(&[u8; ARCH_JUMP_INS_LENGTH]) -> [u8; L],
+) -> Result<(), StaticKeyError> {
+    let mut patches = [(core::ptr::null_mut(), [0u8; L]); MAX_BATCH_PATCHES];
+    let mut patch_count = 0;
+    let mut outdated = jump_entries.iter().filter_map(|jump_entry| {
+        let code_bytes = crate::jump_entry_instruction(jump_entry, enabled);
+        (crate::verify::read_instruction(jump_entry) != code_bytes).then(|| {
+            (
+                (jump_entry.code_addr() + offset) as *mut _,
+                patch(&code_bytes),
+            )
+        })
+    });
+    loop {
+        let next = outdated.next();
+        if let Some(next) = next {
+            patches[patch_count] = next;
+            patch_count += 1;
+            if patch_count < MAX_BATCH_PATCHES {
+                continue;
+            }
+        }
+        if patch_count > 0 {
+            let written = &patches[..patch_count];
+            let (first_addr, _) = written[0];
+            unsafe { M::write_code_batch(written) }
+                .map_err(|err| StaticKeyError::write_code(first_addr as usize - offset, err))?;
+            patch_count = 0;
+        }
+        if next.is_none() {
+            return Ok(());
+        }
+    }
+}
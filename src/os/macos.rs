@@ -1,6 +1,12 @@
 //! macOS-specific implementations
 
-use crate::{JumpEntry, code_manipulate::CodeManipulator};
+use crate::{
+    JumpEntry,
+    alternative::AlternativeEntry,
+    code_manipulate::{CodeManipulateError, CodeManipulator},
+    cpu_feature::CpuFeatureEntry,
+    registry::KeyNameEntry,
+};
 
 // See https://developer.apple.com/library/archive/documentation/DeveloperTools/Reference/Assembler/040-Assembler_Directives/asm_directives.html#//apple_ref/doc/uid/TP30000823-CJBIFBJG
 /// Name and attribute of section storing jump entries
@@ -12,6 +18,91 @@ macro_rules! os_static_key_sec_name_attr {
     };
 }
 
+/// Name and attribute of the companion section storing names of static keys. Mach-O section names
+/// are limited to 16 characters, so it is shorter than `__static_key_names` on other OSs.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_key_name_sec_name_attr {
+    () => {
+        "__DATA,__static_knames,regular,no_dead_strip"
+    };
+}
+
+/// Name and attribute of section storing CPU features of static keys. Mach-O section names are
+/// limited to 16 characters, so it is shorter than `__static_cpu_features` on other OSs.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_cpu_feature_sec_name_attr {
+    () => {
+        "__DATA,__static_cpufeat,regular,no_dead_strip"
+    };
+}
+
+/// Name and attribute of section storing entries of static call trampolines
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_call_sec_name_attr {
+    () => {
+        "__DATA,__static_calls,regular,no_dead_strip"
+    };
+}
+
+/// Name and attribute of section storing entries of static switch sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_switch_sec_name_attr {
+    () => {
+        "__DATA,__static_switch,regular,no_dead_strip"
+    };
+}
+
+/// Name and attribute of section storing the relative addresses of arms of static switch sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_switch_table_sec_name_attr {
+    () => {
+        "__TEXT,__const"
+    };
+}
+
+/// Name and attribute of section storing entries of runtime constant sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_runtime_const_sec_name_attr {
+    () => {
+        "__DATA,__runtime_const,regular,no_dead_strip"
+    };
+}
+
+/// Name and attribute of section storing entries of alternative instructions
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_alternative_sec_name_attr {
+    () => {
+        "__DATA,__static_alts,regular,no_dead_strip"
+    };
+}
+
+/// Name and attribute of section storing replacement instructions of alternatives
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_alternative_replacement_sec_name_attr {
+    () => {
+        "__TEXT,__const"
+    };
+}
+
+/// Name and attribute of the section storing static keys defined by macros. Static keys are always
+/// placed in a data section, even if they are zero-initialized, so that their status can be
+/// modified in the file.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_key_data_sec_name_attr {
+    () => {
+        "__DATA,__data"
+    };
+}
+
 // See https://stackoverflow.com/q/17669593/10005095 and https://github.com/apple-opensource-mirror/ld64/blob/master/unit-tests/test-cases/section-labels/main.c
 unsafe extern "Rust" {
     /// Address of this static is the start address of __static_keys section
@@ -22,6 +113,72 @@ unsafe extern "Rust" {
     pub static mut JUMP_ENTRY_STOP: JumpEntry;
 }
 
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_knames section
+    #[link_name = "\x01section$start$__DATA$__static_knames"]
+    pub static KEY_NAME_ENTRY_START: KeyNameEntry;
+    /// Address of this static is the end address of __static_knames section (excluded)
+    #[link_name = "\x01section$end$__DATA$__static_knames"]
+    pub static KEY_NAME_ENTRY_STOP: KeyNameEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_cpufeat section
+    #[link_name = "\x01section$start$__DATA$__static_cpufeat"]
+    pub static CPU_FEATURE_ENTRY_START: CpuFeatureEntry;
+    /// Address of this static is the end address of __static_cpufeat section (excluded)
+    #[link_name = "\x01section$end$__DATA$__static_cpufeat"]
+    pub static CPU_FEATURE_ENTRY_STOP: CpuFeatureEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_calls section
+    #[link_name = "\x01section$start$__DATA$__static_calls"]
+    pub static mut STATIC_CALL_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __static_calls section (excluded)
+    #[link_name = "\x01section$end$__DATA$__static_calls"]
+    pub static mut STATIC_CALL_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_switch section
+    #[link_name = "\x01section$start$__DATA$__static_switch"]
+    pub static mut SWITCH_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __static_switch section (excluded)
+    #[link_name = "\x01section$end$__DATA$__static_switch"]
+    pub static mut SWITCH_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __runtime_const section
+    #[link_name = "\x01section$start$__DATA$__runtime_const"]
+    pub static mut RUNTIME_CONST_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __runtime_const section (excluded)
+    #[link_name = "\x01section$end$__DATA$__runtime_const"]
+    pub static mut RUNTIME_CONST_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_alts section
+    #[link_name = "\x01section$start$__DATA$__static_alts"]
+    pub static mut ALTERNATIVE_ENTRY_START: AlternativeEntry;
+    /// Address of this static is the end address of __static_alts section (excluded)
+    #[link_name = "\x01section$end$__DATA$__static_alts"]
+    pub static mut ALTERNATIVE_ENTRY_STOP: AlternativeEntry;
+}
+
+// The process-wide patch lock, see crate::patch_lock. Defined as weak definition, so that all copies
+// of this crate share the same definition.
+core::arch::global_asm!(
+    ".pushsection __DATA,__data",
+    ".globl _static_keys_patch_lock_v1",
+    ".weak_definition _static_keys_patch_lock_v1",
+    ".p2align 3",
+    "_static_keys_patch_lock_v1:",
+    ".space 16",
+    ".popsection",
+);
+
 unsafe extern "C" {
     // libkern/OSCacheControl.h
     // void	sys_dcache_flush( void *start, size_t len) __OSX_AVAILABLE_STARTING(__MAC_10_5, __IPHONE_2_0);
@@ -30,6 +187,60 @@ unsafe extern "C" {
     // libkern/OSCacheControl.h
     // void	sys_icache_invalidate( void *start, size_t len) __OSX_AVAILABLE_STARTING(__MAC_10_5, __IPHONE_2_0);
     fn sys_icache_invalidate(start: *mut core::ffi::c_void, len: usize);
+
+    // sched.h
+    // int sched_yield(void);
+    fn sched_yield() -> core::ffi::c_int;
+}
+
+/// Yield the processor to other threads
+pub fn yield_now() {
+    unsafe {
+        sched_yield();
+    }
+}
+
+/// Whether `addr` resides in an executable region
+pub fn is_executable(addr: usize) -> bool {
+    let mut region_addr = addr as u64;
+    let mut region_size = 0;
+    let mut info = mach2::vm_region::vm_region_basic_info_64::default();
+    let mut info_count = mach2::vm_region::vm_region_basic_info_64::count();
+    let mut object_name = 0;
+    let ret = unsafe {
+        mach2::vm::mach_vm_region(
+            mach2::traps::mach_task_self(),
+            &mut region_addr,
+            &mut region_size,
+            mach2::vm_region::VM_REGION_BASIC_INFO_64,
+            (&raw mut info).cast(),
+            &mut info_count,
+            &mut object_name,
+        )
+    };
+    // The region found may start after `addr` if `addr` is not mapped
+    let protection = info.protection;
+    ret == mach2::kern_return::KERN_SUCCESS
+        && region_addr <= addr as u64
+        && protection & mach2::vm_prot::VM_PROT_EXECUTE != 0
+}
+
+/// Deallocate the page remapped by [`ArchCodeManipulator::write_code`] when failing, and return
+/// the error of `syscall`.
+///
+/// # Safety
+///
+/// `remap_addr` must be the address returned by `mach_vm_remap` with `L` bytes.
+unsafe fn deallocate_remap<const L: usize>(
+    remap_addr: u64,
+    syscall: &'static str,
+    errno: i32,
+) -> CodeManipulateError {
+    // The error of deallocation is ignored, since the error of `syscall` is more meaningful
+    let _ = unsafe {
+        mach2::vm::mach_vm_deallocate(mach2::traps::mach_task_self(), remap_addr, L as u64)
+    };
+    CodeManipulateError { syscall, errno }
 }
 
 /// Arch-specific [`CodeManipulator`] using `mach_vm_remap` to remap the code page
@@ -42,7 +253,10 @@ const VM_FLAGS_RETURN_DATA_ADDR: i32 = 0x00100000;
 
 impl CodeManipulator for ArchCodeManipulator {
     // See https://stackoverflow.com/a/76552040/10005095
-    unsafe fn write_code<const L: usize>(addr: *mut core::ffi::c_void, data: &[u8; L]) {
+    unsafe fn write_code<const L: usize>(
+        addr: *mut core::ffi::c_void,
+        data: &[u8; L],
+    ) -> Result<(), CodeManipulateError> {
         let mut remap_addr = 0;
         let mut cur_prot = 0;
         let mut max_prot = 0;
@@ -66,7 +280,10 @@ impl CodeManipulator for ArchCodeManipulator {
             )
         };
         if ret != mach2::kern_return::KERN_SUCCESS {
-            panic!("mach_vm_remap to new failed");
+            return Err(CodeManipulateError {
+                syscall: "mach_vm_remap",
+                errno: ret,
+            });
         }
 
         // 2. Reprotect the page to rw- (needs VM_PROT_COPY because the max protection is currently r-x)
@@ -82,7 +299,7 @@ impl CodeManipulator for ArchCodeManipulator {
             )
         };
         if ret != mach2::kern_return::KERN_SUCCESS {
-            panic!("mach_vm_protect to write failed");
+            return Err(unsafe { deallocate_remap::<L>(remap_addr, "mach_vm_protect", ret) });
         }
 
         // 3. Write the changes
@@ -106,7 +323,7 @@ impl CodeManipulator for ArchCodeManipulator {
             )
         };
         if ret != mach2::kern_return::KERN_SUCCESS {
-            panic!("mach_vm_protect to execute failed");
+            return Err(unsafe { deallocate_remap::<L>(remap_addr, "mach_vm_protect", ret) });
         }
 
         // 6. Invalidate the instruction cache
@@ -132,7 +349,8 @@ impl CodeManipulator for ArchCodeManipulator {
             )
         };
         if ret != mach2::kern_return::KERN_SUCCESS {
-            panic!("mach_vm_remap to origin failed");
+            return Err(unsafe { deallocate_remap::<L>(remap_addr, "mach_vm_remap", ret) });
         }
+        Ok(())
     }
 }
@@ -1,6 +1,12 @@
 //! Other OS-specific implementations
 
-use crate::{JumpEntry, code_manipulate::CodeManipulator};
+use crate::{
+    JumpEntry,
+    alternative::AlternativeEntry,
+    code_manipulate::{CodeManipulateError, CodeManipulator},
+    cpu_feature::CpuFeatureEntry,
+    registry::KeyNameEntry,
+};
 use core::ffi::c_void;
 
 /// Name and attribute of section storing jump entries
@@ -12,6 +18,89 @@ macro_rules! os_static_key_sec_name_attr {
     };
 }
 
+/// Name and attribute of the companion section storing names of static keys
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_key_name_sec_name_attr {
+    () => {
+        "__static_key_names"
+    };
+}
+
+/// Name and attribute of section storing CPU features of static keys
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_cpu_feature_sec_name_attr {
+    () => {
+        "__static_cpu_features"
+    };
+}
+
+/// Name and attribute of section storing entries of static call trampolines
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_call_sec_name_attr {
+    () => {
+        "__static_calls, \"awR\""
+    };
+}
+
+/// Name and attribute of section storing entries of static switch sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_switch_sec_name_attr {
+    () => {
+        "__static_switches, \"awR\""
+    };
+}
+
+/// Name and attribute of section storing the relative addresses of arms of static switch sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_switch_table_sec_name_attr {
+    () => {
+        ".rodata.static_switch_tables, \"a\""
+    };
+}
+
+/// Name and attribute of section storing entries of runtime constant sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_runtime_const_sec_name_attr {
+    () => {
+        "__runtime_consts, \"awR\""
+    };
+}
+
+/// Name and attribute of section storing entries of alternative instructions
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_alternative_sec_name_attr {
+    () => {
+        "__static_alternatives, \"awR\""
+    };
+}
+
+/// Name and attribute of section storing replacement instructions of alternatives
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_alternative_replacement_sec_name_attr {
+    () => {
+        ".rodata.static_alternatives, \"a\""
+    };
+}
+
+/// Name and attribute of the section storing static keys defined by macros. Static keys are always
+/// placed in a data section, even if they are zero-initialized, so that their status can be
+/// modified in the file.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_key_data_sec_name_attr {
+    () => {
+        ".data.static_keys"
+    };
+}
+
 unsafe extern "Rust" {
     /// Address of this static is the start address of __static_keys section
     #[link_name = "__start___static_keys"]
@@ -21,11 +110,92 @@ unsafe extern "Rust" {
     pub static mut JUMP_ENTRY_STOP: JumpEntry;
 }
 
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_key_names section
+    #[link_name = "__start___static_key_names"]
+    pub static KEY_NAME_ENTRY_START: KeyNameEntry;
+    /// Address of this static is the end address of __static_key_names section (excluded)
+    #[link_name = "__stop___static_key_names"]
+    pub static KEY_NAME_ENTRY_STOP: KeyNameEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_cpu_features section
+    #[link_name = "__start___static_cpu_features"]
+    pub static CPU_FEATURE_ENTRY_START: CpuFeatureEntry;
+    /// Address of this static is the end address of __static_cpu_features section (excluded)
+    #[link_name = "__stop___static_cpu_features"]
+    pub static CPU_FEATURE_ENTRY_STOP: CpuFeatureEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_calls section
+    #[link_name = "__start___static_calls"]
+    pub static mut STATIC_CALL_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __static_calls section (excluded)
+    #[link_name = "__stop___static_calls"]
+    pub static mut STATIC_CALL_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_switches section
+    #[link_name = "__start___static_switches"]
+    pub static mut SWITCH_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __static_switches section (excluded)
+    #[link_name = "__stop___static_switches"]
+    pub static mut SWITCH_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __runtime_consts section
+    #[link_name = "__start___runtime_consts"]
+    pub static mut RUNTIME_CONST_ENTRY_START: JumpEntry;
+    /// Address of this static is the end address of __runtime_consts section (excluded)
+    #[link_name = "__stop___runtime_consts"]
+    pub static mut RUNTIME_CONST_ENTRY_STOP: JumpEntry;
+}
+
+unsafe extern "Rust" {
+    /// Address of this static is the start address of __static_alternatives section
+    #[link_name = "__start___static_alternatives"]
+    pub static mut ALTERNATIVE_ENTRY_START: AlternativeEntry;
+    /// Address of this static is the end address of __static_alternatives section (excluded)
+    #[link_name = "__stop___static_alternatives"]
+    pub static mut ALTERNATIVE_ENTRY_STOP: AlternativeEntry;
+}
+
+// The process-wide patch lock, see crate::patch_lock. Defined as weak symbol, so that all copies
+// of this crate share the same definition.
+core::arch::global_asm!(
+    ".pushsection .data.static_keys_patch_lock_v1,\"aw\"",
+    ".weak static_keys_patch_lock_v1",
+    ".type static_keys_patch_lock_v1, %object",
+    ".size static_keys_patch_lock_v1, 16",
+    ".balign 8",
+    "static_keys_patch_lock_v1:",
+    ".zero 16",
+    ".popsection",
+);
+
+/// Yield the processor to other threads. Only a spin loop hint since there is no scheduler.
+pub fn yield_now() {
+    core::hint::spin_loop();
+}
+
+/// Whether `addr` is executable. Always `true` since memory mappings are unknown.
+pub fn is_executable(_addr: usize) -> bool {
+    true
+}
+
 /// Arch-specific [`CodeManipulator`] using [`libc`] with `mprotect`.
 pub struct ArchCodeManipulator;
 
 impl CodeManipulator for crate::os::ArchCodeManipulator {
-    unsafe fn write_code<const L: usize>(addr: *mut c_void, data: &[u8; L]) {
+    unsafe fn write_code<const L: usize>(
+        addr: *mut c_void,
+        data: &[u8; L],
+    ) -> Result<(), CodeManipulateError> {
         core::ptr::copy_nonoverlapping(data.as_ptr(), addr.cast(), L);
+        Ok(())
     }
 }
@@ -2,12 +2,22 @@
 
 use windows::Win32::System::{
     Diagnostics::Debug::FlushInstructionCache,
-    Memory::{PAGE_EXECUTE_READWRITE, PAGE_PROTECTION_FLAGS, VirtualProtect},
+    Memory::{
+        MEM_COMMIT, MEMORY_BASIC_INFORMATION, PAGE_EXECUTE, PAGE_EXECUTE_READ,
+        PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_PROTECTION_FLAGS, VirtualProtect,
+        VirtualQuery,
+    },
     SystemInformation::{GetSystemInfo, SYSTEM_INFO},
-    Threading::GetCurrentProcess,
+    Threading::{GetCurrentProcess, SwitchToThread},
 };
 
-use crate::{JumpEntry, code_manipulate::CodeManipulator};
+use crate::{
+    JumpEntry,
+    alternative::AlternativeEntry,
+    code_manipulate::{CodeManipulateError, CodeManipulator},
+    cpu_feature::CpuFeatureEntry,
+    registry::KeyNameEntry,
+};
 
 // Bugs here, DO NOT USE. See https://github.com/rust-lang/rust/issues/128177
 // See https://sourceware.org/binutils/docs/as/Section.html
@@ -20,6 +30,89 @@ macro_rules! os_static_key_sec_name_attr {
     };
 }
 
+/// Name and attribute of the companion section storing names of static keys
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_key_name_sec_name_attr {
+    () => {
+        ".stkn$b"
+    };
+}
+
+/// Name and attribute of section storing CPU features of static keys
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_cpu_feature_sec_name_attr {
+    () => {
+        ".stcf$b"
+    };
+}
+
+/// Name and attribute of section storing entries of static call trampolines
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_call_sec_name_attr {
+    () => {
+        ".stkc$b"
+    };
+}
+
+/// Name and attribute of section storing entries of static switch sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_switch_sec_name_attr {
+    () => {
+        ".stsw$b"
+    };
+}
+
+/// Name and attribute of section storing the relative addresses of arms of static switch sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_switch_table_sec_name_attr {
+    () => {
+        ".rdata"
+    };
+}
+
+/// Name and attribute of section storing entries of runtime constant sites
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_runtime_const_sec_name_attr {
+    () => {
+        ".strc$b"
+    };
+}
+
+/// Name and attribute of section storing entries of alternative instructions
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_alternative_sec_name_attr {
+    () => {
+        ".stal$b"
+    };
+}
+
+/// Name and attribute of section storing replacement instructions of alternatives
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_alternative_replacement_sec_name_attr {
+    () => {
+        ".rdata"
+    };
+}
+
+/// Name and attribute of the section storing static keys defined by macros. Static keys are always
+/// placed in a data section, even if they are zero-initialized, so that their status can be
+/// modified in the file.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_static_key_data_sec_name_attr {
+    () => {
+        ".data"
+    };
+}
+
 // See https://stackoverflow.com/a/14783759 and https://devblogs.microsoft.com/oldnewthing/20181107-00/?p=100155
 /// Address of this static is the start address of .stks section
 #[unsafe(link_section = ".stks$a")]
@@ -28,11 +121,109 @@ pub static mut JUMP_ENTRY_START: JumpEntry = JumpEntry::dummy();
 #[unsafe(link_section = ".stks$c")]
 pub static mut JUMP_ENTRY_STOP: JumpEntry = JumpEntry::dummy();
 
+/// Address of this static is the start address of .stkn section
+#[unsafe(link_section = ".stkn$a")]
+pub static KEY_NAME_ENTRY_START: KeyNameEntry = KeyNameEntry::dummy();
+/// Address of this static is the end address of .stkn section
+#[unsafe(link_section = ".stkn$c")]
+pub static KEY_NAME_ENTRY_STOP: KeyNameEntry = KeyNameEntry::dummy();
+
+/// Address of this static is the start address of .stcf section
+#[unsafe(link_section = ".stcf$a")]
+pub static CPU_FEATURE_ENTRY_START: CpuFeatureEntry = CpuFeatureEntry::dummy();
+/// Address of this static is the end address of .stcf section
+#[unsafe(link_section = ".stcf$c")]
+pub static CPU_FEATURE_ENTRY_STOP: CpuFeatureEntry = CpuFeatureEntry::dummy();
+
+/// Address of this static is the start address of .stkc section
+#[unsafe(link_section = ".stkc$a")]
+pub static mut STATIC_CALL_ENTRY_START: JumpEntry = JumpEntry::dummy();
+/// Address of this static is the end address of .stkc section
+#[unsafe(link_section = ".stkc$c")]
+pub static mut STATIC_CALL_ENTRY_STOP: JumpEntry = JumpEntry::dummy();
+
+/// Address of this static is the start address of .stsw section
+#[unsafe(link_section = ".stsw$a")]
+pub static mut SWITCH_ENTRY_START: JumpEntry = JumpEntry::dummy();
+/// Address of this static is the end address of .stsw section
+#[unsafe(link_section = ".stsw$c")]
+pub static mut SWITCH_ENTRY_STOP: JumpEntry = JumpEntry::dummy();
+
+/// Address of this static is the start address of .strc section
+#[unsafe(link_section = ".strc$a")]
+pub static mut RUNTIME_CONST_ENTRY_START: JumpEntry = JumpEntry::dummy();
+/// Address of this static is the end address of .strc section
+#[unsafe(link_section = ".strc$c")]
+pub static mut RUNTIME_CONST_ENTRY_STOP: JumpEntry = JumpEntry::dummy();
+
+/// Address of this static is the start address of .stal section
+#[unsafe(link_section = ".stal$a")]
+pub static mut ALTERNATIVE_ENTRY_START: AlternativeEntry = AlternativeEntry::dummy();
+/// Address of this static is the end address of .stal section
+#[unsafe(link_section = ".stal$c")]
+pub static mut ALTERNATIVE_ENTRY_STOP: AlternativeEntry = AlternativeEntry::dummy();
+
+// The process-wide patch lock, see crate::patch_lock. Defined in a COMDAT section with "select any"
+// selection, so that all copies of this crate in the same image share the same definition.
+#[cfg(target_arch = "x86_64")]
+core::arch::global_asm!(
+    ".section .data$static_keys_patch_lock_v1,\"dw\",discard,static_keys_patch_lock_v1",
+    ".globl static_keys_patch_lock_v1",
+    ".p2align 3",
+    "static_keys_patch_lock_v1:",
+    ".zero 16",
+    ".text",
+);
+// C symbols are prefixed with underscore on x86
+#[cfg(target_arch = "x86")]
+core::arch::global_asm!(
+    ".section .data$static_keys_patch_lock_v1,\"dw\",discard,_static_keys_patch_lock_v1",
+    ".globl _static_keys_patch_lock_v1",
+    ".p2align 3",
+    "_static_keys_patch_lock_v1:",
+    ".zero 16",
+    ".text",
+);
+
+/// Convert error of Windows API to [`CodeManipulateError`]
+fn code_manipulate_error(syscall: &'static str, err: windows::core::Error) -> CodeManipulateError {
+    CodeManipulateError {
+        syscall,
+        errno: err.code().0,
+    }
+}
+
+/// Yield the processor to other threads
+pub fn yield_now() {
+    let _ = unsafe { SwitchToThread() };
+}
+
+/// Whether `addr` resides in a committed executable page
+pub fn is_executable(addr: usize) -> bool {
+    let mut info = MEMORY_BASIC_INFORMATION::default();
+    let res = unsafe {
+        VirtualQuery(
+            Some(addr as *const _),
+            &mut info,
+            core::mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+        )
+    };
+    res != 0
+        && info.State == MEM_COMMIT
+        && (info.Protect
+            & (PAGE_EXECUTE | PAGE_EXECUTE_READ | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY))
+            .0
+            != 0
+}
+
 /// Arch-specific [`CodeManipulator`] using `VirtualProtect`.
 pub struct ArchCodeManipulator;
 
 impl CodeManipulator for ArchCodeManipulator {
-    unsafe fn write_code<const L: usize>(addr: *mut core::ffi::c_void, data: &[u8; L]) {
+    unsafe fn write_code<const L: usize>(
+        addr: *mut core::ffi::c_void,
+        data: &[u8; L],
+    ) -> Result<(), CodeManipulateError> {
         // TODO: page_size can be initialized once
         let mut system_info = SYSTEM_INFO::default();
         unsafe {
@@ -55,8 +246,8 @@ impl CodeManipulator for ArchCodeManipulator {
                 &mut origin_protect,
             )
         };
-        if res.is_err() {
-            panic!("Unable to make code region writable");
+        if let Err(err) = res {
+            return Err(code_manipulate_error("VirtualProtect", err));
         }
         unsafe {
             core::ptr::copy_nonoverlapping(data.as_ptr(), addr.cast(), L);
@@ -70,12 +261,13 @@ impl CodeManipulator for ArchCodeManipulator {
                 &mut old_protect,
             )
         };
-        if res.is_err() {
-            panic!("Unable to restore code region to non-writable");
+        if let Err(err) = res {
+            return Err(code_manipulate_error("VirtualProtect", err));
         }
         let res = unsafe { FlushInstructionCache(GetCurrentProcess(), Some(addr), L) };
-        if res.is_err() {
-            panic!("Failed to flush instruction cache");
+        if let Err(err) = res {
+            return Err(code_manipulate_error("FlushInstructionCache", err));
         }
+        Ok(())
     }
 }
@@ -0,0 +1,122 @@
+//! Process-wide lock to serialize code patching.
+//!
+//! If cargo resolves multiple versions of this crate, each version has its own statics, and a
+//! lock defined as a normal static cannot prevent different versions from patching the same page
+//! at the same time. As a result, the lock is defined as a weak symbol with a well-known name in
+//! OS-specific modules, and the linker will merge all definitions into one.
+//!
+//! The layout of [`SharedPatchLock`] is shared by all versions of this crate. **Never change it
+//! without changing the symbol name.**
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The lock is not held
+const UNLOCKED: usize = 0;
+/// The lock is held
+const LOCKED: usize = 1;
+
+/// Lock shared by all copies of this crate in current process
+#[repr(C)]
+pub(crate) struct SharedPatchLock {
+    /// [`UNLOCKED`] or [`LOCKED`]
+    state: AtomicUsize,
+    /// Whether fork handlers have been registered by any copy of this crate
+    fork_handlers_registered: AtomicUsize,
+}
+
+unsafe extern "C" {
+    /// Defined as weak symbol in OS-specific modules
+    #[link_name = "static_keys_patch_lock_v1"]
+    static SHARED_PATCH_LOCK: SharedPatchLock;
+}
+
+/// Times of spinning before yielding the processor when acquiring the lock
+const SPIN_LIMIT: usize = 100;
+
+/// Acquire the shared lock, spinning for a while and then yielding until it is available.
+///
+/// The lock may be held for a long time, such as stopping the world, so waiters yield instead of
+/// burning a core. Futexes are not used, since waking waiters would need a new state, which is
+/// unknown to other copies of this crate.
+fn raw_lock() {
+    let state = unsafe { &SHARED_PATCH_LOCK.state };
+    let mut spins = 0;
+    while state
+        .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        if spins < SPIN_LIMIT {
+            spins += 1;
+            core::hint::spin_loop();
+        } else {
+            crate::os::yield_now();
+        }
+    }
+}
+
+/// Release the shared lock
+fn raw_unlock() {
+    let state = unsafe { &SHARED_PATCH_LOCK.state };
+    state.store(UNLOCKED, Ordering::Release);
+}
+
+/// Guard of the patch lock. The lock is released when dropped.
+pub(crate) struct PatchGuard {
+    /// Make sure this guard can only be created by [`PatchGuard::lock`]
+    _private: (),
+}
+
+impl PatchGuard {
+    /// Acquire the patch lock, waiting until it is available
+    pub(crate) fn lock() -> Self {
+        raw_lock();
+        Self { _private: () }
+    }
+}
+
+impl Drop for PatchGuard {
+    fn drop(&mut self) {
+        raw_unlock();
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+unsafe extern "C" {
+    fn pthread_atfork(
+        prepare: Option<unsafe extern "C" fn()>,
+        parent: Option<unsafe extern "C" fn()>,
+        child: Option<unsafe extern "C" fn()>,
+    ) -> core::ffi::c_int;
+}
+
+/// Register fork handlers, so that fork never happens while patching.
+///
+/// Otherwise, the lock would never be released in child process if another thread is patching
+/// when forking, and the child process may see a static key whose status does not match its code.
+/// The lock is acquired before forking, and released in both parent and child process.
+///
+/// Only the first copy of this crate registers the handlers, since the handlers of different copies
+/// would deadlock on the same lock.
+pub(crate) fn register_fork_handlers() {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        unsafe extern "C" fn prepare() {
+            raw_lock();
+        }
+        unsafe extern "C" fn release() {
+            raw_unlock();
+        }
+
+        let registered = unsafe { &SHARED_PATCH_LOCK.fork_handlers_registered };
+        if registered
+            .compare_exchange(0, 1, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        let res = unsafe { pthread_atfork(Some(prepare), Some(release), Some(release)) };
+        if res != 0 {
+            panic!("Failed to register fork handlers.");
+        }
+    }
+}
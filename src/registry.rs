@@ -0,0 +1,281 @@
+//! Named static keys.
+//!
+//! Static keys defined by [`define_static_key_false`][crate::define_static_key_false] and
+//! [`define_static_key_true`][crate::define_static_key_true] record their names in a companion
+//! section next to the `__static_keys` section. The [`Registry`] reads this section directly, so it
+//! works in `no_std` without allocation.
+
+use crate::{StaticKeyError, StaticTrueKey, os};
+
+/// Entries in the companion section of `__static_keys`, used to record the name of a static key.
+///
+/// Constructed by [`define_static_key_false`][crate::define_static_key_false] and
+/// [`define_static_key_true`][crate::define_static_key_true]. Never use it directly.
+#[doc(hidden)]
+#[repr(C)]
+pub struct KeyNameEntry {
+    /// Address of the static key, or null for dummy entries
+    key: *const StaticTrueKey,
+    /// Start address of the name
+    name: *const u8,
+    /// Length of the name in bytes
+    name_len: usize,
+}
+
+// The entry is never modified, and the static key is Sync
+unsafe impl Sync for KeyNameEntry {}
+
+impl KeyNameEntry {
+    /// Create an entry recording `name` as the name of `key`
+    ///
+    /// Panics if `name` is not a valid name, which turns into a compilation error in statics.
+    pub const fn new<const S: bool>(key: &'static crate::StaticKey<S>, name: &'static str) -> Self {
+        assert!(
+            is_valid_name(name),
+            "Static key names must be non-empty, must not start or end with whitespaces, and must not contain `*`, `?`, `,`, `=`, `#` or `\"`"
+        );
+        Self {
+            // The S generic is useless here
+            key: key as *const _ as *const StaticTrueKey,
+            name: name.as_ptr(),
+            name_len: name.len(),
+        }
+    }
+
+    /// Create a dummy entry
+    pub(crate) const fn dummy() -> Self {
+        Self {
+            key: core::ptr::null(),
+            name: core::ptr::null(),
+            name_len: 0,
+        }
+    }
+
+    /// Whether this entry is dummy
+    fn is_dummy(&self) -> bool {
+        self.key.is_null()
+    }
+}
+
+// Insert a dummy entry here, so that the companion section is always defined even if there is no
+// named static key. See the comment of DUMMY_STATIC_KEY.
+#[used]
+#[unsafe(link_section = crate::os_static_key_name_sec_name_attr!())]
+static DUMMY_KEY_NAME_ENTRY: KeyNameEntry = KeyNameEntry::dummy();
+
+/// All entries in the companion section, including dummy entries
+fn key_name_entries() -> &'static [KeyNameEntry] {
+    let start_addr = &raw const os::KEY_NAME_ENTRY_START;
+    let stop_addr = &raw const os::KEY_NAME_ENTRY_STOP;
+    unsafe { core::slice::from_raw_parts(start_addr, stop_addr.offset_from(start_addr) as usize) }
+}
+
+/// A static key with a name, yielded by [`Registry`].
+#[derive(Clone, Copy)]
+pub struct NamedKey {
+    /// The static key. The S generic is useless here
+    key: &'static StaticTrueKey,
+    /// Name of the static key
+    name: &'static str,
+}
+
+impl NamedKey {
+    /// Name of the static key
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Address of the static key
+    pub fn addr(&self) -> usize {
+        self.key as *const _ as usize
+    }
+
+    /// Get the current status of the static key
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_enabled()
+    }
+
+    /// Whether initial status of the static key is `true`
+    pub fn initial_enabled(&self) -> bool {
+        self.key.initial
+    }
+
+    /// Set the status of the static key.
+    ///
+    /// # Safety
+    ///
+    /// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the instructions cannot be modified. Use [`try_set`][Self::try_set] to handle such
+    /// error.
+    pub unsafe fn set(&self, enabled: bool) {
+        if let Err(err) = unsafe { self.try_set(enabled) } {
+            panic!("Failed to set static key {}: {err}", self.name);
+        }
+    }
+
+    /// Set the status of the static key.
+    ///
+    /// Same as [`set`][Self::set], but return an error if the instructions cannot be modified.
+    ///
+    /// # Safety
+    ///
+    /// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+    pub unsafe fn try_set(&self, enabled: bool) -> Result<(), StaticKeyError> {
+        if enabled {
+            unsafe { self.key.try_enable() }
+        } else {
+            unsafe { self.key.try_disable() }
+        }
+    }
+}
+
+impl core::fmt::Debug for NamedKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NamedKey")
+            .field("name", &self.name)
+            .field("key", self.key)
+            .finish()
+    }
+}
+
+/// Lookup of static keys by name. Use [`registry`] to get it.
+///
+/// Names are given when defining static keys, such as `define_static_key_false!(MY_KEY, "net.trace")`,
+/// and default to the module path and identifier of the static key, such as `my_crate::net::MY_KEY`.
+/// Static keys created by [`new_static_key`][crate::new_static_key] and its variants have no names.
+#[derive(Debug, Clone, Copy)]
+pub struct Registry {
+    /// Make sure the registry can only be created by [`registry`]
+    _private: (),
+}
+
+/// Get the [`Registry`] of named static keys.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::define_static_key_false;
+///
+/// define_static_key_false!(MY_STATIC_KEY, "net.http.trace");
+///
+/// fn main() {
+///     static_keys::global_init();
+///     let registry = static_keys::registry();
+///     assert!(!registry.find("net.http.trace").unwrap().is_enabled());
+///     assert_eq!(unsafe { registry.set_matching("net.http.*", true) }, Ok(1));
+///     assert!(MY_STATIC_KEY.is_enabled());
+/// }
+/// ```
+pub const fn registry() -> Registry {
+    Registry { _private: () }
+}
+
+impl Registry {
+    /// All named static keys. The order is decided by the linker.
+    pub fn iter(&self) -> impl Iterator<Item = NamedKey> + use<> {
+        key_name_entries()
+            .iter()
+            .filter(|entry| !entry.is_dummy())
+            .map(|entry| NamedKey {
+                key: unsafe { &*entry.key },
+                name: unsafe {
+                    core::str::from_utf8_unchecked(core::slice::from_raw_parts(
+                        entry.name,
+                        entry.name_len,
+                    ))
+                },
+            })
+    }
+
+    /// Find the static key with given name. If several static keys have the same name, any of them
+    /// may be returned.
+    pub fn find(&self, name: &str) -> Option<NamedKey> {
+        self.iter().find(|key| key.name == name)
+    }
+
+    /// All static keys whose name matches `pattern`, where `*` matches any sequence of characters
+    /// and `?` matches any single character.
+    pub fn matching<'a>(&self, pattern: &'a str) -> impl Iterator<Item = NamedKey> + use<'a> {
+        self.iter()
+            .filter(move |key| wildcard_match(pattern, key.name))
+    }
+
+    /// Set the status of all static keys whose name matches `pattern` to `enabled`, and return the
+    /// count of matched static keys. See [`matching`][Self::matching] for the syntax of `pattern`.
+    ///
+    /// If the instructions of any static key cannot be modified, the error is returned, and static
+    /// keys after it are left untouched.
+    ///
+    /// # Safety
+    ///
+    /// See [`GenericStaticKey::enable`][crate::GenericStaticKey::enable].
+    pub unsafe fn set_matching(
+        &self,
+        pattern: &str,
+        enabled: bool,
+    ) -> Result<usize, StaticKeyError> {
+        let mut count = 0;
+        for key in self.matching(pattern) {
+            unsafe { key.try_set(enabled) }?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Whether `name` can be given to a static key.
+///
+/// Characters special to wildcard patterns and configurations are rejected, so that a
+/// name is always a pattern matching exactly itself, and can be written into configurations as is.
+pub(crate) const fn is_valid_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    let (Some(first), Some(last)) = (bytes.first(), bytes.last()) else {
+        return false;
+    };
+    if first.is_ascii_whitespace() || last.is_ascii_whitespace() {
+        return false;
+    }
+    let mut i = 0;
+    while i < bytes.len() {
+        if matches!(bytes[i], b'*' | b'?' | b',' | b'=' | b'#' | b'"') {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any sequence of characters and `?` matches
+/// any single character.
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut pattern_chars = pattern.chars();
+    let mut name_chars = name.chars();
+    // Positions right after the last `*`, to retry from when the rest does not match
+    let mut backtrack = None;
+    loop {
+        let name_rest = name_chars.clone();
+        match (pattern_chars.next(), name_chars.next()) {
+            (Some('*'), _) => {
+                name_chars = name_rest.clone();
+                backtrack = Some((pattern_chars.clone(), name_rest));
+                continue;
+            }
+            (Some(p), Some(n)) if p == '?' || p == n => continue,
+            (None, None) => return true,
+            _ => {}
+        }
+        // Mismatch, let the last `*` consume one more character
+        let Some((star_pattern, mut star_name)) = backtrack.take() else {
+            return false;
+        };
+        if star_name.next().is_none() {
+            return false;
+        }
+        pattern_chars = star_pattern.clone();
+        name_chars = star_name.clone();
+        backtrack = Some((star_pattern, star_name));
+    }
+}
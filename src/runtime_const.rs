@@ -0,0 +1,383 @@
+//! Runtime constants, modelled on `runtime_const` of Linux kernel.
+//!
+//! A runtime constant is a value fixed after startup, such as a shift amount, a table base pointer
+//! or a hash seed. It is defined by [`define_runtime_const`], and read by [`runtime_const`], which
+//! loads the value as the immediate of instructions instead of reading it from memory. Setting the
+//! value with [`runtime_const_init`] patches the immediates at every site.
+//!
+//! The instructions loading the value are:
+//!
+//! * x86_64: `movabs rax, imm64`
+//! * x86: `mov eax, imm32` and `mov edx, imm32`
+//! * aarch64: `movz` and three `movk`
+//! * riscv64: `lui`, `addiw`, `slli`, `lui`, `addiw` and `add`
+//! * loongarch64: `lu12i.w`, `ori`, `lu32i.d` and `lu52i.d`
+//!
+//! Each site is recorded in the `__runtime_consts` section with the same layout as jump entries,
+//! where the code address is the start of the instructions, the target address is the end of the
+//! instructions, and the key address is the runtime constant.
+
+use crate::{
+    JumpEntry, StaticKeyError,
+    arch::{self, ARCH_RUNTIME_CONST_INS_LENGTH},
+    code_manipulate::{CodeManipulator, DummyCodeManipulator},
+    os,
+    patch_lock::PatchGuard,
+    transaction::MAX_BATCH_PATCHES,
+};
+
+#[doc(hidden)]
+pub use crate::arch::arch_runtime_const_immediates;
+
+/// Runtime constant generic over code manipulator.
+///
+/// The `const S: u64` is the initial value, which is loaded by the sites before the value is set.
+/// The struct layout is the same for all `S`.
+pub struct GenericRuntimeConst<M: CodeManipulator, const S: u64> {
+    /// Current value
+    ///
+    /// This field is defined as `AtomicU64` to allow interior mutability of static variables to
+    /// avoid creating mutable static.
+    value: core::sync::atomic::AtomicU64,
+    /// Start address of associated entries in __runtime_consts section.
+    ///
+    /// This value is 0 at static. After calling [`global_init`][crate::global_init], the value will
+    /// be assigned correctly.
+    entries: usize,
+    /// Phantom data to hold `M`
+    phantom: core::marker::PhantomData<M>,
+}
+
+/// Runtime constant with initial value `S`, whose sites are modified by
+/// [`crate::os::ArchCodeManipulator`].
+pub type RuntimeConst<const S: u64 = 0> = GenericRuntimeConst<crate::os::ArchCodeManipulator, S>;
+
+// Insert a dummy runtime constant here, and use this at global_init function. This is to avoid
+// linker failure when there is no runtime constants, and thus the __runtime_consts section is never
+// defined.
+static DUMMY_RUNTIME_CONST: GenericRuntimeConst<DummyCodeManipulator, 0> =
+    GenericRuntimeConst::new();
+
+impl<M: CodeManipulator, const S: u64> GenericRuntimeConst<M, S> {
+    /// Create a new runtime constant with initial value `S`.
+    const fn new() -> Self {
+        Self {
+            value: core::sync::atomic::AtomicU64::new(S),
+            entries: 0,
+            phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Initial value
+    #[inline(always)]
+    pub const fn initial_value(&self) -> u64 {
+        S
+    }
+
+    /// Current value
+    pub fn value(&self) -> u64 {
+        self.value.load(core::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// All entries associated with current runtime constant.
+    ///
+    /// This slice is empty before [`global_init`][crate::global_init] or if this runtime constant
+    /// is never used.
+    fn runtime_const_entries(&self) -> &'static [JumpEntry] {
+        let entry_start_addr = self.entries as *const JumpEntry;
+        if entry_start_addr.is_null() {
+            // This runtime constant is never used
+            return &[];
+        }
+        let entry_stop_addr = &raw const os::RUNTIME_CONST_ENTRY_STOP;
+        let mut entry_addr = entry_start_addr;
+        while entry_addr < entry_stop_addr {
+            let entry = unsafe { &*entry_addr };
+            // Not the same runtime constant
+            if self as *const _ as usize != entry.key_addr() {
+                break;
+            }
+            entry_addr = unsafe { entry_addr.add(1) };
+        }
+        unsafe {
+            core::slice::from_raw_parts(
+                entry_start_addr,
+                entry_addr.offset_from(entry_start_addr) as usize,
+            )
+        }
+    }
+
+    /// Make every site of this runtime constant load `value`. Do nothing if current value is
+    /// already `value`.
+    ///
+    /// Modifications of runtime constants are serialized by the same process-wide lock as static
+    /// keys, so this method can be called in parallel. If [`global_init`][crate::global_init] has
+    /// not been called yet, it is called before modifying instructions.
+    ///
+    /// # Safety
+    ///
+    /// Never call this method when other threads may be executing codes in the same code page. This
+    /// method may manipulate code region memory protection, and it may lead to unexpected behaviors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the instructions cannot be modified. Use [`try_init`][Self::try_init] to handle
+    /// such error.
+    pub unsafe fn init(&self, value: u64) {
+        if let Err(err) = unsafe { self.try_init(value) } {
+            panic!("Failed to initialize runtime constant: {err}");
+        }
+    }
+
+    /// Make every site of this runtime constant load `value`. Do nothing if current value is
+    /// already `value`.
+    ///
+    /// Same as [`init`][Self::init], but return an error if the instructions cannot be modified.
+    /// In such case, the value of this runtime constant and all modified instructions are rolled
+    /// back.
+    ///
+    /// # Safety
+    ///
+    /// See [`init`][Self::init].
+    pub unsafe fn try_init(&self, value: u64) -> Result<(), StaticKeyError> {
+        crate::ensure_global_init()?;
+        let _guard = PatchGuard::lock();
+        unsafe {
+            runtime_const_update_locked::<M>(self.runtime_const_entries(), &self.value, value)
+        }
+    }
+}
+
+impl<M: CodeManipulator, const S: u64> core::fmt::Debug for GenericRuntimeConst<M, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RuntimeConst")
+            .field("value", &self.value())
+            .field("initial_value", &S)
+            .finish()
+    }
+}
+
+/// Create a new runtime constant with initial value `S`.
+///
+/// This method should be called to initialize a runtime constant. It is UB to use this method to
+/// create a runtime constant on stack or heap, and use this runtime constant at sites.
+///
+/// Use [`define_runtime_const`] for short.
+pub const fn new_runtime_const<const S: u64>() -> RuntimeConst<S> {
+    RuntimeConst::new()
+}
+
+/// Make every site of `runtime_const` load `value`. This is the same as
+/// [`runtime_const.init(value)`][GenericRuntimeConst::init].
+///
+/// # Safety
+///
+/// See [`GenericRuntimeConst::init`].
+///
+/// # Panics
+///
+/// Panics if the instructions cannot be modified.
+pub unsafe fn runtime_const_init<M: CodeManipulator, const S: u64>(
+    runtime_const: &GenericRuntimeConst<M, S>,
+    value: u64,
+) {
+    unsafe { runtime_const.init(value) }
+}
+
+/// Current instructions at the site of `entry`
+fn read_instructions(entry: &JumpEntry) -> [u8; ARCH_RUNTIME_CONST_INS_LENGTH] {
+    // The instruction is always readable
+    unsafe {
+        core::ptr::read_volatile(entry.code_addr() as *const [u8; ARCH_RUNTIME_CONST_INS_LENGTH])
+    }
+}
+
+/// Whether `addr` lies in the instructions of any runtime constant site. Must be called after
+/// [`global_init`][crate::global_init].
+pub(crate) fn is_patch_site(addr: usize) -> bool {
+    let entry_start_addr = &raw const os::RUNTIME_CONST_ENTRY_START;
+    let entry_stop_addr = &raw const os::RUNTIME_CONST_ENTRY_STOP;
+    let entries = unsafe {
+        let len = entry_stop_addr.offset_from(entry_start_addr) as usize;
+        core::slice::from_raw_parts(entry_start_addr, len)
+    };
+    entries.iter().any(|entry| {
+        !entry.is_dummy()
+            && (entry.code_addr()..entry.code_addr() + ARCH_RUNTIME_CONST_INS_LENGTH)
+                .contains(&addr)
+    })
+}
+
+/// Update entries in __runtime_consts section to be absolute address, and sort them. Then each
+/// runtime constant is assigned with the start address of its entries. Called in
+/// [`global_init`][crate::global_init].
+///
+/// # Panics
+///
+/// Panics if the instructions of any runtime constant site are not the expected length.
+pub(crate) fn global_init() {
+    // Make sure there are at least one entry
+    let _ = crate::runtime_const!(DUMMY_RUNTIME_CONST: u64);
+
+    let entry_start_addr = &raw mut os::RUNTIME_CONST_ENTRY_START;
+    let entry_stop_addr = &raw mut os::RUNTIME_CONST_ENTRY_STOP;
+    let entries = unsafe {
+        let len = entry_stop_addr.offset_from(entry_start_addr) as usize;
+        core::slice::from_raw_parts_mut(entry_start_addr, len)
+    };
+    for entry in entries.iter_mut() {
+        if entry.is_dummy() {
+            continue;
+        }
+        entry.make_relative_address_absolute();
+        // The target address is the end of the instructions
+        if entry.target_addr().wrapping_sub(entry.code_addr()) != ARCH_RUNTIME_CONST_INS_LENGTH {
+            panic!(
+                "Runtime constant instructions at {:#x} are not {ARCH_RUNTIME_CONST_INS_LENGTH} bytes.",
+                entry.code_addr()
+            );
+        }
+    }
+    // The entries are sorted by address of runtime constant and code address
+    entries.sort_unstable_by_key(|entry| (entry.key_addr(), entry.code_addr()));
+    let mut last_key_addr = 0;
+    for entry in entries {
+        if entry.is_dummy() {
+            continue;
+        }
+        let key_addr = entry.key_addr();
+        if key_addr == last_key_addr {
+            continue;
+        }
+        // The M and S generic is useless here
+        let runtime_const =
+            unsafe { &mut *(key_addr as *mut GenericRuntimeConst<DummyCodeManipulator, 0>) };
+        runtime_const.entries = entry as *mut _ as usize;
+        last_key_addr = key_addr;
+    }
+}
+
+/// Make the sites in `entries` load `value`, and store `value`.
+///
+/// The instructions are verified before writing, and rolled back on failure.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`] held. See [`GenericRuntimeConst::init`] for other
+/// requirements.
+unsafe fn runtime_const_update_locked<M: CodeManipulator>(
+    entries: &[JumpEntry],
+    current: &core::sync::atomic::AtomicU64,
+    value: u64,
+) -> Result<(), StaticKeyError> {
+    let old_value = current.load(core::sync::atomic::Ordering::Relaxed);
+    if old_value == value {
+        return Ok(());
+    }
+    for entry in entries {
+        let instructions = read_instructions(entry);
+        if instructions != arch::arch_runtime_const_instruction(&instructions, old_value) {
+            return Err(StaticKeyError::UnexpectedInstruction {
+                site: entry.code_addr(),
+            });
+        }
+    }
+    current.store(value, core::sync::atomic::Ordering::Relaxed);
+    if let Err((end, err)) = unsafe { write_sites::<M>(entries, value, usize::MAX) } {
+        current.store(old_value, core::sync::atomic::Ordering::Relaxed);
+        if let Err((_, rollback_err)) = unsafe { write_sites::<M>(entries, old_value, end) } {
+            panic!("Failed to roll back runtime constant after {err}: {rollback_err}");
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Make the sites in `entries` whose address is lower than `end` load `value`.
+///
+/// On failure, returns the end address of the instructions that may have been written, along
+/// with the error.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`] held.
+unsafe fn write_sites<M: CodeManipulator>(
+    entries: &[JumpEntry],
+    value: u64,
+    end: usize,
+) -> Result<(), (usize, StaticKeyError)> {
+    let mut patches =
+        [(core::ptr::null_mut(), [0u8; ARCH_RUNTIME_CONST_INS_LENGTH]); MAX_BATCH_PATCHES];
+    let entries = entries.iter().filter(|entry| entry.code_addr() < end);
+    let mut entries = entries.peekable();
+    while entries.peek().is_some() {
+        let mut patch_count = 0;
+        for (patch, entry) in patches.iter_mut().zip(entries.by_ref()) {
+            let instructions =
+                arch::arch_runtime_const_instruction(&read_instructions(entry), value);
+            *patch = (entry.code_addr() as *mut _, instructions);
+            patch_count += 1;
+        }
+        let written = &patches[..patch_count];
+        if let Err(err) = unsafe { M::write_code_batch(written) } {
+            let (first_addr, _) = written[0];
+            let (last_addr, _) = written[patch_count - 1];
+            return Err((
+                last_addr as usize + 1,
+                StaticKeyError::write_code(first_addr as usize, err),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Define a runtime constant with initial value, which is 0 by default.
+///
+/// This macro will define a static variable without documentations and visibility modifiers.
+/// Use [`new_runtime_const`] for customization.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::define_runtime_const;
+///
+/// define_runtime_const!(MY_HASH_SEED_RUNTIME_CONST);
+/// define_runtime_const!(MY_SHIFT_RUNTIME_CONST, 12);
+/// ```
+#[macro_export]
+macro_rules! define_runtime_const {
+    ($key: ident) => {
+        $crate::define_runtime_const!($key, 0);
+    };
+    ($key: ident, $initial_value: expr) => {
+        static $key: $crate::RuntimeConst<{ $initial_value }> = $crate::new_runtime_const();
+    };
+}
+
+/// Load the value of a runtime constant as the immediate of instructions, and cast it to given
+/// integer type with `as`.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::{define_runtime_const, runtime_const, runtime_const_init};
+///
+/// define_runtime_const!(MY_BUCKET_SHIFT_RUNTIME_CONST);
+///
+/// fn bucket(hash: u32) -> u32 {
+///     hash >> runtime_const!(MY_BUCKET_SHIFT_RUNTIME_CONST: u32)
+/// }
+///
+/// fn main() {
+///     static_keys::global_init();
+///     assert_eq!(bucket(0x12345678), 0x12345678);
+///     unsafe { runtime_const_init(&MY_BUCKET_SHIFT_RUNTIME_CONST, 24) };
+///     assert_eq!(bucket(0x12345678), 0x12);
+/// }
+/// ```
+#[macro_export]
+macro_rules! runtime_const {
+    ($key: path: $ty: ty) => {
+        ($crate::arch_runtime_const!($key) as $ty)
+    };
+}
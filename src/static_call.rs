@@ -0,0 +1,317 @@
+//! Static calls, modelled on `static_call` of Linux kernel.
+//!
+//! A static call is defined by [`define_static_call`] with a function signature and a default
+//! target. Calling it with [`static_call`] compiles to a direct call to its trampoline, which is a
+//! single JMP instruction to the current target. Updating the target with
+//! [`update`][GenericStaticCall::update] rewrites the JMP instruction, so no indirect call is
+//! involved in any case.
+//!
+//! Each trampoline is recorded in the `__static_calls` section with the same layout as jump
+//! entries, where the code address is the trampoline, the target address is the current target,
+//! and the key address is the static call. The entries are sorted by address of static call in
+//! [`global_init`][crate::global_init].
+//!
+//! The trampoline is a naked function local to the crate defining the static call, so that the
+//! compiler calls it with a PC-relative CALL or JMP instead of through GOT or PLT when
+//! optimizations are enabled.
+
+use crate::{
+    JumpEntry, StaticKeyError,
+    arch::{self, ARCH_STATIC_CALL_INS_LENGTH},
+    code_manipulate::CodeManipulator,
+    os,
+    patch_lock::PatchGuard,
+};
+
+/// Static call generic over code manipulator.
+///
+/// `F` is the function pointer type of this static call, such as `fn(u32) -> u32`. The `call` field
+/// is never modified, so that the compiler knows the trampoline at compile time, and
+/// calls it directly.
+pub struct GenericStaticCall<M: CodeManipulator, F: Copy + 'static> {
+    /// The trampoline of this static call
+    call: F,
+    /// Phantom data to hold `M`
+    phantom: core::marker::PhantomData<M>,
+}
+
+/// Static call to hold the trampoline, whose target is modified by [`crate::os::ArchCodeManipulator`].
+pub type StaticCall<F> = GenericStaticCall<crate::os::ArchCodeManipulator, F>;
+
+// Insert a dummy static call here, and use this at global_init function. This is to avoid linker
+// failure when there is no static calls, and thus the __static_calls section is never defined.
+crate::define_static_call!(DUMMY_STATIC_CALL, fn() = dummy_static_call_target);
+
+/// Default target of [`DUMMY_STATIC_CALL`]
+fn dummy_static_call_target() {}
+
+impl<M: CodeManipulator, F: Copy + 'static> GenericStaticCall<M, F> {
+    /// Create a new static call with given trampoline. Use [`define_static_call`] instead.
+    ///
+    /// # Safety
+    ///
+    /// `call` must be the trampoline defined along with this static call, whose entry records the
+    /// address of this static call.
+    #[doc(hidden)]
+    pub const unsafe fn new(call: F) -> Self {
+        Self {
+            call,
+            phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Function pointer to the trampoline. Use [`static_call`] for short.
+    #[inline(always)]
+    pub fn get(&self) -> F {
+        self.call
+    }
+
+    /// Make this static call jump to `target`. Do nothing if `target` is already the current target.
+    ///
+    /// Modifications of static calls are serialized by the same process-wide lock as static keys,
+    /// so this method can be called in parallel. If [`global_init`][crate::global_init] has not
+    /// been called yet, it is called before modifying instructions.
+    ///
+    /// # Safety
+    ///
+    /// Never call this method when other threads may be executing codes in the same code page,
+    /// including the trampoline itself. This method may manipulate code region memory protection,
+    /// and it may lead to unexpected behaviors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the instruction cannot be modified, or `target` is too far away from the trampoline
+    /// on current architecture, such as +/-128MB on aarch64 and loongarch64. Use
+    /// [`try_update`][Self::try_update] to handle such error.
+    pub unsafe fn update(&self, target: F) {
+        if let Err(err) = unsafe { self.try_update(target) } {
+            panic!("Failed to update static call: {err}");
+        }
+    }
+
+    /// Make this static call jump to `target`. Do nothing if `target` is already the current target.
+    ///
+    /// Same as [`update`][Self::update], but return an error if the instruction cannot be modified.
+    /// In such case, the target and the instruction are rolled back.
+    ///
+    /// # Safety
+    ///
+    /// See [`update`][Self::update].
+    pub unsafe fn try_update(&self, target: F) -> Result<(), StaticKeyError> {
+        crate::ensure_global_init()?;
+        let _guard = PatchGuard::lock();
+        unsafe { static_call_update_locked::<M>(self as *const _ as usize, fn_addr(target)) }
+    }
+
+    /// Whether current target is `target`
+    pub fn is_target(&self, target: F) -> bool {
+        let call_addr = self as *const _ as usize;
+        if crate::ensure_global_init().is_err() {
+            // Nothing is modified if global_init fails, so the default target is compared
+            return static_call_entries()
+                .iter()
+                .filter(|entry| !entry.is_dummy())
+                .map(JumpEntry::to_absolute)
+                .any(|entry| {
+                    entry.key_addr() == call_addr && entry.target_addr() == fn_addr(target)
+                });
+        }
+        let entry = unsafe { &*find_entry(call_addr) };
+        entry.target_addr() == fn_addr(target)
+    }
+}
+
+/// Address of function pointer `f`
+fn fn_addr<F: Copy>(f: F) -> usize {
+    const {
+        assert!(core::mem::size_of::<F>() == core::mem::size_of::<usize>());
+    }
+    unsafe { core::mem::transmute_copy(&f) }
+}
+
+/// All entries in __static_calls section, including dummy entries.
+fn static_call_entries() -> &'static [JumpEntry] {
+    let entry_start_addr = &raw const os::STATIC_CALL_ENTRY_START;
+    let entry_stop_addr = &raw const os::STATIC_CALL_ENTRY_STOP;
+    unsafe {
+        let len = entry_stop_addr.offset_from(entry_start_addr) as usize;
+        core::slice::from_raw_parts(entry_start_addr, len)
+    }
+}
+
+/// Whether `addr` lies in the JMP instruction of any trampoline. Must be called after
+/// [`global_init`][crate::global_init].
+pub(crate) fn is_patch_site(addr: usize) -> bool {
+    static_call_entries().iter().any(|entry| {
+        !entry.is_dummy()
+            && (entry.code_addr()..entry.code_addr() + ARCH_STATIC_CALL_INS_LENGTH).contains(&addr)
+    })
+}
+
+/// Entry of static call at `call_addr`. Must be called after [`global_init`][crate::global_init].
+fn find_entry(call_addr: usize) -> *mut JumpEntry {
+    let Ok(index) =
+        static_call_entries().binary_search_by_key(&call_addr, |entry| entry.key_addr())
+    else {
+        panic!("Static call at {call_addr:#x} is not defined by define_static_call!");
+    };
+    unsafe { (&raw mut os::STATIC_CALL_ENTRY_START).add(index) }
+}
+
+/// Check whether the default target of each static call is in the range of its trampoline. Called
+/// in [`try_global_init`][crate::try_global_init] before [`global_init`].
+pub(crate) fn check_targets() -> Result<(), StaticKeyError> {
+    for entry in static_call_entries() {
+        if entry.is_dummy() {
+            continue;
+        }
+        let entry = entry.to_absolute();
+        if !arch::arch_static_call_target_in_range(&entry) {
+            return Err(StaticKeyError::TargetOutOfRange {
+                site: entry.code_addr(),
+                target: entry.target_addr(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Update entries in __static_calls section to be absolute address, and sort them. Called in
+/// [`global_init`][crate::global_init] after [`check_targets`].
+pub(crate) fn global_init() {
+    // Make sure there are at least one entry
+    crate::static_call!(DUMMY_STATIC_CALL)();
+
+    let entry_start_addr = &raw mut os::STATIC_CALL_ENTRY_START;
+    let entry_stop_addr = &raw mut os::STATIC_CALL_ENTRY_STOP;
+    let entries = unsafe {
+        let len = entry_stop_addr.offset_from(entry_start_addr) as usize;
+        core::slice::from_raw_parts_mut(entry_start_addr, len)
+    };
+    for entry in entries.iter_mut() {
+        if entry.is_dummy() {
+            continue;
+        }
+        entry.make_relative_address_absolute();
+    }
+    // The entries are sorted by address of static call, and dummy entries are placed at the beginning
+    entries.sort_unstable_by_key(|entry| entry.key_addr());
+}
+
+/// Make the trampoline of static call at `call_addr` jump to `target`.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`] held. See [`GenericStaticCall::update`] for other requirements.
+unsafe fn static_call_update_locked<M: CodeManipulator>(
+    call_addr: usize,
+    target: usize,
+) -> Result<(), StaticKeyError> {
+    let entry = unsafe { &mut *find_entry(call_addr) };
+    if entry.target_addr() == target {
+        return Ok(());
+    }
+    let site = entry.code_addr();
+    let new_entry = JumpEntry {
+        code: site,
+        target,
+        key: entry.key,
+    };
+    if !arch::arch_static_call_target_in_range(&new_entry) {
+        return Err(StaticKeyError::TargetOutOfRange { site, target });
+    }
+    let old_instruction = arch::arch_static_call_instruction(entry);
+    // The instruction is always readable
+    let current_instruction =
+        unsafe { core::ptr::read_volatile(site as *const [u8; ARCH_STATIC_CALL_INS_LENGTH]) };
+    if current_instruction != old_instruction {
+        return Err(StaticKeyError::UnexpectedInstruction { site });
+    }
+
+    let new_instruction = arch::arch_static_call_instruction(&new_entry);
+    if let Err(err) = unsafe { M::write_code(site as *mut _, &new_instruction) } {
+        // The instruction may be partially written
+        if let Err(rollback_err) = unsafe { M::write_code(site as *mut _, &old_instruction) } {
+            panic!("Failed to roll back static call after {err}: {rollback_err}");
+        }
+        return Err(StaticKeyError::write_code(site, err));
+    }
+    entry.target = target;
+    Ok(())
+}
+
+/// Define a static call with given function signature and default target.
+///
+/// This macro will define a static variable without documentations and visibility modifiers, along
+/// with its trampoline as a naked function.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::define_static_call;
+///
+/// fn encode_plain(input: u32) -> u32 {
+///     input
+/// }
+///
+/// define_static_call!(MY_ENCODE_STATIC_CALL, fn(u32) -> u32 = encode_plain);
+///
+/// fn main() {}
+/// ```
+#[macro_export]
+macro_rules! define_static_call {
+    ($call: ident, fn($($arg: ty),* $(,)?) $(-> $ret: ty)? = $default: path) => {
+        static $call: $crate::StaticCall<fn($($arg),*) $(-> $ret)?> = {
+            // The trampoline only jumps to the target, so the arguments and the return value are
+            // left untouched regardless of its declared signature and ABI
+            #[unsafe(naked)]
+            unsafe extern "C" fn trampoline() {
+                ::core::arch::naked_asm!(
+                    $crate::arch_static_call_trampoline_asm_template!(),
+                    sym $default,
+                    sym $call,
+                )
+            }
+            // Make sure the default target has the same signature
+            const _: fn($($arg),*) $(-> $ret)? = $default;
+            unsafe {
+                $crate::StaticCall::new(::core::mem::transmute::<
+                    unsafe extern "C" fn(),
+                    fn($($arg),*) $(-> $ret)?,
+                >(trampoline))
+            }
+        };
+    };
+}
+
+/// Get the function pointer of a static call defined by [`define_static_call`], which is a direct
+/// call to its trampoline when called.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::{define_static_call, static_call};
+///
+/// fn checksum_sum(input: &[u8]) -> u32 {
+///     input.iter().map(|byte| *byte as u32).sum()
+/// }
+///
+/// fn checksum_xor(input: &[u8]) -> u32 {
+///     input.iter().fold(0, |acc, byte| acc ^ *byte as u32)
+/// }
+///
+/// define_static_call!(MY_CHECKSUM_STATIC_CALL, fn(&[u8]) -> u32 = checksum_sum);
+///
+/// fn main() {
+///     static_keys::global_init();
+///     assert_eq!(static_call!(MY_CHECKSUM_STATIC_CALL)(&[1, 2, 3]), 6);
+///     unsafe { MY_CHECKSUM_STATIC_CALL.update(checksum_xor) };
+///     assert_eq!(static_call!(MY_CHECKSUM_STATIC_CALL)(&[1, 2, 3]), 0);
+/// }
+/// ```
+#[macro_export]
+macro_rules! static_call {
+    ($call:path) => {
+        $call.get()
+    };
+}
@@ -0,0 +1,461 @@
+//! N-way static switches, which select one of several arms with a single patchable jump.
+//!
+//! A static switch is an integer-valued key defined by [`define_static_switch`]. Each
+//! [`static_switch`] site holds one JMP instruction to the arm of current value, and the relative
+//! addresses of all arms are recorded in a per-site table. Changing the value with
+//! [`set`][GenericStaticSwitch::set] retargets the JMP instruction of every site, so selecting among
+//! three or four strategies costs one jump instead of a chain of
+//! [`static_branch_unlikely`][crate::static_branch_unlikely] checks.
+//!
+//! Each site is recorded in the `__static_switches` section with the same layout as jump entries,
+//! where the code address is the JMP instruction, the target address is the table of arms, and the
+//! key address is the static switch. The table starts with the count of arms, followed by the
+//! relative address of each arm from its own slot.
+
+use crate::{
+    JumpEntry, JumpLabelType, StaticKeyError,
+    arch::{self, ARCH_JUMP_INS_LENGTH},
+    code_manipulate::{CodeManipulator, DummyCodeManipulator},
+    os,
+    patch_lock::PatchGuard,
+    transaction::MAX_BATCH_PATCHES,
+};
+
+/// Static switch generic over code manipulator.
+///
+/// The `const N: usize` is the count of arms, and the value is always in `0..N`. The
+/// `const S: usize` is the initial value, which only affects the initial instructions of sites. The
+/// struct layout is the same for all `N` and `S`.
+pub struct GenericStaticSwitch<M: CodeManipulator, const N: usize, const S: usize> {
+    /// Current value
+    ///
+    /// This field is defined as `AtomicUsize` to allow interior mutability of static variables to
+    /// avoid creating mutable static.
+    value: core::sync::atomic::AtomicUsize,
+    /// Start address of associated entries in __static_switches section.
+    ///
+    /// This value is 0 at static. After calling [`global_init`][crate::global_init], the value will
+    /// be assigned correctly.
+    entries: usize,
+    /// Phantom data to hold `M`
+    phantom: core::marker::PhantomData<M>,
+}
+
+/// Static switch with `N` arms and initial value `S`, whose sites are modified by
+/// [`crate::os::ArchCodeManipulator`].
+pub type StaticSwitch<const N: usize, const S: usize = 0> =
+    GenericStaticSwitch<crate::os::ArchCodeManipulator, N, S>;
+
+// Insert a dummy static switch here, and use this at global_init function. This is to avoid linker
+// failure when there is no static switches, and thus the __static_switches section is never defined.
+static DUMMY_STATIC_SWITCH: GenericStaticSwitch<DummyCodeManipulator, 1, 0> =
+    GenericStaticSwitch::new();
+
+impl<M: CodeManipulator, const N: usize, const S: usize> GenericStaticSwitch<M, N, S> {
+    /// Create a new static switch with initial value `S`.
+    const fn new() -> Self {
+        const {
+            assert!(
+                S < N,
+                "Initial value of static switch must be less than count of arms"
+            );
+        }
+        Self {
+            value: core::sync::atomic::AtomicUsize::new(S),
+            entries: 0,
+            phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Initial value
+    #[inline(always)]
+    pub const fn initial_value(&self) -> usize {
+        S
+    }
+
+    /// Count of arms
+    #[inline(always)]
+    pub const fn arm_count(&self) -> usize {
+        N
+    }
+
+    /// Current value
+    pub fn value(&self) -> usize {
+        self.value.load(core::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// All entries associated with current static switch.
+    ///
+    /// This slice is empty before [`global_init`][crate::global_init] or if this static switch is
+    /// never used.
+    fn switch_entries(&self) -> &'static [JumpEntry] {
+        let entry_start_addr = self.entries as *const JumpEntry;
+        if entry_start_addr.is_null() {
+            // This static switch is never used
+            return &[];
+        }
+        let entry_stop_addr = &raw const os::SWITCH_ENTRY_STOP;
+        let mut entry_addr = entry_start_addr;
+        while entry_addr < entry_stop_addr {
+            let entry = unsafe { &*entry_addr };
+            // Not the same switch
+            if self as *const _ as usize != entry.key_addr() {
+                break;
+            }
+            entry_addr = unsafe { entry_addr.add(1) };
+        }
+        unsafe {
+            core::slice::from_raw_parts(
+                entry_start_addr,
+                entry_addr.offset_from(entry_start_addr) as usize,
+            )
+        }
+    }
+
+    /// Make every site of this static switch jump to the arm of `value`. Do nothing if current value
+    /// is already `value`.
+    ///
+    /// Modifications of static switches are serialized by the same process-wide lock as static keys,
+    /// so this method can be called in parallel. If [`global_init`][crate::global_init] has not
+    /// been called yet, it is called before modifying instructions.
+    ///
+    /// # Safety
+    ///
+    /// Never call this method when other threads may be executing codes in the same code page. This
+    /// method may manipulate code region memory protection, and it may lead to unexpected behaviors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is not less than `N`, or the instructions cannot be modified. Use
+    /// [`try_set`][Self::try_set] to handle the latter error.
+    pub unsafe fn set(&self, value: usize) {
+        if let Err(err) = unsafe { self.try_set(value) } {
+            panic!("Failed to set static switch: {err}");
+        }
+    }
+
+    /// Make every site of this static switch jump to the arm of `value`. Do nothing if current value
+    /// is already `value`.
+    ///
+    /// Same as [`set`][Self::set], but return an error if the instructions cannot be modified. In
+    /// such case, the value of this static switch and all modified instructions are rolled back.
+    ///
+    /// # Safety
+    ///
+    /// See [`set`][Self::set].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is not less than `N`.
+    pub unsafe fn try_set(&self, value: usize) -> Result<(), StaticKeyError> {
+        assert!(
+            value < N,
+            "Value {value} of static switch is out of range 0..{N}"
+        );
+        crate::ensure_global_init()?;
+        let _guard = PatchGuard::lock();
+        unsafe { static_switch_update_locked::<M>(self.switch_entries(), &self.value, value) }
+    }
+}
+
+impl<M: CodeManipulator, const N: usize, const S: usize> core::fmt::Debug
+    for GenericStaticSwitch<M, N, S>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("StaticSwitch")
+            .field("value", &self.value())
+            .field("arm_count", &N)
+            .field("initial_value", &S)
+            .finish()
+    }
+}
+
+/// Create a new static switch with `N` arms and initial value `S`.
+///
+/// This method should be called to initialize a static switch. It is UB to use this method to
+/// create a static switch on stack or heap, and use this static switch to control branches.
+///
+/// Use [`define_static_switch`] for short.
+pub const fn new_static_switch<const N: usize, const S: usize>() -> StaticSwitch<N, S> {
+    StaticSwitch::new()
+}
+
+/// Address of the arm of `value` recorded in the table of `entry`. `value` must be less than the
+/// count of arms.
+fn arm_addr(entry: &JumpEntry, value: usize) -> usize {
+    // The first slot is the count of arms
+    let slot = entry.target_addr() + 4 * (1 + value);
+    let relative_addr = unsafe { core::ptr::read(slot as *const i32) };
+    slot.wrapping_add_signed(relative_addr as isize)
+}
+
+/// Count of arms recorded in the table of `entry`
+fn arm_count(entry: &JumpEntry) -> usize {
+    (unsafe { core::ptr::read(entry.target_addr() as *const u32) }) as usize
+}
+
+/// Jump entry from the site of `entry` to the arm of `value`
+fn arm_jump_entry(entry: &JumpEntry, value: usize) -> JumpEntry {
+    JumpEntry {
+        code: entry.code_addr(),
+        target: arm_addr(entry, value),
+        key: 0,
+    }
+}
+
+/// JMP instruction at the site of `entry` when the static switch has `value`
+fn arm_instruction(entry: &JumpEntry, value: usize) -> [u8; ARCH_JUMP_INS_LENGTH] {
+    arch::arch_jump_entry_instruction(JumpLabelType::Jmp, &arm_jump_entry(entry, value))
+}
+
+/// All entries in __static_switches section, including dummy entries.
+fn static_switch_entries() -> &'static [JumpEntry] {
+    let entry_start_addr = &raw const os::SWITCH_ENTRY_START;
+    let entry_stop_addr = &raw const os::SWITCH_ENTRY_STOP;
+    unsafe {
+        let len = entry_stop_addr.offset_from(entry_start_addr) as usize;
+        core::slice::from_raw_parts(entry_start_addr, len)
+    }
+}
+
+/// Whether `addr` lies in the JMP instruction of any static switch site. Must be called after
+/// [`global_init`][crate::global_init].
+pub(crate) fn is_patch_site(addr: usize) -> bool {
+    static_switch_entries().iter().any(|entry| {
+        !entry.is_dummy()
+            && (entry.code_addr()..entry.code_addr() + ARCH_JUMP_INS_LENGTH).contains(&addr)
+    })
+}
+
+/// Check whether each arm of each static switch site is in the range of its JMP instruction. Called
+/// in [`try_global_init`][crate::try_global_init] before [`global_init`].
+pub(crate) fn check_targets() -> Result<(), StaticKeyError> {
+    for entry in static_switch_entries() {
+        if entry.is_dummy() {
+            continue;
+        }
+        let entry = entry.to_absolute();
+        for value in 0..arm_count(&entry) {
+            let arm_entry = arm_jump_entry(&entry, value);
+            if !arch::arch_jump_target_in_range(&arm_entry) {
+                return Err(StaticKeyError::TargetOutOfRange {
+                    site: arm_entry.code_addr(),
+                    target: arm_entry.target_addr(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Update entries in __static_switches section to be absolute address, and sort them. Then each
+/// static switch is assigned with the start address of its entries. Called in
+/// [`global_init`][crate::global_init] after [`check_targets`].
+pub(crate) fn global_init() {
+    // Make sure there are at least one entry
+    crate::static_switch!(DUMMY_STATIC_SWITCH, { 0 => () });
+
+    let entry_start_addr = &raw mut os::SWITCH_ENTRY_START;
+    let entry_stop_addr = &raw mut os::SWITCH_ENTRY_STOP;
+    let entries = unsafe {
+        let len = entry_stop_addr.offset_from(entry_start_addr) as usize;
+        core::slice::from_raw_parts_mut(entry_start_addr, len)
+    };
+    for entry in entries.iter_mut() {
+        if entry.is_dummy() {
+            continue;
+        }
+        entry.make_relative_address_absolute();
+    }
+    // The entries are sorted by address of static switch and code address
+    entries.sort_unstable_by_key(|entry| (entry.key_addr(), entry.code_addr()));
+    let mut last_key_addr = 0;
+    for entry in entries {
+        if entry.is_dummy() {
+            continue;
+        }
+        let key_addr = entry.key_addr();
+        if key_addr == last_key_addr {
+            continue;
+        }
+        // The M, N and S generic is useless here
+        let switch =
+            unsafe { &mut *(key_addr as *mut GenericStaticSwitch<DummyCodeManipulator, 1, 0>) };
+        switch.entries = entry as *mut _ as usize;
+        last_key_addr = key_addr;
+    }
+}
+
+/// Make the sites in `entries` jump to the arm of `value`, and store `value`.
+///
+/// The instructions are verified before writing, and rolled back on failure.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`] held. See [`GenericStaticSwitch::set`] for other requirements.
+unsafe fn static_switch_update_locked<M: CodeManipulator>(
+    entries: &[JumpEntry],
+    current: &core::sync::atomic::AtomicUsize,
+    value: usize,
+) -> Result<(), StaticKeyError> {
+    let old_value = current.load(core::sync::atomic::Ordering::Relaxed);
+    if old_value == value {
+        return Ok(());
+    }
+    for entry in entries {
+        let site = entry.code_addr();
+        // The instruction is always readable
+        let instruction =
+            unsafe { core::ptr::read_volatile(site as *const [u8; ARCH_JUMP_INS_LENGTH]) };
+        if instruction != arm_instruction(entry, old_value) {
+            return Err(StaticKeyError::UnexpectedInstruction { site });
+        }
+    }
+    current.store(value, core::sync::atomic::Ordering::Relaxed);
+    if let Err((end, err)) = unsafe { write_sites::<M>(entries, value, usize::MAX) } {
+        current.store(old_value, core::sync::atomic::Ordering::Relaxed);
+        if let Err((_, rollback_err)) = unsafe { write_sites::<M>(entries, old_value, end) } {
+            panic!("Failed to roll back static switch after {err}: {rollback_err}");
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Make the sites in `entries` whose address is lower than `end` jump to the arm of `value`.
+///
+/// On failure, returns the end address of the instructions that may have been written, along
+/// with the error.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`] held.
+unsafe fn write_sites<M: CodeManipulator>(
+    entries: &[JumpEntry],
+    value: usize,
+    end: usize,
+) -> Result<(), (usize, StaticKeyError)> {
+    let mut patches = [(core::ptr::null_mut(), [0u8; ARCH_JUMP_INS_LENGTH]); MAX_BATCH_PATCHES];
+    let entries = entries.iter().filter(|entry| entry.code_addr() < end);
+    let mut entries = entries.peekable();
+    while entries.peek().is_some() {
+        let mut patch_count = 0;
+        for (patch, entry) in patches.iter_mut().zip(entries.by_ref()) {
+            *patch = (entry.code_addr() as *mut _, arm_instruction(entry, value));
+            patch_count += 1;
+        }
+        let written = &patches[..patch_count];
+        if let Err(err) = unsafe { M::write_code_batch(written) } {
+            let (first_addr, _) = written[0];
+            let (last_addr, _) = written[patch_count - 1];
+            return Err((
+                last_addr as usize + 1,
+                StaticKeyError::write_code(first_addr as usize, err),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Define a static switch with `N` arms and initial value `S`, which is 0 by default.
+///
+/// This macro will define a static variable without documentations and visibility modifiers.
+/// Use [`new_static_switch`] for customization.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::define_static_switch;
+///
+/// // Three arms, initially 0
+/// define_static_switch!(MY_CODEC_STATIC_SWITCH, 3);
+/// // Four arms, initially 2
+/// define_static_switch!(MY_SIMD_STATIC_SWITCH, 4, 2);
+/// ```
+#[macro_export]
+macro_rules! define_static_switch {
+    ($key: ident, $arm_count: expr) => {
+        $crate::define_static_switch!($key, $arm_count, 0);
+    };
+    ($key: ident, $arm_count: expr, $initial_value: expr) => {
+        static $key: $crate::StaticSwitch<{ $arm_count }, { $initial_value }> =
+            $crate::new_static_switch();
+    };
+}
+
+/// Select an arm by the value of a static switch with a single patchable jump.
+///
+/// The arms are matched by integer literals, which must be exactly `0`, `1`, ..., `N - 1` in order,
+/// where `N` is the count of arms of the static switch. This is checked at compile time. Like
+/// `match`, this macro evaluates to the value of selected arm.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::{define_static_switch, static_switch};
+///
+/// define_static_switch!(MY_MODE_STATIC_SWITCH, 3);
+///
+/// fn encode(input: u32) -> u32 {
+///     static_switch!(MY_MODE_STATIC_SWITCH, {
+///         0 => input,
+///         1 => input.rotate_left(8),
+///         2 => input.swap_bytes(),
+///     })
+/// }
+///
+/// fn main() {
+///     static_keys::global_init();
+///     assert_eq!(encode(0x12345678), 0x12345678);
+///     unsafe { MY_MODE_STATIC_SWITCH.set(2) };
+///     assert_eq!(encode(0x12345678), 0x78563412);
+/// }
+/// ```
+#[macro_export]
+macro_rules! static_switch {
+    ($key: path, { $($value: literal => $arm: expr),+ $(,)? }) => {{
+        const {
+            let values: &[usize] = &[$($value),+];
+            let mut index = 0;
+            while index < values.len() {
+                ::core::assert!(values[index] == index, "Arms of static switch must be 0, 1, ..., N - 1 in order");
+                index += 1;
+            }
+            ::core::assert!(
+                values.len() == $key.arm_count(),
+                "Count of arms does not match the static switch"
+            );
+        }
+        let index: usize = 'my_label: {
+            unsafe {
+                // This is an ugly workaround for https://github.com/rust-lang/rust/issues/128177
+                #[cfg(not(all(target_os = "windows", any(target_arch = "x86", target_arch = "x86_64"))))]
+                ::core::arch::asm!(
+                    $crate::arch_static_switch_asm_template!($($value),+),
+                    $(label {
+                        break 'my_label $value;
+                    },)+
+                    key = sym $key,
+                    initial = const $key.initial_value(),
+                    count = const [$($value),+].len(),
+                    options(noreturn),
+                );
+                #[cfg(all(target_os = "windows", any(target_arch = "x86", target_arch = "x86_64")))]
+                ::core::arch::asm!(
+                    $crate::arch_static_switch_asm_template!($($value),+),
+                    $(label {
+                        break 'my_label $value;
+                    },)+
+                    key = sym $key,
+                    initial = const $key.initial_value(),
+                    count = const [$($value),+].len(),
+                    options(noreturn, att_syntax),
+                );
+            }
+        };
+        match index {
+            $($value => $arm,)+
+            _ => unsafe { ::core::hint::unreachable_unchecked() },
+        }
+    }};
+}
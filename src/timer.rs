@@ -0,0 +1,53 @@
+//! Background timer thread driving deferred and expiring static keys. Only available with `std`
+//! feature.
+
+use std::{
+    sync::OnceLock,
+    thread::Thread,
+    time::{Duration, Instant},
+};
+
+/// Epoch of time passed to [`crate::deferred::tick`]
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Handle of the timer thread
+static TIMER_THREAD: OnceLock<Thread> = OnceLock::new();
+
+/// Time elapsed since [`EPOCH`]
+pub(crate) fn now() -> Duration {
+    EPOCH.get_or_init(Instant::now).elapsed()
+}
+
+/// Wake up the timer thread to check deadlines, and spawn it if not started yet
+pub(crate) fn wake() {
+    TIMER_THREAD
+        .get_or_init(|| {
+            std::thread::Builder::new()
+                .name("static-keys-timer".into())
+                .spawn(run)
+                .expect("Failed to spawn static keys timer thread.")
+                .thread()
+                .clone()
+        })
+        .unpark();
+}
+
+/// Main loop of the timer thread
+fn run() {
+    loop {
+        let mut next_deadline = match unsafe { crate::deferred::tick(now()) } {
+            Ok(deadline) => deadline.map(|deadline| *EPOCH.get_or_init(Instant::now) + deadline),
+            // The failed static key is rescheduled, check again
+            Err(_) => Some(Instant::now()),
+        };
+        if let Some(deadline) = unsafe { crate::expiry::tick(Instant::now()) } {
+            next_deadline = Some(next_deadline.map_or(deadline, |next| next.min(deadline)));
+        }
+        match next_deadline {
+            Some(deadline) => {
+                std::thread::park_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => std::thread::park(),
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Transactional updates of multiple static keys.
+
+use crate::{
+    GenericStaticKey, JumpEntry, StaticKeyError,
+    arch::ARCH_JUMP_INS_LENGTH,
+    code_manipulate::{CodeManipulator, DummyCodeManipulator},
+    patch_lock::PatchGuard,
+};
+
+/// Maximum count of static keys in one [`Transaction`]
+pub const MAX_TRANSACTION_KEYS: usize = 64;
+
+/// Maximum count of instructions written by one [`CodeManipulator::write_code_batch`] call
+pub(crate) const MAX_BATCH_PATCHES: usize = 128;
+
+/// A pending status change of a static key
+#[derive(Clone, Copy)]
+pub(crate) struct KeyChange {
+    /// Address of the static key
+    key_addr: usize,
+    /// Status to be set
+    enabled: bool,
+}
+
+impl KeyChange {
+    /// Set the status of `key` to `enabled`
+    pub(crate) fn new<M: CodeManipulator, const S: bool>(
+        key: &GenericStaticKey<M, S>,
+        enabled: bool,
+    ) -> Self {
+        Self {
+            key_addr: key as *const _ as usize,
+            enabled,
+        }
+    }
+
+    /// The static key to be changed
+    fn key(&self) -> &'static GenericStaticKey<DummyCodeManipulator, true> {
+        // The M and S generic is useless here
+        unsafe { &*(self.key_addr as *const GenericStaticKey<DummyCodeManipulator, true>) }
+    }
+}
+
+/// A set of static key status changes applied together by [`commit`][Transaction::commit].
+///
+/// All instructions to be modified are sorted by address, and the instructions residing in the
+/// same code page are written at once, so flipping many keys costs much less than calling
+/// [`enable`][GenericStaticKey::enable] and [`disable`][GenericStaticKey::disable] for each key.
+///
+/// Use [`batch`] to create a transaction of [`StaticKey`][crate::StaticKey]s.
+///
+/// # Usage
+///
+/// ```rust
+/// use static_keys::{define_static_key_false, define_static_key_true};
+///
+/// define_static_key_false!(MY_FALSE_STATIC_KEY);
+/// define_static_key_true!(MY_TRUE_STATIC_KEY);
+///
+/// fn main() {
+///     static_keys::global_init();
+///     let mut transaction = static_keys::batch();
+///     transaction
+///         .enable(&MY_FALSE_STATIC_KEY)
+///         .disable(&MY_TRUE_STATIC_KEY);
+///     unsafe { transaction.commit() }.unwrap();
+///     assert!(MY_FALSE_STATIC_KEY.is_enabled());
+///     assert!(!MY_TRUE_STATIC_KEY.is_enabled());
+/// }
+/// ```
+pub struct Transaction<M: CodeManipulator = crate::os::ArchCodeManipulator> {
+    /// Pending changes, only the first `len` ones are valid
+    changes: [KeyChange; MAX_TRANSACTION_KEYS],
+    /// Count of pending changes
+    len: usize,
+    /// Phantom data to hold `M`
+    phantom: core::marker::PhantomData<M>,
+}
+
+/// Create an empty [`Transaction`] of [`StaticKey`][crate::StaticKey]s.
+pub const fn batch() -> Transaction {
+    Transaction::new()
+}
+
+impl<M: CodeManipulator> Default for Transaction<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: CodeManipulator> Transaction<M> {
+    /// Create an empty transaction
+    pub const fn new() -> Self {
+        Self {
+            changes: [KeyChange {
+                key_addr: 0,
+                enabled: false,
+            }; MAX_TRANSACTION_KEYS],
+            len: 0,
+            phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Enable `key` when committing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than [`MAX_TRANSACTION_KEYS`] different static keys are added.
+    pub fn enable<const S: bool>(&mut self, key: &'static GenericStaticKey<M, S>) -> &mut Self {
+        self.set(key, true)
+    }
+
+    /// Disable `key` when committing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than [`MAX_TRANSACTION_KEYS`] different static keys are added.
+    pub fn disable<const S: bool>(&mut self, key: &'static GenericStaticKey<M, S>) -> &mut Self {
+        self.set(key, false)
+    }
+
+    /// Set the status of `key` to `enabled` when committing. If `key` has been added to this
+    /// transaction, the previous status is overridden.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than [`MAX_TRANSACTION_KEYS`] different static keys are added.
+    pub fn set<const S: bool>(
+        &mut self,
+        key: &'static GenericStaticKey<M, S>,
+        enabled: bool,
+    ) -> &mut Self {
+        let new_change = KeyChange::new(key, enabled);
+        if let Some(change) = self.changes[..self.len]
+            .iter_mut()
+            .find(|change| change.key_addr == new_change.key_addr)
+        {
+            change.enabled = enabled;
+            return self;
+        }
+        if self.len == MAX_TRANSACTION_KEYS {
+            panic!("Too many static keys in one transaction.");
+        }
+        self.changes[self.len] = new_change;
+        self.len += 1;
+        self
+    }
+
+    /// Count of static keys added to this transaction
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no static key is added to this transaction
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Apply all changes of this transaction.
+    ///
+    /// Static keys whose status is not changed are skipped. If writing any code page fails, the
+    /// status of all static keys and all written instructions are rolled back, and the error is
+    /// returned. Panics if the rollback fails as well.
+    ///
+    /// Transactions are serialized with other modifications of static keys by a process-wide lock,
+    /// so this method can be called in parallel. If [`global_init`][crate::global_init] has not been
+    /// called yet, it is called before modifying instructions.
+    ///
+    /// # Safety
+    ///
+    /// Never call this method when other threads may be executing codes in the same code page. This
+    /// method may manipulate code region memory protection, and it may lead to unexpected behaviors.
+    pub unsafe fn commit(self) -> Result<(), StaticKeyError> {
+        crate::ensure_global_init()?;
+        let _guard = PatchGuard::lock();
+        // Only keep changes which modify the status
+        let mut changes = self.changes;
+        let mut len = 0;
+        for index in 0..self.len {
+            let change = self.changes[index];
+            #[cfg(feature = "std")]
+            crate::expiry::cancel(change.key_addr);
+            if change.key().is_enabled() != change.enabled {
+                changes[len] = change;
+                len += 1;
+            }
+        }
+        unsafe { apply_changes::<M>(&changes[..len]) }
+    }
+}
+
+/// Update the status of static keys and the instructions of associated jump entries.
+///
+/// All `changes` should modify the status. Before writing anything, the instructions of all jump
+/// entries are checked to match the current status, and nothing is modified if any of them does not.
+/// If writing any instruction fails, both status and instructions are rolled back, and the error is
+/// returned. Panics if the rollback fails as well.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`] held. See [`Transaction::commit`] for other requirements.
+pub(crate) unsafe fn apply_changes<M: CodeManipulator>(
+    changes: &[KeyChange],
+) -> Result<(), StaticKeyError> {
+    for change in changes {
+        crate::verify::check_jump_entries(change.key().jump_entries(), !change.enabled)?;
+    }
+    for change in changes {
+        change
+            .key()
+            .enabled
+            .store(change.enabled, core::sync::atomic::Ordering::Relaxed);
+    }
+    let Err((failed_end, err)) = (unsafe { write_changes::<M>(changes, usize::MAX, false) }) else {
+        return Ok(());
+    };
+
+    // Roll back
+    for change in changes {
+        change
+            .key()
+            .enabled
+            .store(!change.enabled, core::sync::atomic::Ordering::Relaxed);
+    }
+    if let Err((_, rollback_err)) = unsafe { write_changes::<M>(changes, failed_end, true) } {
+        panic!("Failed to roll back static keys after {err}: {rollback_err}");
+    }
+    Err(err)
+}
+
+/// Write instructions of all jump entries associated with `changes` whose address is lower than
+/// `end`, in the order of address. If `rollback` is `true`, the instructions are restored to
+/// the status before `changes`.
+///
+/// The jump entries of each static key are already sorted by address in
+/// [`global_init`][crate::global_init], so they are merged here without allocation.
+///
+/// If writing fails, return the end address of jump entries which may have been written, along
+/// with the error.
+///
+/// # Safety
+///
+/// Must be called with [`PatchGuard`] held. See [`Transaction::commit`] for other requirements.
+unsafe fn write_changes<M: CodeManipulator>(
+    changes: &[KeyChange],
+    end: usize,
+    rollback: bool,
+) -> Result<(), (usize, StaticKeyError)> {
+    let mut cursors = [0usize; MAX_TRANSACTION_KEYS];
+    let mut patches = [(core::ptr::null_mut(), [0u8; ARCH_JUMP_INS_LENGTH]); MAX_BATCH_PATCHES];
+    let mut patch_count = 0;
+    loop {
+        // Find the jump entry with lowest address among all static keys
+        let mut next: Option<(usize, &JumpEntry)> = None;
+        for (index, change) in changes.iter().enumerate() {
+            let Some(jump_entry) = change.key().jump_entries().get(cursors[index]) else {
+                continue;
+            };
+            if next.is_none_or(|(_, next_entry)| jump_entry.code_addr() < next_entry.code_addr()) {
+                next = Some((index, jump_entry));
+            }
+        }
+        let next = next.filter(|(_, jump_entry)| jump_entry.code_addr() < end);
+
+        if let Some((index, jump_entry)) = next {
+            cursors[index] += 1;
+            let enabled = changes[index].enabled ^ rollback;
+            patches[patch_count] = (
+                jump_entry.code_addr() as *mut _,
+                crate::jump_entry_instruction(jump_entry, enabled),
+            );
+            patch_count += 1;
+            if patch_count < MAX_BATCH_PATCHES {
+                continue;
+            }
+        }
+        if patch_count > 0 {
+            let written = &patches[..patch_count];
+            if let Err(err) = unsafe { M::write_code_batch(written) } {
+                let (first_addr, _) = written[0];
+                let (last_addr, _) = written[patch_count - 1];
+                return Err((
+                    last_addr as usize + 1,
+                    StaticKeyError::write_code(first_addr as usize, err),
+                ));
+            }
+            patch_count = 0;
+        }
+        if next.is_none() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Structural validation of jump entries.
+
+use crate::{JumpEntry, UninitializedGuard, arch::ARCH_JUMP_INS_LENGTH};
+
+/// A structural problem of jump entries in the `__static_keys` section, reported by
+/// [`validate_jump_entries`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationIssue {
+    /// The instruction of a jump entry does not reside in executable memory
+    CodeNotExecutable {
+        /// Address of the instruction
+        site: usize,
+    },
+    /// The JMP destination of a jump entry cannot be encoded in the jump instruction of current
+    /// architecture
+    TargetOutOfRange {
+        /// Address of the instruction
+        site: usize,
+        /// Address of the JMP destination
+        target: usize,
+    },
+    /// The instructions of two jump entries overlap
+    OverlappingSites {
+        /// Address of the instruction with lower address
+        site: usize,
+        /// Address of the other instruction
+        other_site: usize,
+    },
+    /// The static key address of a jump entry is not aligned, so it conflicts with the flag
+    /// recorded in its LSB. Such jump entry is ignored.
+    MisalignedKey {
+        /// Address of the instruction
+        site: usize,
+        /// Address of the static key
+        key_addr: usize,
+    },
+    /// A named static key, or the static key used inside [`global_init`][crate::global_init], has
+    /// no jump entries. This means the static key is never used, or the `__static_keys` section is
+    /// not linked correctly.
+    KeyWithoutJumpEntries {
+        /// Address of the static key
+        key_addr: usize,
+    },
+}
+
+impl core::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::CodeNotExecutable { site } => {
+                write!(f, "instruction at {site:#x} is not executable")
+            }
+            Self::TargetOutOfRange { site, target } => write!(
+                f,
+                "instruction at {site:#x} cannot jump to {target:#x}, which is out of range"
+            ),
+            Self::OverlappingSites { site, other_site } => write!(
+                f,
+                "instruction at {site:#x} overlaps with instruction at {other_site:#x}"
+            ),
+            Self::MisalignedKey { site, key_addr } => write!(
+                f,
+                "instruction at {site:#x} is associated with misaligned static key at {key_addr:#x}"
+            ),
+            Self::KeyWithoutJumpEntries { key_addr } => {
+                write!(f, "static key at {key_addr:#x} has no jump entries")
+            }
+        }
+    }
+}
+
+/// Issues of a single jump entry
+fn jump_entry_issues(jump_entry: &JumpEntry) -> [Option<ValidationIssue>; 3] {
+    let site = jump_entry.code_addr();
+    [
+        (!crate::os::is_executable(site)).then_some(ValidationIssue::CodeNotExecutable { site }),
+        (!crate::arch::arch_jump_target_in_range(jump_entry)).then_some(
+            ValidationIssue::TargetOutOfRange {
+                site,
+                target: jump_entry.target_addr(),
+            },
+        ),
+        (!jump_entry.is_key_aligned()).then_some(ValidationIssue::MisalignedKey {
+            site,
+            key_addr: jump_entry.key_addr(),
+        }),
+    ]
+}
+
+/// Call `visit` with each pair of overlapping instructions, by sorting the instructions by address
+/// and comparing neighbours.
+#[cfg(feature = "std")]
+fn visit_overlapping_sites(
+    jump_entries: impl Iterator<Item = JumpEntry>,
+    visit: &mut impl FnMut(ValidationIssue),
+) {
+    let mut sites = jump_entries
+        .map(|jump_entry| jump_entry.code_addr())
+        .collect::<std::vec::Vec<_>>();
+    sites.sort_unstable();
+    for pair in sites.windows(2) {
+        if pair[1] - pair[0] < ARCH_JUMP_INS_LENGTH {
+            visit(ValidationIssue::OverlappingSites {
+                site: pair[0],
+                other_site: pair[1],
+            });
+        }
+    }
+}
+
+/// Call `visit` with each pair of overlapping instructions. The instructions cannot be sorted
+/// without allocation, so each pair of them is compared.
+#[cfg(not(feature = "std"))]
+fn visit_overlapping_sites(
+    jump_entries: impl Iterator<Item = JumpEntry> + Clone,
+    visit: &mut impl FnMut(ValidationIssue),
+) {
+    for (index, jump_entry) in jump_entries.clone().enumerate() {
+        for other in jump_entries.clone().skip(index + 1) {
+            let (site, other_site) = if jump_entry.code_addr() <= other.code_addr() {
+                (jump_entry.code_addr(), other.code_addr())
+            } else {
+                (other.code_addr(), jump_entry.code_addr())
+            };
+            if other_site - site < ARCH_JUMP_INS_LENGTH {
+                visit(ValidationIssue::OverlappingSites { site, other_site });
+            }
+        }
+    }
+}
+
+/// Check the structure of all jump entries in the `__static_keys` section, and call `visit` with
+/// each issue found.
+///
+/// Jump entries are trusted when modifying static keys, so an invalid jump entry, which may be
+/// caused by a bad relocation or a misbehaving linker, would make this crate overwrite unrelated
+/// memory. This function checks that:
+///
+/// * The instruction of each jump entry resides in executable memory.
+/// * The JMP destination of each jump entry can be encoded in the jump instruction.
+/// * The instructions of different jump entries never overlap.
+/// * The static key of each jump entry is aligned, so its LSB can record the likely branch.
+/// * Each named static key in the [`registry`][crate::registry], and the static key used inside
+///   [`global_init`][crate::global_init], has jump entries.
+///
+/// This function does not call [`global_init`][crate::global_init], so it can be called before it to
+/// find issues which would make it fail, such as out-of-range JMP destinations. In such case,
+/// [`global_init`][crate::global_init] waits until this function returns, so `visit` must not call
+/// [`global_init`][crate::global_init] or modify static keys, which would never return.
+///
+/// With the `std` feature, the instructions are sorted by address to find overlapping ones.
+/// Otherwise, each pair of instructions is compared, which is slow for a large number of jump
+/// entries.
+///
+/// # Usage
+///
+/// ```rust
+/// static_keys::validate_jump_entries(|issue| eprintln!("Invalid jump entry: {issue}"));
+/// static_keys::global_init();
+/// ```
+pub fn validate_jump_entries(mut visit: impl FnMut(ValidationIssue)) {
+    // The jump entries keep relative addresses as long as the guard is held
+    let guard = UninitializedGuard::acquire();
+    let relative = guard.is_some();
+    // Absolute addresses of all jump entries except dummy ones
+    let valid_jump_entries = || {
+        crate::jump_entries()
+            .iter()
+            .filter(|jump_entry| !jump_entry.is_dummy())
+            .map(move |jump_entry| {
+                if relative {
+                    jump_entry.to_absolute()
+                } else {
+                    jump_entry.clone()
+                }
+            })
+    };
+    for jump_entry in valid_jump_entries() {
+        jump_entry_issues(&jump_entry)
+            .into_iter()
+            .flatten()
+            .for_each(&mut visit);
+    }
+    visit_overlapping_sites(valid_jump_entries(), &mut visit);
+    let key_addrs = crate::registry()
+        .iter()
+        .map(|key| key.addr())
+        .chain(core::iter::once(
+            &raw const crate::DUMMY_STATIC_KEY as usize,
+        ));
+    for key_addr in key_addrs {
+        if !valid_jump_entries().any(|jump_entry| jump_entry.key_addr() == key_addr) {
+            visit(ValidationIssue::KeyWithoutJumpEntries { key_addr });
+        }
+    }
+    drop(guard);
+}
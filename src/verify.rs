@@ -0,0 +1,120 @@
+//! Verification of instructions at jump entries.
+
+use crate::{
+    GenericStaticKey, JumpEntry, StaticKeyError, arch::ARCH_JUMP_INS_LENGTH,
+    code_manipulate::DummyCodeManipulator, patch_lock::PatchGuard,
+};
+
+/// A jump entry whose instruction does not match the status of its static key, reported by
+/// [`verify_all`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteMismatch {
+    /// Address of the instruction
+    site: usize,
+    /// Address of the associated static key
+    key_addr: usize,
+    /// Status of the associated static key
+    enabled: bool,
+    /// Instruction expected by the status
+    expected: [u8; ARCH_JUMP_INS_LENGTH],
+    /// Instruction actually found
+    found: [u8; ARCH_JUMP_INS_LENGTH],
+}
+
+impl SiteMismatch {
+    /// Address of the instruction
+    pub fn site(&self) -> usize {
+        self.site
+    }
+
+    /// Address of the associated static key
+    pub fn key_addr(&self) -> usize {
+        self.key_addr
+    }
+
+    /// Status of the associated static key
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Instruction expected by the status of the associated static key
+    pub fn expected(&self) -> &[u8] {
+        &self.expected
+    }
+
+    /// Instruction actually found at the site
+    pub fn found(&self) -> &[u8] {
+        &self.found
+    }
+}
+
+/// Current instruction at given jump entry
+pub(crate) fn read_instruction(jump_entry: &JumpEntry) -> [u8; ARCH_JUMP_INS_LENGTH] {
+    // The instruction is always readable
+    unsafe { core::ptr::read_volatile(jump_entry.code_addr() as *const [u8; ARCH_JUMP_INS_LENGTH]) }
+}
+
+/// Make sure the instructions at `jump_entries` are the ones when their static key has status
+/// `enabled`, before replacing them.
+///
+/// Otherwise, the jump entries are corrupted, or the code has been modified by others, and
+/// writing new instructions would overwrite unrelated code. The first mismatched jump entry is
+/// returned as an error.
+pub(crate) fn check_jump_entries(
+    jump_entries: &[JumpEntry],
+    enabled: bool,
+) -> Result<(), StaticKeyError> {
+    match jump_entries.iter().find(|jump_entry| {
+        read_instruction(jump_entry) != crate::jump_entry_instruction(jump_entry, enabled)
+    }) {
+        Some(jump_entry) => Err(StaticKeyError::UnexpectedInstruction {
+            site: jump_entry.code_addr(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Check the instruction at every jump entry against the status of its static key, and return
+/// all mismatched ones.
+///
+/// All instructions should always match the status of their static keys, so any mismatch means
+/// the code has been tampered, or the jump entries are corrupted. This can be used for health
+/// checks.
+///
+/// If [`global_init`][crate::global_init] has not been called yet, it is called first, and nothing
+/// is yielded if it fails. Each jump entry is checked with the process-wide patch lock held, so
+/// static keys can be modified in parallel.
+///
+/// # Usage
+///
+/// ```rust
+/// static_keys::global_init();
+/// assert_eq!(static_keys::verify_all().count(), 0);
+/// ```
+pub fn verify_all() -> impl Iterator<Item = SiteMismatch> {
+    let jump_entries = match crate::ensure_global_init() {
+        Ok(()) => crate::jump_entries(),
+        // The jump entries are not initialized
+        Err(_) => &[],
+    };
+    jump_entries
+        .iter()
+        .filter(|jump_entry| !jump_entry.is_dummy() && jump_entry.is_key_aligned())
+        .filter_map(|jump_entry| {
+            let _guard = PatchGuard::lock();
+            // The M and S generic is useless here
+            let key = unsafe {
+                &*(jump_entry.key_addr() as *const GenericStaticKey<DummyCodeManipulator, true>)
+            };
+            let enabled = key.is_enabled();
+            let expected = crate::jump_entry_instruction(jump_entry, enabled);
+            let found = read_instruction(jump_entry);
+            (found != expected).then_some(SiteMismatch {
+                site: jump_entry.code_addr(),
+                key_addr: jump_entry.key_addr(),
+                enabled,
+                expected,
+                found,
+            })
+        })
+}
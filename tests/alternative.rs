@@ -0,0 +1,68 @@
+//! Tests for alternative instructions.
+
+// The templates are in AT&T syntax, which works on all OSs
+#![cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+
+use static_keys::alternative;
+
+fn always() -> bool {
+    true
+}
+
+fn never() -> bool {
+    false
+}
+
+#[inline(never)]
+fn replaced() -> u32 {
+    let output: u32;
+    unsafe {
+        alternative!(
+            "movl $1, {0:e}",
+            "movl $2, {0:e}",
+            always,
+            out(reg) output,
+            options(nomem, nostack, att_syntax),
+        );
+    }
+    output
+}
+
+#[inline(never)]
+fn not_replaced() -> u32 {
+    let output: u32;
+    unsafe {
+        alternative!(
+            "movl $1, {0:e}",
+            "movl $2, {0:e}",
+            never,
+            out(reg) output,
+            options(nomem, nostack, att_syntax)
+        );
+    }
+    output
+}
+
+/// The replacement is much shorter, and padded with NOPs of different lengths
+#[inline(never)]
+fn replaced_shorter(input: u32) -> u32 {
+    let mut output = input;
+    unsafe {
+        alternative!(
+            "addl $0x10000, {0:e}\naddl $0x10000, {0:e}\naddl $0x10000, {0:e}",
+            "addl $1, {0:e}",
+            always,
+            inout(reg) output,
+            options(nomem, nostack, att_syntax),
+        );
+    }
+    output
+}
+
+#[test]
+fn test_alternative() {
+    static_keys::global_init();
+    assert_eq!(replaced(), 2);
+    assert_eq!(not_replaced(), 1);
+    assert_eq!(replaced_shorter(10), 11);
+}
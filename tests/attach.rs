@@ -0,0 +1,191 @@
+//! Tests for modifying static keys of another process with ptrace.
+//!
+//! The test binary is executed again as the target process, which runs [`child`] and answers
+//! commands from stdin.
+
+#![cfg(all(feature = "std", target_os = "linux"))]
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use static_keys::{
+    attach::{AttachError, RemoteProcess},
+    define_static_key_false, define_static_key_true, static_branch_likely, static_branch_unlikely,
+};
+
+define_static_key_false!(ATTACH_TRACE_STATIC_KEY, "attach.net.trace");
+define_static_key_true!(ATTACH_LOG_STATIC_KEY, "attach.db.log");
+
+/// Environment variable telling the test binary to act as the target process
+const CHILD_ENV_VAR: &str = "STATIC_KEYS_ATTACH_CHILD";
+
+#[inline(never)]
+fn trace_unlikely() -> bool {
+    static_branch_unlikely!(ATTACH_TRACE_STATIC_KEY)
+}
+
+#[inline(never)]
+fn log_likely() -> bool {
+    static_branch_likely!(ATTACH_LOG_STATIC_KEY)
+}
+
+/// Prefix of answers of the target process, which follow the output of the test harness on the same
+/// line
+const ANSWER_PREFIX: &str = "=> ";
+
+/// Body of the target process. `init` calls `global_init`, and `check` prints the branches taken.
+#[test]
+fn child() {
+    if std::env::var_os(CHILD_ENV_VAR).is_none() {
+        return;
+    }
+    let mut stdout = std::io::stdout();
+    for line in std::io::stdin().lines() {
+        match line.unwrap().as_str() {
+            "init" => {
+                static_keys::global_init();
+                writeln!(stdout, "{ANSWER_PREFIX}ok").unwrap();
+            }
+            "check" => writeln!(
+                stdout,
+                "{ANSWER_PREFIX}{} {}",
+                trace_unlikely(),
+                log_likely()
+            )
+            .unwrap(),
+            _ => break,
+        }
+    }
+}
+
+/// The target process, killed when dropped
+struct Target {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Target {
+    fn spawn() -> Self {
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "child", "--nocapture", "--test-threads=1"])
+            .env(CHILD_ENV_VAR, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Self {
+            child,
+            stdin,
+            stdout,
+        }
+    }
+
+    fn pid(&self) -> i32 {
+        self.child.id() as i32
+    }
+
+    /// Send a command and return its answer
+    fn send(&mut self, command: &str) -> String {
+        writeln!(self.stdin, "{command}").unwrap();
+        // Skip the output of the test harness
+        loop {
+            let mut line = String::new();
+            assert_ne!(self.stdout.read_line(&mut line).unwrap(), 0);
+            if let Some((_, answer)) = line.trim_end().rsplit_once(ANSWER_PREFIX) {
+                return answer.to_string();
+            }
+        }
+    }
+}
+
+impl Drop for Target {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn test_attach_initialized() {
+    let mut target = Target::spawn();
+    assert_eq!(target.send("init"), "ok");
+    assert_eq!(target.send("check"), "false true");
+
+    let process = RemoteProcess::open(target.pid()).unwrap();
+    let keys = process.keys().unwrap();
+    let trace = keys
+        .iter()
+        .find(|key| key.name() == Some("attach.net.trace"))
+        .unwrap();
+    assert!(!trace.is_enabled());
+    assert!(!trace.initial_enabled());
+    assert_eq!(trace.site_count(), 1);
+
+    process.set("attach.net.trace", true).unwrap();
+    process.set("attach.db.log", false).unwrap();
+    assert_eq!(target.send("check"), "true false");
+
+    process.set("attach.net.trace", false).unwrap();
+    assert_eq!(target.send("check"), "false false");
+
+    assert!(matches!(
+        process.set("attach.none", true),
+        Err(AttachError::UnknownKey(_))
+    ));
+}
+
+#[test]
+fn test_attach_uninitialized() {
+    let mut target = Target::spawn();
+    assert_eq!(target.send("check"), "false true");
+
+    let process = RemoteProcess::open(target.pid()).unwrap();
+    process.set("attach.net.trace", true).unwrap();
+    assert_eq!(target.send("check"), "true true");
+
+    // Static keys modified remotely stay consistent after global_init
+    assert_eq!(target.send("init"), "ok");
+    assert_eq!(target.send("check"), "true true");
+    process.set("attach.net.trace", false).unwrap();
+    assert_eq!(target.send("check"), "false true");
+}
+
+#[test]
+fn test_attach_execute() {
+    let mut target = Target::spawn();
+    assert_eq!(target.send("init"), "ok");
+
+    let process = RemoteProcess::open(target.pid()).unwrap();
+    assert_eq!(
+        process.execute("get attach.net.trace"),
+        r#"{"ok":true,"key":{"name":"attach.net.trace","enabled":false,"initial":false}}"#
+    );
+    assert_eq!(
+        process.execute("enable attach.*"),
+        r#"{"ok":true,"matched":2}"#
+    );
+    assert_eq!(target.send("check"), "true true");
+    assert_eq!(process.execute("reset"), r#"{"ok":true,"matched":2}"#);
+    assert_eq!(target.send("check"), "false true");
+    assert_eq!(
+        process.execute("get attach.none"),
+        r#"{"ok":false,"error":"unknown static key attach.none"}"#
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_static-keys-ctl"))
+        .args([
+            "attach",
+            &target.pid().to_string(),
+            "disable",
+            "attach.db.log",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(target.send("check"), "false false");
+}
@@ -0,0 +1,162 @@
+//! Tests for pre-baking the default status of static keys into ELF files.
+//!
+//! The test binary is baked into a temporary file, which is executed to run [`child`].
+
+#![cfg(all(feature = "std", target_os = "linux"))]
+
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use static_keys::{
+    StaticFalseKey, define_static_key_false, define_static_key_true,
+    dump::{DumpError, Image},
+    new_static_false_key, static_branch_likely, static_branch_unlikely,
+};
+
+define_static_key_false!(BAKE_TRACE_STATIC_KEY, "bake.net.trace");
+define_static_key_true!(BAKE_LOG_STATIC_KEY, "bake.db.log");
+static BAKE_BSS_STATIC_KEY: StaticFalseKey = new_static_false_key();
+
+/// Environment variable telling the test binary to print the status of static keys
+const CHILD_ENV_VAR: &str = "STATIC_KEYS_BAKE_CHILD";
+
+#[inline(never)]
+fn trace_unlikely() -> bool {
+    static_branch_unlikely!(BAKE_TRACE_STATIC_KEY)
+}
+
+#[inline(never)]
+fn log_likely() -> bool {
+    static_branch_likely!(BAKE_LOG_STATIC_KEY)
+}
+
+#[inline(never)]
+fn bss_unlikely() -> bool {
+    static_branch_unlikely!(BAKE_BSS_STATIC_KEY)
+}
+
+/// Body of the baked binary. Print the branches taken and the status before and after
+/// `global_init`.
+#[test]
+fn child() {
+    if std::env::var_os(CHILD_ENV_VAR).is_none() {
+        return;
+    }
+    let check = || {
+        [
+            trace_unlikely(),
+            BAKE_TRACE_STATIC_KEY.is_enabled(),
+            log_likely(),
+            BAKE_LOG_STATIC_KEY.is_enabled(),
+        ]
+    };
+    let before = check();
+    static_keys::global_init();
+    let after = check();
+    println!("=> {before:?} {after:?}");
+}
+
+/// Path of a temporary file
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("static-keys-{name}-{}", std::process::id()))
+}
+
+/// Run `command`, retrying if the executable is still open in a forked process
+fn run(command: &mut Command) -> Output {
+    loop {
+        match command.output() {
+            Err(err) if err.kind() == std::io::ErrorKind::ExecutableFileBusy => {
+                std::thread::yield_now();
+            }
+            output => return output.unwrap(),
+        }
+    }
+}
+
+/// Run the baked binary and return the printed status
+fn run_child(path: &Path) -> String {
+    let output = run(Command::new(path)
+        .args(["--exact", "child", "--nocapture"])
+        .env(CHILD_ENV_VAR, "1"));
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    stdout
+        .split_once("=> ")
+        .unwrap()
+        .1
+        .lines()
+        .next()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn test_bake() {
+    assert!(!trace_unlikely());
+    assert!(!bss_unlikely());
+    let mut image = Image::open(std::env::current_exe().unwrap()).unwrap();
+    assert_eq!(image.set_matching("bake.net.*", true).unwrap(), 1);
+    assert_eq!(image.set_matching("bake.db.log", false).unwrap(), 1);
+    assert_eq!(image.set_matching("bake.none", true).unwrap(), 0);
+    assert!(matches!(
+        image.set_matching("bake::BAKE_BSS_STATIC_KEY", true),
+        Err(DumpError::ZeroInitialized { .. })
+    ));
+
+    let keys = image.keys();
+    let trace = keys
+        .iter()
+        .find(|key| key.name() == Some("bake.net.trace"))
+        .unwrap();
+    assert!(trace.initial_enabled());
+    assert_eq!(trace.sites().len(), 1);
+
+    let path = temp_path("bake");
+    std::fs::write(&path, image.data()).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let status = run_child(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        status,
+        "[true, true, false, false] [true, true, false, false]"
+    );
+}
+
+#[test]
+fn test_bake_binary() {
+    let exe = std::env::current_exe().unwrap();
+    let path = temp_path("bake-binary");
+    let output = run(Command::new(env!("CARGO_BIN_EXE_static-keys-bake"))
+        .arg(&exe)
+        .arg(&path)
+        .args(["bake.db.log=off", "bake.net.trace=1"]));
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "bake.db.log: 1 static key set to off\nbake.net.trace: 1 static key set to on\n"
+    );
+
+    let output = run(Command::new(env!("CARGO_BIN_EXE_static-keys-dump")).arg(&path));
+    std::fs::remove_file(&path).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("bake.db.log (bake::BAKE_LOG_STATIC_KEY): initially off, 1 site\n"));
+    assert!(
+        stdout.contains("bake.net.trace (bake::BAKE_TRACE_STATIC_KEY): initially on, 1 site\n")
+    );
+
+    let output = run(Command::new(env!("CARGO_BIN_EXE_static-keys-bake"))
+        .arg(&exe)
+        .arg(&path)
+        .arg("bake.none=on"));
+    assert!(!output.status.success());
+    assert!(!path.exists());
+
+    let output = run(Command::new(env!("CARGO_BIN_EXE_static-keys-bake"))
+        .arg(&exe)
+        .arg(&path)
+        .arg("bake.net.trace=maybe"));
+    assert_eq!(output.status.code(), Some(2));
+}
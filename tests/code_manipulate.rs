@@ -0,0 +1,84 @@
+//! Tests for failures reported by code manipulators.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use static_keys::{
+    GenericStaticKey, StaticKeyError,
+    code_manipulate::{ArchCodeManipulator, CodeManipulateError, CodeManipulator},
+    new_static_key, static_branch_likely, static_branch_unlikely,
+};
+
+/// Remaining count of successful writes before [`FailingCodeManipulator`] fails once
+static WRITES_BEFORE_FAILURE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Code manipulator which fails after given count of writes
+struct FailingCodeManipulator;
+
+impl CodeManipulator for FailingCodeManipulator {
+    unsafe fn write_code<const L: usize>(
+        addr: *mut core::ffi::c_void,
+        data: &[u8; L],
+    ) -> Result<(), CodeManipulateError> {
+        if WRITES_BEFORE_FAILURE.fetch_sub(1, Ordering::Relaxed) == 0 {
+            WRITES_BEFORE_FAILURE.store(usize::MAX, Ordering::Relaxed);
+            return Err(CodeManipulateError {
+                syscall: "failing_write",
+                errno: 42,
+            });
+        }
+        unsafe { ArchCodeManipulator::write_code(addr, data) }
+    }
+}
+
+#[used]
+static FAILING_STATIC_KEY: GenericStaticKey<FailingCodeManipulator, false> = new_static_key();
+
+#[inline(never)]
+fn failing_unlikely() -> bool {
+    static_branch_unlikely!(FAILING_STATIC_KEY)
+}
+
+#[inline(never)]
+fn failing_likely() -> bool {
+    static_branch_likely!(FAILING_STATIC_KEY)
+}
+
+#[test]
+fn test_write_code_failure() {
+    static_keys::global_init();
+
+    // The second write fails after the first one succeeds
+    WRITES_BEFORE_FAILURE.store(1, Ordering::Relaxed);
+    let err = unsafe { FAILING_STATIC_KEY.try_enable() }.unwrap_err();
+    let StaticKeyError::WriteCode {
+        site,
+        syscall,
+        errno,
+    } = err
+    else {
+        panic!("Unexpected error: {err}");
+    };
+    assert_ne!(site, 0);
+    assert_eq!(syscall, "failing_write");
+    assert_eq!(errno, 42);
+    // Both the status and instructions are rolled back
+    assert!(!FAILING_STATIC_KEY.is_enabled());
+    assert!(!failing_unlikely());
+    assert!(!failing_likely());
+
+    unsafe { FAILING_STATIC_KEY.try_enable() }.unwrap();
+    assert!(FAILING_STATIC_KEY.is_enabled());
+    assert!(failing_unlikely());
+    assert!(failing_likely());
+
+    WRITES_BEFORE_FAILURE.store(0, Ordering::Relaxed);
+    assert!(unsafe { FAILING_STATIC_KEY.try_disable() }.is_err());
+    assert!(FAILING_STATIC_KEY.is_enabled());
+    assert!(failing_unlikely());
+    assert!(failing_likely());
+
+    unsafe { FAILING_STATIC_KEY.try_disable() }.unwrap();
+    assert!(!FAILING_STATIC_KEY.is_enabled());
+    assert!(!failing_unlikely());
+    assert!(!failing_likely());
+}
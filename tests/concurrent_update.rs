@@ -0,0 +1,131 @@
+//! Tests for modifying static keys from multiple threads at the same time.
+
+use static_keys::{define_static_key_false, static_branch_unlikely};
+
+define_static_key_false!(CONCURRENT_STATIC_KEY_0);
+define_static_key_false!(CONCURRENT_STATIC_KEY_1);
+define_static_key_false!(CONCURRENT_STATIC_KEY_2);
+define_static_key_false!(CONCURRENT_STATIC_KEY_3);
+
+const TOGGLE_COUNT: usize = 200;
+
+// These functions are adjacent, so their sites are likely to share the same page.
+#[inline(never)]
+fn concurrent_0() -> usize {
+    if static_branch_unlikely!(CONCURRENT_STATIC_KEY_0) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+fn concurrent_1() -> usize {
+    if static_branch_unlikely!(CONCURRENT_STATIC_KEY_1) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+fn concurrent_2() -> usize {
+    if static_branch_unlikely!(CONCURRENT_STATIC_KEY_2) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+fn concurrent_3() -> usize {
+    if static_branch_unlikely!(CONCURRENT_STATIC_KEY_3) {
+        1
+    } else {
+        2
+    }
+}
+
+#[test]
+fn test_concurrent_toggle() {
+    static_keys::global_init();
+
+    let keys = [
+        &CONCURRENT_STATIC_KEY_0,
+        &CONCURRENT_STATIC_KEY_1,
+        &CONCURRENT_STATIC_KEY_2,
+        &CONCURRENT_STATIC_KEY_3,
+    ];
+    std::thread::scope(|scope| {
+        for (index, key) in keys.into_iter().enumerate() {
+            scope.spawn(move || {
+                for _ in 0..TOGGLE_COUNT {
+                    // Branches of these keys are not executed while toggling
+                    unsafe {
+                        key.enable();
+                        key.disable();
+                    }
+                }
+                // Keep odd keys enabled
+                if index % 2 == 1 {
+                    unsafe {
+                        key.enable();
+                    }
+                }
+            });
+        }
+    });
+
+    // No update is lost
+    assert_eq!(concurrent_0(), 2);
+    assert_eq!(concurrent_1(), 1);
+    assert_eq!(concurrent_2(), 2);
+    assert_eq!(concurrent_3(), 1);
+
+    #[cfg(unix)]
+    test_fork_while_toggling();
+}
+
+/// Fork while another thread is toggling. The child process must be able to toggle keys.
+#[cfg(unix)]
+fn test_fork_while_toggling() {
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    let statuses = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                unsafe {
+                    CONCURRENT_STATIC_KEY_0.enable();
+                    CONCURRENT_STATIC_KEY_0.disable();
+                }
+            }
+        });
+        let statuses = (0..20)
+            .map(|_| {
+                let pid = unsafe { libc::fork() };
+                assert!(pid >= 0);
+                if pid == 0 {
+                    // Child process: a deadlock is turned into a failure by SIGALRM
+                    unsafe {
+                        libc::alarm(10);
+                        CONCURRENT_STATIC_KEY_2.enable();
+                    }
+                    let code = if concurrent_2() == 1 { 0 } else { 1 };
+                    unsafe {
+                        libc::_exit(code);
+                    }
+                }
+                let mut status = 0;
+                unsafe {
+                    libc::waitpid(pid, &mut status, 0);
+                }
+                status
+            })
+            .collect::<Vec<_>>();
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        statuses
+    });
+    for status in statuses {
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
@@ -0,0 +1,151 @@
+//! Tests for configuration of named static keys.
+
+#![cfg(feature = "std")]
+
+use static_keys::{
+    config::{self, ConfigError},
+    define_static_key_false, define_static_key_true, static_branch_unlikely,
+};
+
+define_static_key_false!(CONFIG_STR_TRACE_STATIC_KEY, "config.str.trace");
+define_static_key_true!(CONFIG_STR_LOG_STATIC_KEY, "config.str.log");
+define_static_key_false!(CONFIG_FILE_TRACE_STATIC_KEY, "config.file.net.trace");
+define_static_key_false!(CONFIG_FILE_LOG_STATIC_KEY, "config.file.db.log");
+define_static_key_false!(CONFIG_ARGS_A_STATIC_KEY, "config.args.a");
+define_static_key_false!(CONFIG_ARGS_B_STATIC_KEY, "config.args.b");
+define_static_key_false!(CONFIG_ENV_STATIC_KEY, "config.env.trace");
+define_static_key_false!(CONFIG_SNAPSHOT_A_STATIC_KEY, "config.snapshot.a");
+define_static_key_true!(CONFIG_SNAPSHOT_AB_STATIC_KEY, "config.snapshot.a.b");
+
+#[inline(never)]
+fn str_trace_unlikely() -> bool {
+    static_branch_unlikely!(CONFIG_STR_TRACE_STATIC_KEY)
+}
+
+#[test]
+fn test_apply_str() {
+    static_keys::global_init();
+    let report =
+        unsafe { config::apply_str("config.str.trace=on, config.str.log=off,config.str.none=on") }
+            .unwrap();
+    assert_eq!(report.applied(), 2);
+    assert_eq!(report.unknown(), ["config.str.none"]);
+    assert!(str_trace_unlikely());
+    assert!(!CONFIG_STR_LOG_STATIC_KEY.is_enabled());
+
+    // Malformed configuration modifies nothing
+    assert!(matches!(
+        unsafe { config::apply_str("config.str.trace=off,config.str.log") },
+        Err(ConfigError::Syntax { .. })
+    ));
+    assert!(matches!(
+        unsafe { config::apply_str("config.str.trace=off,config.str.log=maybe") },
+        Err(ConfigError::InvalidValue { .. })
+    ));
+    assert!(str_trace_unlikely());
+
+    let report = unsafe { config::apply_str("config.str.*=false") }.unwrap();
+    assert_eq!(report.applied(), 2);
+    assert!(!str_trace_unlikely());
+}
+
+#[test]
+fn test_apply_file() {
+    static_keys::global_init();
+    let path = std::env::temp_dir().join(format!("static-keys-config-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "# Comment\n\
+         [config.file]\n\
+         \"net.trace\" = true # Inline comment\n\
+         db.log = \"on\"\n\
+         unknown = 0\n",
+    )
+    .unwrap();
+    let report = unsafe { config::apply_file(&path) }.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(report.applied(), 2);
+    assert_eq!(report.unknown(), ["config.file.unknown"]);
+    assert!(CONFIG_FILE_TRACE_STATIC_KEY.is_enabled());
+    assert!(CONFIG_FILE_LOG_STATIC_KEY.is_enabled());
+
+    assert!(matches!(
+        unsafe { config::apply_file(&path) },
+        Err(ConfigError::Io { .. })
+    ));
+}
+
+#[test]
+fn test_apply_args() {
+    static_keys::global_init();
+    let args = [
+        "program",
+        "--static-key",
+        "config.args.a=on",
+        "--verbose",
+        "--static-key=config.args.b=1",
+    ];
+    let report = unsafe { config::apply_args(args) }.unwrap();
+    assert_eq!(report.applied(), 2);
+    assert!(report.unknown().is_empty());
+    assert!(CONFIG_ARGS_A_STATIC_KEY.is_enabled());
+    assert!(CONFIG_ARGS_B_STATIC_KEY.is_enabled());
+
+    assert!(matches!(
+        unsafe { config::apply_args(["program", "--static-key"]) },
+        Err(ConfigError::Syntax { .. })
+    ));
+}
+
+#[test]
+fn test_env() {
+    static_keys::global_init();
+    unsafe { std::env::set_var(config::ENV_VAR, "config.env.trace=on") };
+    let report = unsafe { config::apply_env() }.unwrap();
+    assert_eq!(report.applied(), 1);
+    assert!(CONFIG_ENV_STATIC_KEY.is_enabled());
+
+    let snapshot = config::snapshot();
+    assert!(
+        snapshot
+            .split(',')
+            .any(|item| item == "config.env.trace=on")
+    );
+    unsafe { CONFIG_ENV_STATIC_KEY.disable() };
+    unsafe { config::export_env() };
+    let exported = std::env::var(config::ENV_VAR).unwrap();
+    assert!(
+        exported
+            .split(',')
+            .any(|item| item == "config.env.trace=off")
+    );
+
+    unsafe { std::env::remove_var(config::ENV_VAR) };
+    assert_eq!(unsafe { config::apply_env() }.unwrap().applied(), 0);
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    static_keys::global_init();
+    unsafe { CONFIG_SNAPSHOT_A_STATIC_KEY.enable() };
+    let snapshot = config::snapshot()
+        .split(',')
+        .filter(|item| item.starts_with("config.snapshot."))
+        .collect::<Vec<_>>()
+        .join(",");
+    assert_eq!(snapshot.split(',').count(), 2);
+
+    unsafe { CONFIG_SNAPSHOT_A_STATIC_KEY.disable() };
+    unsafe { CONFIG_SNAPSHOT_AB_STATIC_KEY.disable() };
+    let report = unsafe { config::apply_str(&snapshot) }.unwrap();
+    assert_eq!(report.applied(), 2);
+    assert!(report.unknown().is_empty());
+    assert!(CONFIG_SNAPSHOT_A_STATIC_KEY.is_enabled());
+    assert!(CONFIG_SNAPSHOT_AB_STATIC_KEY.is_enabled());
+
+    // A name without wildcards only matches itself
+    let report = unsafe { config::apply_str("config.snapshot.a=off") }.unwrap();
+    assert_eq!(report.applied(), 1);
+    assert!(!CONFIG_SNAPSHOT_A_STATIC_KEY.is_enabled());
+    assert!(CONFIG_SNAPSHOT_AB_STATIC_KEY.is_enabled());
+}
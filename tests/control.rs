@@ -0,0 +1,134 @@
+//! Tests for the control server of named static keys.
+
+#![cfg(all(feature = "std", unix))]
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::Command,
+    sync::OnceLock,
+};
+
+use static_keys::{define_static_key_false, define_static_key_true, static_branch_unlikely};
+
+define_static_key_false!(CONTROL_TRACE_STATIC_KEY, "control.net.trace");
+define_static_key_true!(CONTROL_LOG_STATIC_KEY, "control.db.\\log");
+
+#[inline(never)]
+fn trace_unlikely() -> bool {
+    static_branch_unlikely!(CONTROL_TRACE_STATIC_KEY)
+}
+
+/// Start the control server once, and return the path of its socket
+fn socket_path() -> &'static PathBuf {
+    static SOCKET_PATH: OnceLock<PathBuf> = OnceLock::new();
+    SOCKET_PATH.get_or_init(|| {
+        let path =
+            std::env::temp_dir().join(format!("static-keys-control-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        static_keys::global_init();
+        let server_path = path.clone();
+        std::thread::spawn(move || unsafe { static_keys::control::serve(server_path) });
+        while UnixStream::connect(&path).is_err() {
+            std::thread::yield_now();
+        }
+        path
+    })
+}
+
+/// Send commands in one connection and collect responses
+fn send(commands: &[&str]) -> Vec<String> {
+    let mut stream = UnixStream::connect(socket_path()).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    commands
+        .iter()
+        .map(|command| {
+            writeln!(stream, "{command}").unwrap();
+            let mut response = String::new();
+            reader.read_line(&mut response).unwrap();
+            response.trim_end().to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn test_commands() {
+    let responses = send(&[
+        "get control.net.trace",
+        "enable control.net.*",
+        "get control.net.trace",
+        "disable control.db.*",
+        "get control.db.\\log",
+        "reset control.*",
+        "get control.net.trace",
+        "get control.db.\\log",
+    ]);
+    assert_eq!(
+        responses,
+        [
+            r#"{"ok":true,"key":{"name":"control.net.trace","enabled":false,"initial":false}}"#,
+            r#"{"ok":true,"matched":1}"#,
+            r#"{"ok":true,"key":{"name":"control.net.trace","enabled":true,"initial":false}}"#,
+            r#"{"ok":true,"matched":1}"#,
+            r#"{"ok":true,"key":{"name":"control.db.\\log","enabled":false,"initial":true}}"#,
+            r#"{"ok":true,"matched":2}"#,
+            r#"{"ok":true,"key":{"name":"control.net.trace","enabled":false,"initial":false}}"#,
+            r#"{"ok":true,"key":{"name":"control.db.\\log","enabled":true,"initial":true}}"#,
+        ]
+    );
+    assert!(!trace_unlikely());
+
+    let list = &send(&["list"])[0];
+    assert!(list.starts_with(r#"{"ok":true,"keys":["#));
+    assert!(list.contains(r#"{"name":"control.net.trace","enabled":false,"initial":false}"#));
+}
+
+#[test]
+fn test_errors() {
+    let responses = send(&[
+        "get control.none",
+        "enable control.none.*",
+        "enable",
+        "list control",
+        "toggle control.net.trace",
+        "enable control.net.trace now",
+    ]);
+    assert_eq!(
+        responses,
+        [
+            r#"{"ok":false,"error":"unknown static key control.none"}"#,
+            r#"{"ok":false,"error":"no static key matches control.none.*"}"#,
+            r#"{"ok":false,"error":"wrong arguments of enable"}"#,
+            r#"{"ok":false,"error":"wrong arguments of list"}"#,
+            r#"{"ok":false,"error":"unknown command toggle"}"#,
+            r#"{"ok":false,"error":"too many arguments"}"#,
+        ]
+    );
+}
+
+#[test]
+fn test_ctl() {
+    let ctl = env!("CARGO_BIN_EXE_static-keys-ctl");
+    let output = Command::new(ctl)
+        .arg(socket_path())
+        .args(["get", "control.net.trace"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .starts_with(r#"{"ok":true,"key":"#)
+    );
+
+    let output = Command::new(ctl)
+        .arg(socket_path())
+        .args(["get", "control.none"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let output = Command::new(ctl).arg(socket_path()).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
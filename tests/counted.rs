@@ -0,0 +1,92 @@
+//! Tests for reference-counted static keys.
+
+use static_keys::{
+    define_counted_static_key_false, define_counted_static_key_true, static_branch_likely,
+    static_branch_unlikely,
+};
+
+define_counted_static_key_false!(COUNTED_FALSE_STATIC_KEY);
+define_counted_static_key_true!(COUNTED_TRUE_STATIC_KEY);
+
+#[inline(never)]
+fn counted_false_unlikely() -> bool {
+    static_branch_unlikely!(COUNTED_FALSE_STATIC_KEY)
+}
+
+#[inline(never)]
+fn counted_false_likely() -> bool {
+    static_branch_likely!(COUNTED_FALSE_STATIC_KEY)
+}
+
+#[inline(never)]
+fn counted_true_unlikely() -> bool {
+    static_branch_unlikely!(COUNTED_TRUE_STATIC_KEY)
+}
+
+#[test]
+fn test_counted_false_key() {
+    static_keys::global_init();
+    assert_eq!(COUNTED_FALSE_STATIC_KEY.count(), 0);
+    assert!(!COUNTED_FALSE_STATIC_KEY.is_enabled());
+    assert!(!counted_false_unlikely());
+    assert!(!counted_false_likely());
+
+    let guard_0 = unsafe { COUNTED_FALSE_STATIC_KEY.acquire() };
+    assert_eq!(COUNTED_FALSE_STATIC_KEY.count(), 1);
+    assert!(COUNTED_FALSE_STATIC_KEY.is_enabled());
+    assert!(counted_false_unlikely());
+    assert!(counted_false_likely());
+
+    let guard_1 = unsafe { COUNTED_FALSE_STATIC_KEY.try_acquire() }.unwrap();
+    assert_eq!(COUNTED_FALSE_STATIC_KEY.count(), 2);
+
+    // Still referenced by the other guard
+    drop(guard_0);
+    assert_eq!(COUNTED_FALSE_STATIC_KEY.count(), 1);
+    assert!(COUNTED_FALSE_STATIC_KEY.is_enabled());
+    assert!(counted_false_unlikely());
+    assert!(counted_false_likely());
+
+    drop(guard_1);
+    assert_eq!(COUNTED_FALSE_STATIC_KEY.count(), 0);
+    assert!(!COUNTED_FALSE_STATIC_KEY.is_enabled());
+    assert!(!counted_false_unlikely());
+    assert!(!counted_false_likely());
+}
+
+#[test]
+fn test_counted_true_key() {
+    static_keys::global_init();
+    assert_eq!(COUNTED_TRUE_STATIC_KEY.count(), 1);
+    assert!(counted_true_unlikely());
+
+    let guard = unsafe { COUNTED_TRUE_STATIC_KEY.acquire() };
+    assert_eq!(COUNTED_TRUE_STATIC_KEY.count(), 2);
+    drop(guard);
+    // The initial reference is still held
+    assert_eq!(COUNTED_TRUE_STATIC_KEY.count(), 1);
+    assert!(COUNTED_TRUE_STATIC_KEY.is_enabled());
+    assert!(counted_true_unlikely());
+
+    let initial = unsafe { COUNTED_TRUE_STATIC_KEY.take_initial() }.unwrap();
+    assert_eq!(COUNTED_TRUE_STATIC_KEY.count(), 1);
+    // The initial reference can only be taken once
+    assert!(unsafe { COUNTED_TRUE_STATIC_KEY.take_initial() }.is_none());
+    drop(initial);
+    assert_eq!(COUNTED_TRUE_STATIC_KEY.count(), 0);
+    assert!(!COUNTED_TRUE_STATIC_KEY.is_enabled());
+    assert!(!counted_true_unlikely());
+
+    let guard = unsafe { COUNTED_TRUE_STATIC_KEY.acquire() };
+    assert!(COUNTED_TRUE_STATIC_KEY.is_enabled());
+    assert!(counted_true_unlikely());
+    drop(guard);
+    assert!(!COUNTED_TRUE_STATIC_KEY.is_enabled());
+}
+
+#[test]
+fn test_counted_false_key_take_initial() {
+    static_keys::global_init();
+    // There is no initial reference
+    assert!(unsafe { COUNTED_FALSE_STATIC_KEY.take_initial() }.is_none());
+}
@@ -0,0 +1,93 @@
+//! Tests for static keys backed by CPU features.
+
+use static_keys::{define_cpu_feature_key, static_branch_unlikely};
+
+define_cpu_feature_key!(HAS_SSE2, x86: "sse2", aarch64: "asimd");
+define_cpu_feature_key!(HAS_AVX2, x86: "avx2");
+define_cpu_feature_key!(HAS_AVX, x86: "avx");
+define_cpu_feature_key!(HAS_FMA, x86: "fma");
+define_cpu_feature_key!(HAS_AVX512F, x86: "avx512f");
+define_cpu_feature_key!(HAS_AVX512VBMI, x86: "avx512vbmi");
+define_cpu_feature_key!(HAS_LZCNT, x86: "lzcnt");
+define_cpu_feature_key!(HAS_XSAVEOPT, x86: "xsaveopt");
+define_cpu_feature_key!(HAS_RDSEED, x86: "rdseed");
+define_cpu_feature_key!(HAS_AES, aarch64: "aes");
+define_cpu_feature_key!(HAS_NOTHING);
+
+#[inline(never)]
+fn has_sse2() -> bool {
+    static_branch_unlikely!(HAS_SSE2)
+}
+
+#[test]
+fn test_baseline_feature() {
+    static_keys::global_init();
+    // Both SSE2 on x86_64 and ASIMD on aarch64 are always available, and they can be detected
+    // without std except for aarch64 on other OSs than Linux
+    #[cfg(any(
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", any(feature = "std", target_os = "linux"))
+    ))]
+    {
+        assert!(HAS_SSE2.is_enabled());
+        assert!(has_sse2());
+    }
+    assert_eq!(has_sse2(), HAS_SSE2.is_enabled());
+}
+
+// Without std, the CPU features are detected by CPUID and XGETBV directly, which should agree with
+// std
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[test]
+fn test_detected_feature() {
+    static_keys::global_init();
+    assert_eq!(
+        HAS_AVX2.is_enabled(),
+        std::arch::is_x86_feature_detected!("avx2")
+    );
+    assert_eq!(
+        HAS_AVX.is_enabled(),
+        std::arch::is_x86_feature_detected!("avx")
+    );
+    assert_eq!(
+        HAS_FMA.is_enabled(),
+        std::arch::is_x86_feature_detected!("fma")
+    );
+    assert_eq!(
+        HAS_AVX512F.is_enabled(),
+        std::arch::is_x86_feature_detected!("avx512f")
+    );
+    assert_eq!(
+        HAS_AVX512VBMI.is_enabled(),
+        std::arch::is_x86_feature_detected!("avx512vbmi")
+    );
+    assert_eq!(
+        HAS_LZCNT.is_enabled(),
+        std::arch::is_x86_feature_detected!("lzcnt")
+    );
+    assert_eq!(
+        HAS_XSAVEOPT.is_enabled(),
+        std::arch::is_x86_feature_detected!("xsaveopt")
+    );
+    assert_eq!(
+        HAS_RDSEED.is_enabled(),
+        std::arch::is_x86_feature_detected!("rdseed")
+    );
+    // The static key is only enabled on aarch64
+    assert!(!HAS_AES.is_enabled());
+}
+
+#[test]
+fn test_no_feature() {
+    static_keys::global_init();
+    assert!(!HAS_NOTHING.is_enabled());
+}
+
+#[test]
+fn test_registry() {
+    static_keys::global_init();
+    let key = static_keys::registry()
+        .find("cpu_feature::HAS_AVX2")
+        .unwrap();
+    assert_eq!(key.is_enabled(), HAS_AVX2.is_enabled());
+}
@@ -0,0 +1,107 @@
+//! Tests for deferred static keys.
+
+use std::time::Duration;
+
+use static_keys::{define_deferred_static_key_false, static_branch_unlikely};
+
+define_deferred_static_key_false!(DEFERRED_STATIC_KEY, Duration::from_millis(100));
+
+#[inline(never)]
+fn deferred_unlikely() -> bool {
+    static_branch_unlikely!(DEFERRED_STATIC_KEY)
+}
+
+/// Check both the status and branch
+fn assert_enabled(enabled: bool) {
+    assert_eq!(DEFERRED_STATIC_KEY.is_enabled(), enabled);
+    assert_eq!(deferred_unlikely(), enabled);
+}
+
+#[cfg(not(feature = "std"))]
+#[test]
+fn test_deferred_tick() {
+    use static_keys::deferred::tick;
+
+    static_keys::global_init();
+    assert_eq!(DEFERRED_STATIC_KEY.timeout(), Duration::from_millis(100));
+    assert_eq!(unsafe { tick(Duration::ZERO) }, Ok(None));
+
+    // Enabling takes effect immediately
+    unsafe {
+        DEFERRED_STATIC_KEY.enable();
+    }
+    assert_enabled(true);
+
+    // Disabling is deferred
+    unsafe {
+        DEFERRED_STATIC_KEY.disable();
+    }
+    assert!(DEFERRED_STATIC_KEY.is_disable_pending());
+    assert_enabled(true);
+    assert_eq!(
+        unsafe { tick(Duration::from_millis(1000)) },
+        Ok(Some(Duration::from_millis(1100)))
+    );
+    assert_enabled(true);
+
+    // Enabling within the timeout cancels the pending disable
+    unsafe {
+        DEFERRED_STATIC_KEY.enable();
+    }
+    assert!(!DEFERRED_STATIC_KEY.is_disable_pending());
+    assert_eq!(unsafe { tick(Duration::from_millis(1200)) }, Ok(None));
+    assert_enabled(true);
+
+    // Disabling requests within the timeout are merged
+    unsafe {
+        DEFERRED_STATIC_KEY.disable();
+    }
+    assert_eq!(
+        unsafe { tick(Duration::from_millis(2000)) },
+        Ok(Some(Duration::from_millis(2100)))
+    );
+    unsafe {
+        DEFERRED_STATIC_KEY.disable();
+    }
+    assert_eq!(
+        unsafe { tick(Duration::from_millis(2099)) },
+        Ok(Some(Duration::from_millis(2100)))
+    );
+    assert_enabled(true);
+    assert_eq!(unsafe { tick(Duration::from_millis(2100)) }, Ok(None));
+    assert!(!DEFERRED_STATIC_KEY.is_disable_pending());
+    assert_enabled(false);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_deferred_timer() {
+    static_keys::global_init();
+    unsafe {
+        DEFERRED_STATIC_KEY.enable();
+    }
+    assert_enabled(true);
+
+    unsafe {
+        DEFERRED_STATIC_KEY.disable();
+    }
+    assert_enabled(true);
+    // Enabling within the timeout cancels the pending disable
+    unsafe {
+        DEFERRED_STATIC_KEY.enable();
+    }
+    std::thread::sleep(Duration::from_millis(300));
+    assert_enabled(true);
+
+    unsafe {
+        DEFERRED_STATIC_KEY.disable();
+    }
+    for _ in 0..100 {
+        if !DEFERRED_STATIC_KEY.is_enabled() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert!(!DEFERRED_STATIC_KEY.is_disable_pending());
+    assert_enabled(false);
+}
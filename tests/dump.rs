@@ -0,0 +1,126 @@
+//! Tests for offline inspection of static keys in ELF files.
+
+#![cfg(all(feature = "std", target_os = "linux"))]
+
+use std::process::Command;
+
+use static_keys::{
+    InstructionKind, define_static_key_false, define_static_key_true,
+    dump::{DumpError, Image, ImageKey},
+    static_branch_likely, static_branch_unlikely,
+};
+
+define_static_key_false!(DUMP_TRACE_STATIC_KEY, "dump.net.trace");
+define_static_key_true!(DUMP_LOG_STATIC_KEY, "dump.db.log");
+define_static_key_false!(DUMP_UNUSED_STATIC_KEY, "dump.unused");
+
+#[inline(never)]
+fn trace_unlikely() -> bool {
+    static_branch_unlikely!(DUMP_TRACE_STATIC_KEY)
+}
+
+#[inline(never)]
+fn log_likely() -> bool {
+    static_branch_likely!(DUMP_LOG_STATIC_KEY)
+}
+
+fn find_key<'a>(keys: &'a [ImageKey], name: &str) -> &'a ImageKey {
+    keys.iter().find(|key| key.name() == Some(name)).unwrap()
+}
+
+#[test]
+fn test_dump() {
+    assert!(!trace_unlikely());
+    assert!(log_likely());
+    let image = Image::open(std::env::current_exe().unwrap()).unwrap();
+    let keys = image.keys();
+
+    let trace = find_key(&keys, "dump.net.trace");
+    assert_eq!(trace.symbol(), Some("dump::DUMP_TRACE_STATIC_KEY"));
+    assert!(!trace.initial_enabled());
+    assert_eq!(trace.sites().len(), 1);
+    let site = &trace.sites()[0];
+    let (function, offset) = site.function().unwrap();
+    assert_eq!(function, "dump::trace_unlikely");
+    assert!(offset > 0);
+    assert!(!site.likely_branch_is_true());
+    assert!(!site.is_far());
+    assert_eq!(site.instruction(), InstructionKind::Nop);
+
+    let log = find_key(&keys, "dump.db.log");
+    assert!(log.initial_enabled());
+    assert_eq!(log.sites().len(), 1);
+    assert_eq!(log.sites()[0].function().unwrap().0, "dump::log_likely");
+    assert!(log.sites()[0].likely_branch_is_true());
+    assert_eq!(log.sites()[0].instruction(), InstructionKind::Nop);
+
+    assert!(find_key(&keys, "dump.unused").sites().is_empty());
+    assert!(
+        keys.iter()
+            .all(|key| key.symbol() != Some("static_keys::DUMMY_STATIC_KEY"))
+    );
+}
+
+#[test]
+fn test_dump_errors() {
+    assert!(matches!(
+        Image::parse(b"not an ELF file".to_vec()),
+        Err(DumpError::InvalidElf(_))
+    ));
+    assert!(matches!(
+        Image::open("/nonexistent/static-keys"),
+        Err(DumpError::Io { .. })
+    ));
+
+    let data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+    // `e_machine` of another architecture
+    let mut other_machine = data.clone();
+    other_machine[18..20].copy_from_slice(&0xffffu16.to_ne_bytes());
+    assert!(matches!(
+        Image::parse(other_machine),
+        Err(DumpError::InvalidElf(_))
+    ));
+    // `e_phoff` overflowing when adding offsets of program headers
+    let mut overflowing = data;
+    let word = std::mem::size_of::<usize>();
+    overflowing[24 + word..24 + 2 * word].copy_from_slice(&usize::MAX.to_ne_bytes());
+    assert!(matches!(
+        Image::parse(overflowing),
+        Err(DumpError::InvalidElf(_))
+    ));
+}
+
+#[test]
+fn test_dump_binary() {
+    let dump = env!("CARGO_BIN_EXE_static-keys-dump");
+    let exe = std::env::current_exe().unwrap();
+    let output = Command::new(dump)
+        .args([
+            "--expect",
+            "dump.net.trace",
+            "--expect",
+            "dump::DUMP_LOG_STATIC_KEY",
+        ])
+        .arg(&exe)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("dump.net.trace (dump::DUMP_TRACE_STATIC_KEY): initially off, 1 site\n")
+    );
+    assert!(stdout.contains(" dump::trace_unlikely+0x"));
+
+    let output = Command::new(dump)
+        .args(["--expect", "dump.unused", "--expect", "dump.none"])
+        .arg(&exe)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Static key dump.unused has no branch sites"));
+    assert!(stderr.contains("Static key dump.none is not found"));
+
+    let output = Command::new(dump).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
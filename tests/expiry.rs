@@ -0,0 +1,54 @@
+//! Tests for static keys enabled for a limited time.
+
+#![cfg(feature = "std")]
+
+use std::time::Duration;
+
+use static_keys::{define_static_key_false, static_branch_unlikely};
+
+define_static_key_false!(EXPIRING_STATIC_KEY);
+
+#[inline(never)]
+fn expiring_unlikely() -> bool {
+    static_branch_unlikely!(EXPIRING_STATIC_KEY)
+}
+
+/// Check both the status and branch
+fn assert_enabled(enabled: bool) {
+    assert_eq!(EXPIRING_STATIC_KEY.is_enabled(), enabled);
+    assert_eq!(expiring_unlikely(), enabled);
+}
+
+#[test]
+fn test_expiry() {
+    static_keys::global_init();
+    unsafe {
+        EXPIRING_STATIC_KEY.enable_for(Duration::from_millis(200));
+    }
+    assert_enabled(true);
+    let remaining = EXPIRING_STATIC_KEY.expires_in().unwrap();
+    assert!(remaining <= Duration::from_millis(200));
+
+    for _ in 0..100 {
+        if !EXPIRING_STATIC_KEY.is_enabled() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_enabled(false);
+    assert_eq!(EXPIRING_STATIC_KEY.expires_in(), None);
+
+    // Modifying the static key explicitly cancels the expiry
+    unsafe {
+        EXPIRING_STATIC_KEY.enable_for(Duration::from_millis(100));
+        EXPIRING_STATIC_KEY.enable();
+    }
+    assert_eq!(EXPIRING_STATIC_KEY.expires_in(), None);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_enabled(true);
+
+    unsafe {
+        EXPIRING_STATIC_KEY.disable();
+    }
+    assert_enabled(false);
+}
@@ -0,0 +1,76 @@
+//! Tests for static branches whose destination may be far away.
+
+use static_keys::{
+    define_static_key_false, define_static_key_true, static_branch_likely_far,
+    static_branch_unlikely_far,
+};
+
+define_static_key_true!(FAR_TRUE_STATIC_KEY);
+define_static_key_false!(FAR_FALSE_STATIC_KEY);
+
+#[inline(never)]
+fn true_likely() -> usize {
+    if static_branch_likely_far!(FAR_TRUE_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+fn true_unlikely() -> usize {
+    if static_branch_unlikely_far!(FAR_TRUE_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+fn false_likely() -> usize {
+    if static_branch_likely_far!(FAR_FALSE_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+fn false_unlikely() -> usize {
+    if static_branch_unlikely_far!(FAR_FALSE_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[test]
+fn test_far_branch() {
+    static_keys::global_init();
+    assert_eq!(static_keys::verify_all().count(), 0);
+    static_keys::validate_jump_entries(|issue| panic!("Invalid jump entry: {issue}"));
+
+    assert_eq!(true_likely(), 1);
+    assert_eq!(true_unlikely(), 1);
+    assert_eq!(false_likely(), 2);
+    assert_eq!(false_unlikely(), 2);
+
+    unsafe {
+        FAR_TRUE_STATIC_KEY.disable();
+        FAR_FALSE_STATIC_KEY.enable();
+    }
+    assert_eq!(true_likely(), 2);
+    assert_eq!(true_unlikely(), 2);
+    assert_eq!(false_likely(), 1);
+    assert_eq!(false_unlikely(), 1);
+    assert_eq!(static_keys::verify_all().count(), 0);
+
+    unsafe {
+        FAR_TRUE_STATIC_KEY.enable();
+        FAR_FALSE_STATIC_KEY.disable();
+    }
+    assert_eq!(true_likely(), 1);
+    assert_eq!(true_unlikely(), 1);
+    assert_eq!(false_likely(), 2);
+    assert_eq!(false_unlikely(), 2);
+}
@@ -0,0 +1,136 @@
+//! Tests for introspection of static keys and branch sites.
+
+use static_keys::{
+    InstructionKind, define_static_call, define_static_key_false, define_static_key_true,
+    static_branch_likely, static_branch_unlikely, static_call,
+};
+
+define_static_key_false!(INTROSPECT_FALSE_STATIC_KEY);
+define_static_key_true!(INTROSPECT_TRUE_STATIC_KEY);
+define_static_key_false!(UNUSED_STATIC_KEY);
+
+fn introspect_target() {}
+
+define_static_call!(INTROSPECT_STATIC_CALL, fn() = introspect_target);
+
+#[inline(never)]
+fn false_unlikely() -> bool {
+    static_branch_unlikely!(INTROSPECT_FALSE_STATIC_KEY)
+}
+
+#[inline(never)]
+fn false_likely() -> bool {
+    static_branch_likely!(INTROSPECT_FALSE_STATIC_KEY)
+}
+
+#[inline(never)]
+fn true_likely() -> bool {
+    static_branch_likely!(INTROSPECT_TRUE_STATIC_KEY)
+}
+
+#[test]
+fn test_keys() {
+    static_keys::global_init();
+    assert!(true_likely());
+
+    let key = static_keys::keys()
+        .find(|key| key.addr() == &raw const INTROSPECT_TRUE_STATIC_KEY as usize)
+        .unwrap();
+    assert!(key.is_enabled());
+    assert!(key.initial_enabled());
+    assert_eq!(key.site_count(), 1);
+    assert_eq!(key.sites().count(), 1);
+
+    // Static keys never used by any branch are unknown
+    assert!(static_keys::keys().all(|key| key.addr() != &raw const UNUSED_STATIC_KEY as usize));
+    // Sorted by address without duplication
+    let addrs = static_keys::keys()
+        .map(|key| key.addr())
+        .collect::<Vec<_>>();
+    assert!(addrs.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn test_sites() {
+    static_keys::global_init();
+    assert!(!false_unlikely());
+    assert!(!false_likely());
+
+    let sites = INTROSPECT_FALSE_STATIC_KEY.sites().collect::<Vec<_>>();
+    assert_eq!(sites.len(), 2);
+    assert!(
+        sites
+            .windows(2)
+            .all(|pair| pair[0].code_addr() < pair[1].code_addr())
+    );
+    let unlikely_site = sites
+        .iter()
+        .find(|site| !site.likely_branch_is_true())
+        .unwrap();
+    let likely_site = sites
+        .iter()
+        .find(|site| site.likely_branch_is_true())
+        .unwrap();
+    assert_eq!(unlikely_site.instruction(), InstructionKind::Nop);
+    assert_eq!(likely_site.instruction(), InstructionKind::Jmp);
+    assert!(static_keys::is_patch_site(unlikely_site.code_addr()));
+    assert!(static_keys::is_patch_site(likely_site.code_addr()));
+
+    unsafe {
+        INTROSPECT_FALSE_STATIC_KEY.enable();
+    }
+    assert!(false_unlikely());
+    assert!(false_likely());
+    for site in INTROSPECT_FALSE_STATIC_KEY.sites() {
+        let expected = if site.likely_branch_is_true() {
+            InstructionKind::Nop
+        } else {
+            InstructionKind::Jmp
+        };
+        assert_eq!(site.instruction(), expected);
+    }
+
+    unsafe {
+        INTROSPECT_FALSE_STATIC_KEY.disable();
+    }
+    assert!(!false_unlikely());
+    assert!(!false_likely());
+}
+
+#[test]
+fn test_is_patch_site() {
+    static_keys::global_init();
+    assert!(!static_keys::is_patch_site(
+        &raw const INTROSPECT_FALSE_STATIC_KEY as usize
+    ));
+    assert!(!static_keys::is_patch_site(0));
+
+    // The JMP instruction of a trampoline follows its alignment padding
+    let trampoline = static_call!(INTROSPECT_STATIC_CALL) as usize;
+    assert!((trampoline..trampoline + 8).any(static_keys::is_patch_site));
+    assert!(!static_keys::is_patch_site(
+        introspect_target as fn() as usize
+    ));
+}
+
+#[test]
+fn test_debug() {
+    static_keys::global_init();
+    assert_eq!(
+        format!("{UNUSED_STATIC_KEY:?}"),
+        "GenericStaticKey { enabled: false, initial_enabled: false, site_count: 0 }"
+    );
+    let key = static_keys::keys()
+        .find(|key| key.addr() == &raw const INTROSPECT_TRUE_STATIC_KEY as usize)
+        .unwrap();
+    assert!(format!("{key:?}").contains("initial_enabled: true, site_count: 1"));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_panic_hook() {
+    static_keys::global_init();
+    static_keys::install_panic_hook();
+    let result = std::thread::spawn(|| panic!("Expected panic")).join();
+    assert!(result.is_err());
+}
@@ -0,0 +1,30 @@
+//! Tests for modifying static keys without calling `global_init` first.
+
+use static_keys::{define_static_key_false, static_branch_unlikely};
+
+define_static_key_false!(LAZY_INIT_STATIC_KEY);
+
+#[inline(never)]
+fn lazy_init_unlikely() -> bool {
+    static_branch_unlikely!(LAZY_INIT_STATIC_KEY)
+}
+
+#[test]
+fn test_lazy_init() {
+    // `global_init` is called by the modification
+    unsafe {
+        LAZY_INIT_STATIC_KEY.enable();
+    }
+    assert!(LAZY_INIT_STATIC_KEY.is_enabled());
+    assert!(lazy_init_unlikely());
+
+    // Calling it again does nothing
+    static_keys::global_init();
+    assert!(lazy_init_unlikely());
+
+    unsafe {
+        LAZY_INIT_STATIC_KEY.disable();
+    }
+    assert!(!LAZY_INIT_STATIC_KEY.is_enabled());
+    assert!(!lazy_init_unlikely());
+}
@@ -0,0 +1,170 @@
+//! Stress test for toggling static keys while other threads are executing the associated branches.
+
+#![cfg(target_os = "linux")]
+
+use std::sync::{
+    Arc, Barrier,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use static_keys::{
+    GenericStaticKey,
+    code_manipulate::{ArchCodeManipulator, CodeManipulateError, CodeManipulator},
+    define_static_key_false, new_static_key, static_branch_likely, static_branch_unlikely,
+};
+
+define_static_key_false!(LIVE_STATIC_KEY);
+
+const WORKER_COUNT: usize = 8;
+const TOGGLE_COUNT: usize = 500;
+
+/// Incremented before and after each modification of `LIVE_STATIC_KEY`, so it is odd while the
+/// instructions are being modified
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether `result` of the branches is possible at `epoch`
+fn is_possible_result(result: usize, epoch: usize) -> bool {
+    // The static key is disabled at epoch 0, and toggled every two epochs
+    let result_at = |epoch: usize| if (epoch / 2) % 2 == 1 { 1 } else { 2 };
+    if epoch.is_multiple_of(2) {
+        result == result_at(epoch)
+    } else {
+        result == result_at(epoch - 1) || result == result_at(epoch + 1)
+    }
+}
+
+#[inline(never)]
+fn live_likely() -> usize {
+    if static_branch_likely!(LIVE_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+fn live_unlikely() -> usize {
+    if static_branch_unlikely!(LIVE_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[test]
+fn test_live_toggle() {
+    static_keys::global_init();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let barrier = Arc::new(Barrier::new(WORKER_COUNT + 1));
+    let workers = (0..WORKER_COUNT)
+        .map(|_| {
+            let stop = stop.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                let mut rounds = 0usize;
+                while !stop.load(Ordering::Relaxed) {
+                    let before = EPOCH.load(Ordering::SeqCst);
+                    let likely = live_likely();
+                    let unlikely = live_unlikely();
+                    let after = EPOCH.load(Ordering::SeqCst);
+                    // The results must match a status of the static key during the calls
+                    for result in [likely, unlikely] {
+                        assert!(
+                            (before..=after).any(|epoch| is_possible_result(result, epoch)),
+                            "Result {result} is impossible between epoch {before} and {after}"
+                        );
+                    }
+                    rounds += 1;
+                }
+                rounds
+            })
+        })
+        .collect::<Vec<_>>();
+
+    barrier.wait();
+    for _ in 0..TOGGLE_COUNT {
+        EPOCH.fetch_add(1, Ordering::SeqCst);
+        LIVE_STATIC_KEY.enable_live();
+        EPOCH.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(live_likely(), 1);
+        assert_eq!(live_unlikely(), 1);
+        EPOCH.fetch_add(1, Ordering::SeqCst);
+        LIVE_STATIC_KEY.disable_live();
+        EPOCH.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(live_likely(), 2);
+        assert_eq!(live_unlikely(), 2);
+    }
+    stop.store(true, Ordering::Relaxed);
+    for worker in workers {
+        assert!(worker.join().unwrap() > 0);
+    }
+
+    // Toggling to the current status does nothing
+    LIVE_STATIC_KEY.disable_live();
+    assert!(!LIVE_STATIC_KEY.is_enabled());
+    assert_eq!(live_likely(), 2);
+}
+
+/// Count of [`CountingCodeManipulator::write_code_batch`] calls
+static BATCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Code manipulator counting batches written
+struct CountingCodeManipulator;
+
+impl CodeManipulator for CountingCodeManipulator {
+    unsafe fn write_code<const L: usize>(
+        addr: *mut core::ffi::c_void,
+        data: &[u8; L],
+    ) -> Result<(), CodeManipulateError> {
+        unsafe { Self::write_code_batch(&[(addr, *data)]) }
+    }
+
+    unsafe fn write_code_batch<const L: usize>(
+        patches: &[(*mut core::ffi::c_void, [u8; L])],
+    ) -> Result<(), CodeManipulateError> {
+        BATCH_COUNT.fetch_add(1, Ordering::Relaxed);
+        unsafe { ArchCodeManipulator::write_code_batch(patches) }
+    }
+}
+
+#[used]
+static COUNTING_STATIC_KEY: GenericStaticKey<CountingCodeManipulator, false> = new_static_key();
+
+#[inline(never)]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn counting_likely() -> usize {
+    if static_branch_likely!(COUNTING_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn counting_unlikely() -> usize {
+    if static_branch_unlikely!(COUNTING_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[test]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn test_live_batch() {
+    static_keys::global_init();
+
+    // Each of the three steps of breakpoint protocol writes all sites in one batch
+    COUNTING_STATIC_KEY.enable_live();
+    assert_eq!(BATCH_COUNT.load(Ordering::Relaxed), 3);
+    assert_eq!(counting_likely(), 1);
+    assert_eq!(counting_unlikely(), 1);
+
+    COUNTING_STATIC_KEY.disable_live();
+    assert_eq!(BATCH_COUNT.load(Ordering::Relaxed), 6);
+    assert_eq!(counting_likely(), 2);
+    assert_eq!(counting_unlikely(), 2);
+}
@@ -0,0 +1,30 @@
+//! Tests for modifying static keys while other threads may be running before `global_init`.
+
+#![cfg(target_os = "linux")]
+
+use static_keys::{StaticKeyError, define_static_key_false, static_branch_unlikely};
+
+define_static_key_false!(LIVE_UNINIT_STATIC_KEY);
+
+#[inline(never)]
+fn live_uninit_unlikely() -> bool {
+    static_branch_unlikely!(LIVE_UNINIT_STATIC_KEY)
+}
+
+#[test]
+fn test_live_uninit() {
+    // `global_init` is never called lazily, since other threads may be running
+    assert_eq!(
+        LIVE_UNINIT_STATIC_KEY.enable_stop_the_world(),
+        Err(StaticKeyError::NotInitialized)
+    );
+    assert!(std::panic::catch_unwind(|| LIVE_UNINIT_STATIC_KEY.enable_live()).is_err());
+    assert!(!LIVE_UNINIT_STATIC_KEY.is_enabled());
+    assert!(!live_uninit_unlikely());
+
+    static_keys::global_init();
+    LIVE_UNINIT_STATIC_KEY.enable_live();
+    assert!(live_uninit_unlikely());
+    LIVE_UNINIT_STATIC_KEY.disable_stop_the_world().unwrap();
+    assert!(!live_uninit_unlikely());
+}
@@ -0,0 +1,69 @@
+//! Tests for named static keys.
+
+use static_keys::{define_static_key_false, define_static_key_true, static_branch_unlikely};
+
+define_static_key_false!(HTTP_TRACE_STATIC_KEY, "registry.net.http.trace");
+define_static_key_false!(DNS_TRACE_STATIC_KEY, "registry.net.dns.trace");
+define_static_key_true!(SLOW_LOG_STATIC_KEY, "registry.db.slow_log");
+define_static_key_false!(DEFAULT_NAME_STATIC_KEY);
+
+#[inline(never)]
+fn http_trace_unlikely() -> bool {
+    static_branch_unlikely!(HTTP_TRACE_STATIC_KEY)
+}
+
+#[test]
+fn test_find() {
+    static_keys::global_init();
+    let registry = static_keys::registry();
+    let key = registry.find("registry.db.slow_log").unwrap();
+    assert_eq!(key.name(), "registry.db.slow_log");
+    assert_eq!(key.addr(), &raw const SLOW_LOG_STATIC_KEY as usize);
+    assert!(key.is_enabled());
+    assert!(key.initial_enabled());
+
+    let key = registry.find("registry::DEFAULT_NAME_STATIC_KEY").unwrap();
+    assert_eq!(key.addr(), &raw const DEFAULT_NAME_STATIC_KEY as usize);
+    assert!(!key.initial_enabled());
+
+    assert!(registry.find("registry.net").is_none());
+    assert!(registry.iter().count() >= 4);
+}
+
+#[test]
+fn test_matching() {
+    static_keys::global_init();
+    let registry = static_keys::registry();
+    assert_eq!(registry.matching("registry.net.*").count(), 2);
+    assert_eq!(registry.matching("registry.*.trace").count(), 2);
+    assert_eq!(registry.matching("registry.net.???.trace").count(), 1);
+    assert_eq!(registry.matching("registry.*s*").count(), 2);
+    assert_eq!(registry.matching("registry.*").count(), 3);
+    assert_eq!(registry.matching("registry.net").count(), 0);
+    assert_eq!(registry.matching("registry.net.*.").count(), 0);
+}
+
+#[test]
+fn test_set_matching() {
+    static_keys::global_init();
+    let registry = static_keys::registry();
+    assert!(!http_trace_unlikely());
+
+    assert_eq!(
+        unsafe { registry.set_matching("registry.net.*", true) },
+        Ok(2)
+    );
+    assert!(HTTP_TRACE_STATIC_KEY.is_enabled());
+    assert!(DNS_TRACE_STATIC_KEY.is_enabled());
+    assert!(http_trace_unlikely());
+    assert!(SLOW_LOG_STATIC_KEY.is_enabled());
+
+    unsafe { registry.find("registry.net.http.trace").unwrap().set(false) };
+    assert!(!http_trace_unlikely());
+    assert_eq!(
+        unsafe { registry.set_matching("registry.net.*", false) },
+        Ok(2)
+    );
+    assert!(!DNS_TRACE_STATIC_KEY.is_enabled());
+    assert_eq!(unsafe { registry.set_matching("nothing.*", true) }, Ok(0));
+}
@@ -0,0 +1,73 @@
+//! Tests for runtime constants.
+
+use static_keys::{
+    RuntimeConst, define_runtime_const, new_runtime_const, runtime_const, runtime_const_init,
+};
+
+define_runtime_const!(RUNTIME_CONST_SEED);
+define_runtime_const!(RUNTIME_CONST_SHIFT, 4);
+static RUNTIME_CONST_TABLE: RuntimeConst = new_runtime_const();
+
+#[inline(never)]
+fn seed() -> u64 {
+    runtime_const!(RUNTIME_CONST_SEED: u64)
+}
+
+#[inline(never)]
+fn shift(input: u32) -> u32 {
+    input >> runtime_const!(RUNTIME_CONST_SHIFT: u32)
+}
+
+#[inline(never)]
+fn shift_signed(input: i64) -> i64 {
+    input >> runtime_const!(RUNTIME_CONST_SHIFT: u32)
+}
+
+#[inline(never)]
+fn table() -> *const u8 {
+    runtime_const!(RUNTIME_CONST_TABLE: usize) as *const u8
+}
+
+#[test]
+fn test_runtime_const() {
+    static_keys::global_init();
+    assert_eq!(seed(), 0);
+    assert_eq!(RUNTIME_CONST_SEED.value(), 0);
+
+    for value in [
+        0x0123_4567_89ab_cdef,
+        u64::MAX,
+        0x8000_0000,
+        0x7fff_ffff_ffff_f800,
+        0xffff_ffff_0000_0800,
+        1,
+        0,
+    ] {
+        unsafe { runtime_const_init(&RUNTIME_CONST_SEED, value) };
+        assert_eq!(RUNTIME_CONST_SEED.value(), value);
+        assert_eq!(seed(), value);
+    }
+
+    // Setting to the current value does nothing
+    unsafe { RUNTIME_CONST_SEED.try_init(0) }.unwrap();
+    assert_eq!(seed(), 0);
+}
+
+#[test]
+fn test_runtime_const_initial_value() {
+    assert_eq!(RUNTIME_CONST_SHIFT.initial_value(), 4);
+    assert_eq!(shift(0x100), 0x10);
+    assert_eq!(shift_signed(-0x100), -0x10);
+    unsafe { RUNTIME_CONST_SHIFT.init(8) };
+    assert_eq!(shift(0x100), 1);
+    assert_eq!(shift_signed(-0x100), -1);
+}
+
+#[test]
+fn test_runtime_const_pointer() {
+    static TABLE: [u8; 4] = [1, 2, 3, 4];
+    assert!(table().is_null());
+    unsafe { RUNTIME_CONST_TABLE.init(TABLE.as_ptr() as u64) };
+    assert_eq!(table(), TABLE.as_ptr());
+    assert_eq!(unsafe { *table().add(2) }, 3);
+}
@@ -0,0 +1,112 @@
+//! Tests for static calls.
+
+use static_keys::{define_static_call, static_call};
+
+fn add_one(input: u32) -> u32 {
+    input + 1
+}
+
+fn double(input: u32) -> u32 {
+    input * 2
+}
+
+fn concat(left: &str, right: &str, separator: char) -> String {
+    format!("{left}{separator}{right}")
+}
+
+fn concat_reversed(left: &str, right: &str, separator: char) -> String {
+    format!("{right}{separator}{left}")
+}
+
+static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+fn count() {
+    COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn skip() {}
+
+define_static_call!(STATIC_CALL_ARITHMETIC, fn(u32) -> u32 = add_one);
+define_static_call!(STATIC_CALL_CONCAT, fn(&str, &str, char) -> String = concat);
+define_static_call!(STATIC_CALL_COUNT, fn() = count);
+define_static_call!(STATIC_CALL_CLOSURE, fn(u32) -> u32 = add_one);
+
+#[inline(never)]
+fn call_arithmetic(input: u32) -> u32 {
+    static_call!(STATIC_CALL_ARITHMETIC)(input)
+}
+
+#[test]
+fn test_static_call() {
+    static_keys::global_init();
+    assert_eq!(call_arithmetic(3), 4);
+    assert!(STATIC_CALL_ARITHMETIC.is_target(add_one));
+
+    unsafe { STATIC_CALL_ARITHMETIC.update(double) };
+    assert_eq!(call_arithmetic(3), 6);
+    assert!(STATIC_CALL_ARITHMETIC.is_target(double));
+    assert!(!STATIC_CALL_ARITHMETIC.is_target(add_one));
+
+    // Updating to the current target does nothing
+    unsafe { STATIC_CALL_ARITHMETIC.try_update(double) }.unwrap();
+    assert_eq!(call_arithmetic(3), 6);
+
+    unsafe { STATIC_CALL_ARITHMETIC.update(add_one) };
+    assert_eq!(call_arithmetic(3), 4);
+}
+
+/// Whether the first instructions of `function` call or jump to `target` directly
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+fn calls_directly(function: usize, target: usize) -> bool {
+    let code = unsafe { std::slice::from_raw_parts(function as *const u8, 64) };
+    // CALL rel32 and JMP rel32
+    (0..code.len() - 5).any(|offset| {
+        let rel = i32::from_le_bytes(code[offset + 1..offset + 5].try_into().unwrap());
+        matches!(code[offset], 0xe8 | 0xe9)
+            && (function + offset + 5).wrapping_add_signed(rel as isize) == target
+    })
+}
+
+/// Whether the first instructions of `function` call or jump to `target` directly
+#[cfg(target_arch = "aarch64")]
+fn calls_directly(function: usize, target: usize) -> bool {
+    let code = unsafe { std::slice::from_raw_parts(function as *const u32, 16) };
+    // BL imm26 and B imm26
+    code.iter().enumerate().any(|(index, &instruction)| {
+        let rel = ((instruction << 6) as i32 >> 4) as isize;
+        matches!(instruction & 0xfc00_0000, 0x9400_0000 | 0x1400_0000)
+            && (function + index * 4).wrapping_add_signed(rel) == target
+    })
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64"))]
+#[test]
+#[cfg_attr(
+    debug_assertions,
+    ignore = "the trampoline is only called directly with optimizations"
+)]
+fn test_static_call_direct() {
+    let function = call_arithmetic as fn(u32) -> u32 as usize;
+    let trampoline = static_call!(STATIC_CALL_ARITHMETIC) as usize;
+    assert!(calls_directly(function, trampoline));
+}
+
+#[test]
+fn test_static_call_signatures() {
+    assert_eq!(static_call!(STATIC_CALL_CONCAT)("a", "b", '-'), "a-b");
+    unsafe { STATIC_CALL_CONCAT.update(concat_reversed) };
+    assert_eq!(static_call!(STATIC_CALL_CONCAT)("a", "b", '-'), "b-a");
+
+    static_call!(STATIC_CALL_COUNT)();
+    unsafe { STATIC_CALL_COUNT.update(skip) };
+    static_call!(STATIC_CALL_COUNT)();
+    unsafe { STATIC_CALL_COUNT.update(count) };
+    static_call!(STATIC_CALL_COUNT)();
+    assert_eq!(COUNTER.load(std::sync::atomic::Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_static_call_closure() {
+    unsafe { STATIC_CALL_CLOSURE.update(|input| input + 10) };
+    assert_eq!(static_call!(STATIC_CALL_CLOSURE)(3), 13);
+}
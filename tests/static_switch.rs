@@ -0,0 +1,84 @@
+//! Tests for static switches.
+
+use static_keys::{StaticSwitch, define_static_switch, new_static_switch, static_switch};
+
+define_static_switch!(STATIC_SWITCH_MODE, 3);
+define_static_switch!(STATIC_SWITCH_LEVEL, 4, 2);
+static STATIC_SWITCH_SHARED: StaticSwitch<2> = new_static_switch();
+define_static_switch!(STATIC_SWITCH_RANGE, 2);
+
+#[inline(never)]
+fn mode(input: u32) -> u32 {
+    static_switch!(STATIC_SWITCH_MODE, {
+        0 => input + 1,
+        1 => input * 2,
+        2 => input * 10,
+    })
+}
+
+#[inline(never)]
+fn level() -> &'static str {
+    static_switch!(STATIC_SWITCH_LEVEL, {
+        0 => "scalar",
+        1 => "sse",
+        2 => "avx2",
+        3 => "avx512",
+    })
+}
+
+#[inline(never)]
+fn shared_first() -> bool {
+    static_switch!(STATIC_SWITCH_SHARED, { 0 => false, 1 => true })
+}
+
+#[inline(never)]
+fn shared_second() -> u8 {
+    static_switch!(STATIC_SWITCH_SHARED, { 0 => 10, 1 => 20 })
+}
+
+#[test]
+fn test_static_switch() {
+    static_keys::global_init();
+    assert_eq!(STATIC_SWITCH_MODE.value(), 0);
+    assert_eq!(mode(3), 4);
+
+    unsafe { STATIC_SWITCH_MODE.set(2) };
+    assert_eq!(STATIC_SWITCH_MODE.value(), 2);
+    assert_eq!(mode(3), 30);
+
+    unsafe { STATIC_SWITCH_MODE.set(1) };
+    assert_eq!(mode(3), 6);
+
+    // Setting to the current value does nothing
+    unsafe { STATIC_SWITCH_MODE.try_set(1) }.unwrap();
+    assert_eq!(mode(3), 6);
+
+    unsafe { STATIC_SWITCH_MODE.set(0) };
+    assert_eq!(mode(3), 4);
+}
+
+#[test]
+fn test_static_switch_initial_value() {
+    assert_eq!(STATIC_SWITCH_LEVEL.initial_value(), 2);
+    assert_eq!(STATIC_SWITCH_LEVEL.arm_count(), 4);
+    assert_eq!(level(), "avx2");
+    unsafe { STATIC_SWITCH_LEVEL.set(3) };
+    assert_eq!(level(), "avx512");
+    unsafe { STATIC_SWITCH_LEVEL.set(0) };
+    assert_eq!(level(), "scalar");
+}
+
+#[test]
+fn test_static_switch_multiple_sites() {
+    assert!(!shared_first());
+    assert_eq!(shared_second(), 10);
+    unsafe { STATIC_SWITCH_SHARED.set(1) };
+    assert!(shared_first());
+    assert_eq!(shared_second(), 20);
+}
+
+#[test]
+#[should_panic]
+fn test_static_switch_out_of_range() {
+    unsafe { STATIC_SWITCH_RANGE.set(2) };
+}
@@ -0,0 +1,112 @@
+//! Tests for toggling static keys while all other threads are parked.
+
+#![cfg(target_os = "linux")]
+
+use std::sync::{
+    Arc, Barrier, Mutex,
+    atomic::{AtomicBool, Ordering},
+    mpsc,
+};
+
+use static_keys::{define_static_key_true, static_branch_likely, static_branch_unlikely};
+
+define_static_key_true!(STOP_THE_WORLD_STATIC_KEY);
+
+const WORKER_COUNT: usize = 8;
+const TOGGLE_COUNT: usize = 200;
+
+/// Tests in this file must not stop the world at the same time
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[inline(never)]
+fn stw_likely() -> usize {
+    if static_branch_likely!(STOP_THE_WORLD_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[inline(never)]
+fn stw_unlikely() -> usize {
+    if static_branch_unlikely!(STOP_THE_WORLD_STATIC_KEY) {
+        1
+    } else {
+        2
+    }
+}
+
+#[test]
+fn test_stop_the_world_toggle() {
+    let _serial = SERIAL.lock().unwrap();
+    static_keys::global_init();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let barrier = Arc::new(Barrier::new(WORKER_COUNT + 1));
+    let workers = (0..WORKER_COUNT)
+        .map(|_| {
+            let stop = stop.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                while !stop.load(Ordering::Relaxed) {
+                    assert!(matches!(stw_likely(), 1 | 2));
+                    assert!(matches!(stw_unlikely(), 1 | 2));
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    barrier.wait();
+    for _ in 0..TOGGLE_COUNT {
+        STOP_THE_WORLD_STATIC_KEY.disable_stop_the_world().unwrap();
+        assert_eq!(stw_likely(), 2);
+        assert_eq!(stw_unlikely(), 2);
+        STOP_THE_WORLD_STATIC_KEY.enable_stop_the_world().unwrap();
+        assert_eq!(stw_likely(), 1);
+        assert_eq!(stw_unlikely(), 1);
+    }
+    stop.store(true, Ordering::Relaxed);
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+#[test]
+fn test_stop_the_world_unacknowledged() {
+    let _serial = SERIAL.lock().unwrap();
+    static_keys::global_init();
+
+    let (tid_sender, tid_receiver) = mpsc::channel();
+    let (exit_sender, exit_receiver) = mpsc::channel::<()>();
+    let blocker = std::thread::spawn(move || {
+        unsafe {
+            let mut set = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, libc::SIGRTMIN() + 8);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        }
+        tid_sender.send(unsafe { libc::gettid() }).unwrap();
+        exit_receiver.recv().unwrap();
+    });
+    let blocker_tid = tid_receiver.recv().unwrap();
+
+    let static_keys::StaticKeyError::StopTheWorld(error) = STOP_THE_WORLD_STATIC_KEY
+        .disable_stop_the_world()
+        .unwrap_err()
+    else {
+        panic!("Unexpected error");
+    };
+    assert!(matches!(
+        error,
+        static_keys::StopTheWorldError::Unacknowledged { count: 1, .. }
+    ));
+    assert_eq!(error.unacknowledged_count(), 1);
+    assert_eq!(error.unacknowledged_threads(), &[blocker_tid]);
+    // Nothing is modified
+    assert!(STOP_THE_WORLD_STATIC_KEY.is_enabled());
+    assert_eq!(stw_likely(), 1);
+
+    exit_sender.send(()).unwrap();
+    blocker.join().unwrap();
+}
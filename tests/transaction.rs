@@ -0,0 +1,83 @@
+//! Tests for transactional updates of multiple static keys.
+
+use static_keys::{
+    define_static_key_false, define_static_key_true, static_branch_likely, static_branch_unlikely,
+};
+
+define_static_key_false!(TRANSACTION_FALSE_KEY_0);
+define_static_key_false!(TRANSACTION_FALSE_KEY_1);
+define_static_key_true!(TRANSACTION_TRUE_KEY);
+
+// These functions are adjacent, so their sites are likely to share the same page.
+#[inline(never)]
+fn false_key_0_unlikely() -> bool {
+    static_branch_unlikely!(TRANSACTION_FALSE_KEY_0)
+}
+
+#[inline(never)]
+fn false_key_0_likely() -> bool {
+    static_branch_likely!(TRANSACTION_FALSE_KEY_0)
+}
+
+#[inline(never)]
+fn false_key_1_unlikely() -> bool {
+    static_branch_unlikely!(TRANSACTION_FALSE_KEY_1)
+}
+
+#[inline(never)]
+fn true_key_unlikely() -> bool {
+    static_branch_unlikely!(TRANSACTION_TRUE_KEY)
+}
+
+#[inline(never)]
+fn true_key_likely() -> bool {
+    static_branch_likely!(TRANSACTION_TRUE_KEY)
+}
+
+/// Check both the status and branches of all keys
+fn assert_branches(false_key_0: bool, false_key_1: bool, true_key: bool) {
+    assert_eq!(TRANSACTION_FALSE_KEY_0.is_enabled(), false_key_0);
+    assert_eq!(false_key_0_unlikely(), false_key_0);
+    assert_eq!(false_key_0_likely(), false_key_0);
+    assert_eq!(TRANSACTION_FALSE_KEY_1.is_enabled(), false_key_1);
+    assert_eq!(false_key_1_unlikely(), false_key_1);
+    assert_eq!(TRANSACTION_TRUE_KEY.is_enabled(), true_key);
+    assert_eq!(true_key_unlikely(), true_key);
+    assert_eq!(true_key_likely(), true_key);
+}
+
+#[test]
+fn test_transaction() {
+    static_keys::global_init();
+    assert_branches(false, false, true);
+
+    let transaction = static_keys::batch();
+    assert!(transaction.is_empty());
+    unsafe { transaction.commit() }.unwrap();
+    assert_branches(false, false, true);
+
+    let mut transaction = static_keys::batch();
+    transaction
+        .enable(&TRANSACTION_FALSE_KEY_0)
+        .enable(&TRANSACTION_FALSE_KEY_1)
+        .disable(&TRANSACTION_TRUE_KEY);
+    assert_eq!(transaction.len(), 3);
+    unsafe { transaction.commit() }.unwrap();
+    assert_branches(true, true, false);
+
+    // Later changes override earlier ones, and unchanged keys are skipped
+    let mut transaction = static_keys::batch();
+    transaction
+        .disable(&TRANSACTION_FALSE_KEY_0)
+        .set(&TRANSACTION_FALSE_KEY_0, true)
+        .disable(&TRANSACTION_FALSE_KEY_1)
+        .enable(&TRANSACTION_TRUE_KEY);
+    assert_eq!(transaction.len(), 3);
+    unsafe { transaction.commit() }.unwrap();
+    assert_branches(true, false, true);
+
+    let mut transaction = static_keys::batch();
+    transaction.disable(&TRANSACTION_FALSE_KEY_0);
+    unsafe { transaction.commit() }.unwrap();
+    assert_branches(false, false, true);
+}
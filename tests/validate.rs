@@ -0,0 +1,77 @@
+//! Tests for structural validation of jump entries.
+//!
+//! Invalid jump entries are injected into the `__static_keys` section manually. Never modify the
+//! static keys here.
+
+#![cfg(all(target_os = "linux", target_pointer_width = "64"))]
+
+use std::sync::atomic::AtomicUsize;
+
+use static_keys::{ValidationIssue, define_static_key_false, static_branch_unlikely};
+
+define_static_key_false!(VALID_STATIC_KEY);
+// Never used in any branch
+define_static_key_false!(UNUSED_STATIC_KEY);
+
+/// Non-executable memory pointed by invalid jump entries
+#[unsafe(no_mangle)]
+static STATIC_KEYS_TEST_DATA: [u8; 16] = [0; 16];
+
+/// Memory large enough to hold a static key at a misaligned address
+#[unsafe(no_mangle)]
+static STATIC_KEYS_TEST_KEY: [AtomicUsize; 4] = [const { AtomicUsize::new(0) }; 4];
+
+core::arch::global_asm!(
+    concat!(".pushsection ", static_keys::os_static_key_sec_name_attr!()),
+    ".balign 8",
+    // Not executable
+    ".quad STATIC_KEYS_TEST_DATA - .",
+    ".quad STATIC_KEYS_TEST_DATA - .",
+    ".quad STATIC_KEYS_TEST_KEY - .",
+    // Not executable, overlapping with the previous one, and misaligned key
+    ".quad STATIC_KEYS_TEST_DATA + 1 - .",
+    ".quad STATIC_KEYS_TEST_DATA - .",
+    ".quad STATIC_KEYS_TEST_KEY + 4 - .",
+    ".popsection",
+);
+
+#[inline(never)]
+fn valid_unlikely() -> bool {
+    static_branch_unlikely!(VALID_STATIC_KEY)
+}
+
+#[test]
+fn test_validate() {
+    static_keys::global_init();
+    assert!(!valid_unlikely());
+
+    let data_addr = STATIC_KEYS_TEST_DATA.as_ptr() as usize;
+    let key_addr = STATIC_KEYS_TEST_KEY.as_ptr() as usize;
+    let unused_key_addr = &raw const UNUSED_STATIC_KEY as usize;
+    let mut issues = Vec::new();
+    static_keys::validate_jump_entries(|issue| issues.push(issue));
+    issues.sort_by_key(|issue| format!("{issue:?}"));
+    assert_eq!(
+        issues,
+        [
+            ValidationIssue::CodeNotExecutable { site: data_addr },
+            ValidationIssue::CodeNotExecutable {
+                site: data_addr + 1
+            },
+            ValidationIssue::KeyWithoutJumpEntries {
+                key_addr: unused_key_addr
+            },
+            ValidationIssue::MisalignedKey {
+                site: data_addr + 1,
+                key_addr: key_addr + 4
+            },
+            ValidationIssue::OverlappingSites {
+                site: data_addr,
+                other_site: data_addr + 1
+            },
+        ]
+    );
+    for issue in issues {
+        assert!(issue.to_string().contains(" at 0x"));
+    }
+}
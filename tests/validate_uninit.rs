@@ -0,0 +1,67 @@
+//! Tests for structural validation of jump entries before `global_init`.
+//!
+//! An out-of-range jump entry is injected into the `__static_keys` section manually, so
+//! `global_init` fails. Never call it before validating.
+
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+use std::sync::atomic::AtomicUsize;
+
+use static_keys::{
+    StaticKeyError, ValidationIssue, define_static_key_false, static_branch_unlikely,
+};
+
+define_static_key_false!(VALIDATE_UNINIT_STATIC_KEY);
+
+#[inline(never)]
+fn validate_uninit_unlikely() -> bool {
+    static_branch_unlikely!(VALIDATE_UNINIT_STATIC_KEY)
+}
+
+/// Site of the invalid jump entry
+#[unsafe(no_mangle)]
+extern "C" fn static_keys_test_far_site() {}
+
+/// Memory large enough to hold a static key
+#[unsafe(no_mangle)]
+static STATIC_KEYS_TEST_FAR_KEY: [AtomicUsize; 4] = [const { AtomicUsize::new(0) }; 4];
+
+core::arch::global_asm!(
+    concat!(".pushsection ", static_keys::os_static_key_sec_name_attr!()),
+    ".balign 8",
+    // The JMP destination is 4GB away, which cannot be encoded in a 5-byte JMP
+    ".quad static_keys_test_far_site - .",
+    ".quad static_keys_test_far_site + 0x100000000 - .",
+    ".quad STATIC_KEYS_TEST_FAR_KEY - .",
+    ".popsection",
+);
+
+#[test]
+fn test_validate_before_global_init() {
+    let site = static_keys_test_far_site as extern "C" fn() as usize;
+    let mut issues = Vec::new();
+    static_keys::validate_jump_entries(|issue| issues.push(issue));
+    assert_eq!(
+        issues,
+        [ValidationIssue::TargetOutOfRange {
+            site,
+            target: site + 0x100000000
+        }]
+    );
+
+    // Validation does not initialize, and `global_init` rejects the jump entry
+    let err = StaticKeyError::TargetOutOfRange {
+        site,
+        target: site + 0x100000000,
+    };
+    assert_eq!(static_keys::try_global_init(), Err(err.clone()));
+    let res = std::panic::catch_unwind(static_keys::global_init);
+    assert!(res.is_err());
+
+    // Nothing is initialized, so lazy initialization fails in the same way without panicking
+    assert_eq!(unsafe { VALIDATE_UNINIT_STATIC_KEY.try_enable() }, Err(err));
+    assert!(!VALIDATE_UNINIT_STATIC_KEY.is_enabled());
+    assert!(!validate_uninit_unlikely());
+    assert_eq!(static_keys::keys().count(), 0);
+    assert_eq!(static_keys::verify_all().count(), 0);
+}
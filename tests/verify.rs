@@ -0,0 +1,113 @@
+//! Tests for verification of instructions at jump entries.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use static_keys::{
+    GenericStaticKey, StaticKeyError,
+    code_manipulate::{ArchCodeManipulator, CodeManipulateError, CodeManipulator},
+    define_static_key_false, define_static_key_true, new_static_key, static_branch_likely,
+    static_branch_unlikely,
+};
+
+/// Whether [`SkippingCodeManipulator`] pretends to write without modifying anything
+static SKIP_WRITES: AtomicBool = AtomicBool::new(false);
+
+/// Code manipulator which can skip writes, as if the instructions were tampered
+struct SkippingCodeManipulator;
+
+impl CodeManipulator for SkippingCodeManipulator {
+    unsafe fn write_code<const L: usize>(
+        addr: *mut core::ffi::c_void,
+        data: &[u8; L],
+    ) -> Result<(), CodeManipulateError> {
+        if SKIP_WRITES.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        unsafe { ArchCodeManipulator::write_code(addr, data) }
+    }
+}
+
+define_static_key_false!(NORMAL_STATIC_KEY);
+
+define_static_key_true!(TRUE_STATIC_KEY);
+
+#[used]
+static SKIPPING_STATIC_KEY: GenericStaticKey<SkippingCodeManipulator, false> = new_static_key();
+
+#[inline(never)]
+fn normal_unlikely() -> bool {
+    static_branch_unlikely!(NORMAL_STATIC_KEY)
+}
+
+#[inline(never)]
+fn skipping_unlikely() -> bool {
+    static_branch_unlikely!(SKIPPING_STATIC_KEY)
+}
+
+#[inline(never)]
+fn true_likely() -> bool {
+    static_branch_likely!(TRUE_STATIC_KEY)
+}
+
+#[inline(never)]
+fn true_unlikely() -> bool {
+    static_branch_unlikely!(TRUE_STATIC_KEY)
+}
+
+#[test]
+fn test_verify_one_site_per_branch() {
+    static_keys::global_init();
+    // Only the branch layout for the initial status is emitted, even without optimization
+    assert_eq!(TRUE_STATIC_KEY.sites().count(), 2);
+    assert!(true_likely());
+    assert!(true_unlikely());
+    // Other tests may tamper instructions of their own static keys
+    let key_addr = &raw const TRUE_STATIC_KEY as usize;
+    assert!(static_keys::verify_all().all(|mismatch| mismatch.key_addr() != key_addr));
+}
+
+#[test]
+fn test_verify() {
+    static_keys::global_init();
+    assert_eq!(static_keys::verify_all().count(), 0);
+
+    unsafe {
+        NORMAL_STATIC_KEY.enable();
+        SKIPPING_STATIC_KEY.enable();
+    }
+    assert!(normal_unlikely());
+    assert!(skipping_unlikely());
+    assert_eq!(static_keys::verify_all().count(), 0);
+
+    // The instruction is left as JMP while the static key is disabled
+    SKIP_WRITES.store(true, Ordering::Relaxed);
+    unsafe {
+        SKIPPING_STATIC_KEY.disable();
+    }
+    SKIP_WRITES.store(false, Ordering::Relaxed);
+    assert!(!SKIPPING_STATIC_KEY.is_enabled());
+    assert!(skipping_unlikely());
+    let mismatches = static_keys::verify_all().collect::<Vec<_>>();
+    assert_eq!(mismatches.len(), 1);
+    let mismatch = &mismatches[0];
+    assert_eq!(mismatch.key_addr(), &raw const SKIPPING_STATIC_KEY as usize);
+    assert!(!mismatch.is_enabled());
+    assert_ne!(mismatch.expected(), mismatch.found());
+
+    // The mismatched instruction is never overwritten
+    let err = unsafe { SKIPPING_STATIC_KEY.try_enable() }.unwrap_err();
+    assert_eq!(
+        err,
+        StaticKeyError::UnexpectedInstruction {
+            site: mismatch.site()
+        }
+    );
+    assert!(!SKIPPING_STATIC_KEY.is_enabled());
+    assert!(skipping_unlikely());
+
+    // Other static keys are not affected
+    unsafe {
+        NORMAL_STATIC_KEY.disable();
+    }
+    assert!(!normal_unlikely());
+}